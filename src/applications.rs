@@ -0,0 +1,884 @@
+use crate::desktop_entry::{split_exec, DesktopEntry, ParseError};
+use crate::uri::file_uri;
+use crate::xdg::{config_dirs, data_dirs, find_executable};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use mime::Mime;
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    time::SystemTime,
+};
+
+lazy_static! {
+    /// The installed applications, read again when one of their directories changes.
+    static ref INSTALLED: Mutex<Option<Installed>> = Mutex::new(None);
+}
+
+/// An application installed through a desktop entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Application {
+    /// The desktop file ID, like `org.gnome.eog.desktop`.
+    pub id: String,
+    pub path: PathBuf,
+    pub name: String,
+    pub icon: Option<String>,
+    pub exec: String,
+    pub working_dir: Option<PathBuf>,
+    pub terminal: bool,
+    pub mime_types: Vec<String>,
+    /// Whether the application is hidden from the lists of applications to choose from.
+    pub no_display: bool,
+}
+
+impl Application {
+    /// Reads the desktop entry at `path`, the entries that aren't launchable applications are
+    /// ignored.
+    pub fn load(id: impl Into<String>, path: impl AsRef<Path>) -> Option<Application> {
+        let entry = match DesktopEntry::open(path.as_ref()) {
+            Ok(entry) => entry,
+            Err(e) => {
+                debug!("Can't read {}: {}", path.as_ref().display(), e);
+                return None;
+            }
+        };
+        let values = entry.group("Desktop Entry")?;
+        let is_true = |key: &str| values.boolean(key).unwrap_or(false);
+        if values.string("Type").as_deref() != Some("Application") || is_true("Hidden") {
+            return None;
+        }
+        if let Some(try_exec) = values.string("TryExec") {
+            find_executable(&try_exec)?;
+        }
+        Some(Application {
+            id: id.into(),
+            path: path.as_ref().to_path_buf(),
+            name: values.locale_string("Name", None)?,
+            icon: values.locale_string("Icon", None).filter(|i| !i.is_empty()),
+            exec: values.string("Exec").filter(|e| !e.is_empty())?,
+            working_dir: values
+                .string("Path")
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from),
+            terminal: is_true("Terminal"),
+            mime_types: values.strings("MimeType").unwrap_or_default(),
+            no_display: is_true("NoDisplay"),
+        })
+    }
+
+    /// Whether the application declares that it handles `mime`.
+    pub fn supports(&self, mime: &str) -> bool {
+        self.mime_types.iter().any(|m| m.eq_ignore_ascii_case(mime))
+    }
+
+    /// Builds the command lines that open `paths`, one for each process to spawn.
+    ///
+    /// An application that only accepts one file (`%f` or `%u`) is launched once per file.
+    pub fn commands(&self, paths: &[PathBuf]) -> Result<Vec<Vec<String>>, io::Error> {
+        let args = split_exec(&self.exec).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid Exec key in {}", self.path.display()),
+            )
+        })?;
+        let single = args.iter().any(|arg| has_field_code(arg, &['f', 'u']));
+        let mut commands = Vec::new();
+        if single && paths.len() > 1 {
+            for path in paths {
+                commands.push(self.expand(&args, std::slice::from_ref(path)));
+            }
+        } else {
+            commands.push(self.expand(&args, paths));
+        }
+        if self.terminal {
+            for command in commands.iter_mut() {
+                let terminal = env::var("TERMINAL").unwrap_or_else(|_| "xterm".to_owned());
+                command.splice(0..0, vec![terminal, "-e".to_owned()]);
+            }
+        }
+        Ok(commands)
+    }
+
+    /// Spawns the application to open `paths`.
+    pub fn launch(&self, paths: &[PathBuf]) -> Result<(), io::Error> {
+        // The URIs must be absolute
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|p| p.canonicalize().unwrap_or_else(|_| p.clone()))
+            .collect();
+        for command in self.commands(&paths)? {
+            if command.is_empty() {
+                continue;
+            }
+            debug!("Launching {:?}", command);
+            let mut process = Command::new(&command[0]);
+            process
+                .args(&command[1..])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            if let Some(ref dir) = self.working_dir {
+                process.current_dir(dir);
+            }
+            let mut child = process.spawn()?;
+            // Reaps the process once it exits
+            thread::spawn(move || child.wait());
+        }
+        Ok(())
+    }
+
+    /// Replaces the field codes of `args`.
+    fn expand(&self, args: &[String], paths: &[PathBuf]) -> Vec<String> {
+        let files: Vec<String> = paths
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        let uris: Vec<String> = paths.iter().map(file_uri).collect();
+        let mut expanded = Vec::new();
+        for arg in args {
+            match &arg[..] {
+                "%F" => expanded.extend(files.iter().cloned()),
+                "%U" => expanded.extend(uris.iter().cloned()),
+                "%i" => {
+                    if let Some(ref icon) = self.icon {
+                        expanded.push("--icon".to_owned());
+                        expanded.push(icon.clone());
+                    }
+                }
+                "%f" | "%u" if paths.is_empty() => {}
+                _ => {
+                    let mut value = String::new();
+                    let mut chars = arg.chars();
+                    while let Some(c) = chars.next() {
+                        if c != '%' {
+                            value.push(c);
+                            continue;
+                        }
+                        match chars.next() {
+                            Some('f') => value.push_str(files.first().map_or("", |f| &f[..])),
+                            Some('u') => value.push_str(uris.first().map_or("", |u| &u[..])),
+                            Some('c') => value.push_str(&self.name),
+                            Some('k') => value.push_str(&self.path.to_string_lossy()),
+                            Some('%') => value.push('%'),
+                            // Deprecated or misplaced field codes are removed
+                            _ => {}
+                        }
+                    }
+                    expanded.push(value);
+                }
+            }
+        }
+        expanded
+    }
+}
+
+/// Opens `path` with the default application for `mime`.
+pub fn open(path: impl AsRef<Path>, mime: &Mime) -> Result<(), io::Error> {
+    match default_for(mime) {
+        Some(app) => app.launch(&[path.as_ref().to_path_buf()]),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No application can open {} files", mime.essence_str()),
+        )),
+    }
+}
+
+/// Resolves the default application of `mime` following the `mimeapps.list` chain, falling back
+/// to the added associations and then to any installed application declaring the type.
+pub fn default_for(mime: &Mime) -> Option<Application> {
+    let mime = mime.essence_str();
+    let associations = Associations::read(mime);
+    for id in associations
+        .defaults
+        .iter()
+        .chain(associations.added.iter())
+    {
+        if let Some(app) = find(id) {
+            return Some(app);
+        }
+    }
+    installed()
+        .iter()
+        .find(|app| !associations.removed.contains(&app.id) && app.supports(mime))
+        .cloned()
+}
+
+/// Every application that can open `mime` or one of the types it's a subclass of, the default one
+/// first and the most specific ones before the others.
+pub fn for_mime(mime: &Mime) -> Vec<Application> {
+    let installed = installed();
+    let mut found: Vec<Application> = Vec::new();
+    let add = |app: Application, found: &mut Vec<Application>| {
+        if !found.iter().any(|f| f.id == app.id) {
+            found.push(app);
+        }
+    };
+    if let Some(app) = default_for(mime) {
+        add(app, &mut found);
+    }
+    for mime in mime_hierarchy(mime.essence_str()) {
+        let associations = Associations::read(&mime);
+        for id in associations
+            .defaults
+            .iter()
+            .chain(associations.added.iter())
+        {
+            if let Some(app) = find(id) {
+                add(app, &mut found);
+            }
+        }
+        // The hidden applications are only listed when they're explicitly associated
+        for app in installed.iter().filter(|app| !app.no_display) {
+            if !associations.removed.contains(&app.id) && app.supports(&mime) {
+                add(app.clone(), &mut found);
+            }
+        }
+    }
+    found
+}
+
+/// Makes `app` the default application of `mime` for the user.
+pub fn set_default(mime: &Mime, app: &Application) -> Result<(), io::Error> {
+    let config_dir = dirs::config_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No configuration directory"))?;
+    fs::create_dir_all(&config_dir)?;
+    let path = config_dir.join("mimeapps.list");
+    let mut content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e);
+        }
+    };
+    let mime = mime.essence_str();
+    // The default also goes first in the added associations, like other implementations do
+    let mut added = vec![app.id.clone()];
+    let entry = DesktopEntry::parse(content.as_bytes()).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}", path.display(), e),
+        )
+    })?;
+    if let Some(ids) = entry
+        .group("Added Associations")
+        .and_then(|values| values.strings(mime))
+    {
+        added.extend(ids.into_iter().filter(|id| *id != app.id));
+    }
+    content = set_entry(
+        &content,
+        "Default Applications",
+        mime,
+        &format!("{};", app.id),
+    );
+    content = set_entry(
+        &content,
+        "Added Associations",
+        mime,
+        &format!("{};", added.join(";")),
+    );
+    let temp_path = config_dir.join(".mimeapps.list.reactor");
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, &path).map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        e
+    })
+}
+
+/// The associations of a MIME type gathered from every `mimeapps.list`.
+#[derive(Debug, Default)]
+struct Associations {
+    /// The default applications in order of preference.
+    defaults: Vec<String>,
+    added: Vec<String>,
+    removed: HashSet<String>,
+}
+
+impl Associations {
+    fn read(mime: &str) -> Associations {
+        let mut associations = Associations::default();
+        for list in mimeapps_lists() {
+            let entry = match DesktopEntry::open(&list) {
+                Ok(entry) => entry,
+                Err(ParseError::Io(_)) => {
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Ignoring {}: {}", list.display(), e);
+                    continue;
+                }
+            };
+            let group = |name: &str| {
+                entry
+                    .group(name)
+                    .and_then(|values| values.strings(mime))
+                    .unwrap_or_default()
+            };
+            // The removed associations of a file apply to it and the less important ones
+            associations.removed.extend(group("Removed Associations"));
+            for id in group("Default Applications") {
+                if !associations.removed.contains(&id) {
+                    associations.defaults.push(id);
+                }
+            }
+            for id in group("Added Associations") {
+                if !associations.removed.contains(&id) {
+                    associations.added.push(id);
+                }
+            }
+        }
+        associations
+    }
+}
+
+/// `mime` followed by the types it is a subclass of, nearest first, following the
+/// shared-mime-info database.
+fn mime_hierarchy(mime: &str) -> Vec<String> {
+    let mut aliases = HashMap::new();
+    let mut parents: HashMap<String, Vec<String>> = HashMap::new();
+    for dir in data_dirs() {
+        let mime_dir = dir.join("mime");
+        for (file, is_alias) in &[("aliases", true), ("subclasses", false)] {
+            let content = match fs::read_to_string(mime_dir.join(file)) {
+                Ok(content) => content,
+                Err(_) => {
+                    continue;
+                }
+            };
+            for line in content.lines() {
+                let mut fields = line.split_whitespace();
+                let (child, parent) = match (fields.next(), fields.next()) {
+                    (Some(child), Some(parent)) => (child.to_lowercase(), parent.to_lowercase()),
+                    _ => {
+                        continue;
+                    }
+                };
+                if *is_alias {
+                    aliases.entry(child).or_insert(parent);
+                } else {
+                    let entry = parents.entry(child).or_default();
+                    if !entry.contains(&parent) {
+                        entry.push(parent);
+                    }
+                }
+            }
+        }
+    }
+    let mime = mime.to_lowercase();
+    let mut hierarchy = vec![aliases.get(&mime).cloned().unwrap_or(mime)];
+    let mut i = 0;
+    while i < hierarchy.len() {
+        let mut next = parents.get(&hierarchy[i]).cloned().unwrap_or_default();
+        // Implicit subclasses of the specification
+        if hierarchy[i].starts_with("text/") && hierarchy[i] != "text/plain" {
+            next.push("text/plain".to_owned());
+        }
+        if !hierarchy[i].starts_with("inode/") && hierarchy[i] != "application/octet-stream" {
+            next.push("application/octet-stream".to_owned());
+        }
+        for parent in next {
+            let parent = aliases.get(&parent).cloned().unwrap_or(parent);
+            if !hierarchy.contains(&parent) {
+                hierarchy.push(parent);
+            }
+        }
+        i += 1;
+    }
+    // The generic types are the last resort
+    if let Some(index) = hierarchy
+        .iter()
+        .position(|m| m == "application/octet-stream")
+    {
+        let octet_stream = hierarchy.remove(index);
+        hierarchy.push(octet_stream);
+    }
+    hierarchy
+}
+
+/// Sets `key` to `value` in `group` of the key file `content`, keeping everything else as it was.
+fn set_entry(content: &str, group: &str, key: &str, value: &str) -> String {
+    let mut lines: Vec<String> = content.lines().map(|l| l.to_owned()).collect();
+    let header = format!("[{}]", group);
+    let entry = format!("{}={}", key, value);
+    match lines.iter().position(|l| l.trim() == header) {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|l| l.trim_start().starts_with('['))
+                .map_or(lines.len(), |i| start + 1 + i);
+            let existing = lines[start + 1..end]
+                .iter()
+                .position(|l| l.splitn(2, '=').next().map(|k| k.trim()) == Some(key));
+            match existing {
+                Some(i) => lines[start + 1 + i] = entry,
+                None => lines.insert(start + 1, entry),
+            }
+        }
+        None => {
+            if lines.last().map_or(false, |l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(header);
+            lines.push(entry);
+        }
+    }
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+/// Finds the application with the desktop file ID `id`.
+pub fn find(id: &str) -> Option<Application> {
+    applications_dirs()
+        .into_iter()
+        .find_map(|dir| lookup(&dir, id))
+        .and_then(|path| Application::load(id, path))
+}
+
+/// The installed applications with the directories they were read from.
+struct Installed {
+    /// Every directory read, with its modification time then.
+    dirs: Vec<(PathBuf, Option<SystemTime>)>,
+    applications: Arc<Vec<Application>>,
+}
+
+/// Every installed application, when the same ID is found twice the most important one wins.
+///
+/// The applications are read once and read again only when their directories change.
+pub fn installed() -> Arc<Vec<Application>> {
+    let mut cache = INSTALLED.lock();
+    if let Some(ref installed) = *cache {
+        if installed
+            .dirs
+            .iter()
+            .all(|(dir, modified)| modification_time(dir) == *modified)
+        {
+            return installed.applications.clone();
+        }
+    }
+    let mut found = HashMap::new();
+    let mut ids = Vec::new();
+    let mut dirs = Vec::new();
+    for dir in applications_dirs() {
+        collect_entries(&dir, "", &mut found, &mut ids, &mut dirs);
+    }
+    let applications: Arc<Vec<Application>> = Arc::new(
+        ids.into_iter()
+            .filter_map(|id| {
+                let path = found.remove(&id)?;
+                Application::load(id, path)
+            })
+            .collect(),
+    );
+    *cache = Some(Installed {
+        dirs,
+        applications: applications.clone(),
+    });
+    applications
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|meta| meta.modified()).ok()
+}
+
+fn collect_entries(
+    dir: &Path,
+    prefix: &str,
+    found: &mut HashMap<String, PathBuf>,
+    ids: &mut Vec<String>,
+    dirs: &mut Vec<(PathBuf, Option<SystemTime>)>,
+) {
+    // Missing directories are recorded too, creating them changes the applications
+    dirs.push((dir.to_path_buf(), modification_time(dir)));
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => {
+            return;
+        }
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => {
+                continue;
+            }
+        };
+        let path = entry.path();
+        if path.is_dir() {
+            collect_entries(&path, &format!("{}{}-", prefix, name), found, ids, dirs);
+        } else if name.ends_with(".desktop") {
+            let id = format!("{}{}", prefix, name);
+            if !found.contains_key(&id) {
+                found.insert(id.clone(), path);
+                ids.push(id);
+            }
+        }
+    }
+}
+
+/// Finds the file of the desktop file ID `id` inside `dir`, the dashes of an ID can stand for
+/// subdirectories.
+fn lookup(dir: &Path, id: &str) -> Option<PathBuf> {
+    let path = dir.join(id);
+    if path.is_file() {
+        return Some(path);
+    }
+    for (i, _) in id.match_indices('-') {
+        let subdir = dir.join(&id[..i]);
+        if subdir.is_dir() {
+            if let Some(path) = lookup(&subdir, &id[i + 1..]) {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// The `mimeapps.list` files in decreasing order of importance.
+fn mimeapps_lists() -> Vec<PathBuf> {
+    let desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(|d| d.to_lowercase())
+        .collect();
+    let mut dirs = config_dirs();
+    dirs.extend(applications_dirs());
+    let mut lists = Vec::new();
+    for dir in dirs {
+        for desktop in desktops.iter() {
+            lists.push(dir.join(format!("{}-mimeapps.list", desktop)));
+        }
+        lists.push(dir.join("mimeapps.list"));
+    }
+    lists
+}
+
+/// The `applications` directories of `$XDG_DATA_HOME` and `$XDG_DATA_DIRS`.
+fn applications_dirs() -> Vec<PathBuf> {
+    data_dirs()
+        .into_iter()
+        .map(|d| d.join("applications"))
+        .collect()
+}
+
+/// Whether `arg` contains one of the field codes `codes`, `%%` being a literal percent.
+fn has_field_code(arg: &str, codes: &[char]) -> bool {
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some(code) if codes.contains(&code) => {
+                    return true;
+                }
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::MutexGuard;
+    use tempfile::TempDir;
+
+    lazy_static! {
+        /// The environment is shared by the tests running at the same time.
+        static ref ENVIRONMENT: Mutex<()> = Mutex::new(());
+    }
+
+    /// The XDG directories pointed at a temporary directory while it exists.
+    struct Xdg {
+        dir: TempDir,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl Xdg {
+        fn new() -> Xdg {
+            let lock = ENVIRONMENT.lock();
+            let dir = tempfile::tempdir().unwrap();
+            for (var, name) in &[
+                ("XDG_CONFIG_HOME", "config"),
+                ("XDG_CONFIG_DIRS", "etc/xdg"),
+                ("XDG_DATA_HOME", "data"),
+                ("XDG_DATA_DIRS", "share"),
+            ] {
+                fs::create_dir_all(dir.path().join(name)).unwrap();
+                env::set_var(var, dir.path().join(name));
+            }
+            env::set_var("XDG_CURRENT_DESKTOP", "Test");
+            *INSTALLED.lock() = None;
+            Xdg { dir, _lock: lock }
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            let path = self.dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        /// Installs the application `id` for `mime_types` in the system data directory.
+        fn install(&self, id: &str, mime_types: &str) {
+            self.write(
+                &format!("share/applications/{}", id),
+                &format!(
+                    "[Desktop Entry]\nType=Application\nName={}\nExec={} %f\nMimeType={}\n",
+                    id, id, mime_types
+                ),
+            );
+        }
+    }
+
+    fn application(exec: &str) -> Application {
+        Application {
+            id: "viewer.desktop".to_owned(),
+            path: PathBuf::from("/usr/share/applications/viewer.desktop"),
+            name: "Image Viewer".to_owned(),
+            icon: Some("viewer".to_owned()),
+            exec: exec.to_owned(),
+            working_dir: None,
+            terminal: false,
+            mime_types: vec!["image/png".to_owned()],
+            no_display: false,
+        }
+    }
+
+    fn commands(exec: &str, paths: &[&str]) -> Vec<Vec<String>> {
+        let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        application(exec).commands(&paths).unwrap()
+    }
+
+    fn default_id(mime: &str) -> Option<String> {
+        default_for(&mime.parse().unwrap()).map(|app| app.id)
+    }
+
+    #[test]
+    fn field_codes() {
+        let paths = ["/tmp/a b.png", "/tmp/c.png"];
+        // A single file per process
+        assert_eq!(
+            commands("viewer %f", &paths),
+            vec![vec!["viewer", "/tmp/a b.png"], vec!["viewer", "/tmp/c.png"]]
+        );
+        assert_eq!(
+            commands("viewer --uri=%u", &paths),
+            vec![
+                vec!["viewer", "--uri=file:///tmp/a%20b.png"],
+                vec!["viewer", "--uri=file:///tmp/c.png"]
+            ]
+        );
+        assert_eq!(
+            commands("viewer %F", &paths),
+            vec![vec!["viewer", "/tmp/a b.png", "/tmp/c.png"]]
+        );
+        assert_eq!(
+            commands("viewer %U", &paths),
+            vec![vec!["viewer", "file:///tmp/a%20b.png", "file:///tmp/c.png"]]
+        );
+        assert_eq!(
+            commands("viewer %i --title=%c %k 100%% %d%D%n%N%v%m", &paths[..1]),
+            vec![vec![
+                "viewer",
+                "--icon",
+                "viewer",
+                "--title=Image Viewer",
+                "/usr/share/applications/viewer.desktop",
+                "100%",
+                ""
+            ]]
+        );
+        // Without files the arguments that stand for them are removed
+        assert_eq!(commands("viewer %f %U", &[]), vec![vec!["viewer"]]);
+        let mut app = application("viewer %i %F");
+        app.icon = None;
+        assert_eq!(
+            app.commands(&[PathBuf::from("/tmp/c.png")]).unwrap(),
+            vec![vec!["viewer", "/tmp/c.png"]]
+        );
+        assert!(application("viewer \"%F").commands(&[]).is_err());
+    }
+
+    #[test]
+    fn default_precedence() {
+        let xdg = Xdg::new();
+        for id in &[
+            "user.desktop",
+            "desktop.desktop",
+            "system.desktop",
+            "any.desktop",
+        ] {
+            xdg.install(id, "text/x-test;");
+        }
+        xdg.write(
+            "etc/xdg/mimeapps.list",
+            "[Default Applications]\ntext/x-test=missing.desktop;system.desktop;\n",
+        );
+        assert_eq!(default_id("text/x-test").unwrap(), "system.desktop");
+        xdg.write(
+            "config/mimeapps.list",
+            "[Default Applications]\ntext/x-test=user.desktop;\n",
+        );
+        assert_eq!(default_id("text/x-test").unwrap(), "user.desktop");
+        // The list of the current desktop goes before the generic one of the same directory
+        xdg.write(
+            "config/test-mimeapps.list",
+            "[Default Applications]\ntext/x-test=desktop.desktop;\n",
+        );
+        assert_eq!(default_id("text/x-test").unwrap(), "desktop.desktop");
+
+        // A removed association hides the application from the less important lists
+        xdg.write(
+            "config/test-mimeapps.list",
+            "[Removed Associations]\ntext/x-test=user.desktop;\n",
+        );
+        assert_eq!(default_id("text/x-test").unwrap(), "system.desktop");
+        xdg.write(
+            "config/mimeapps.list",
+            "[Added Associations]\ntext/x-test=desktop.desktop;\n\
+             [Removed Associations]\ntext/x-test=system.desktop;\n",
+        );
+        assert_eq!(default_id("text/x-test").unwrap(), "desktop.desktop");
+        xdg.write(
+            "config/mimeapps.list",
+            "[Removed Associations]\ntext/x-test=system.desktop;desktop.desktop;\n",
+        );
+        assert_eq!(default_id("text/x-test").unwrap(), "any.desktop");
+        assert!(default_id("text/x-other").is_none());
+    }
+
+    #[test]
+    fn set_defaults() {
+        let xdg = Xdg::new();
+        xdg.install("user.desktop", "text/x-test;");
+        xdg.install("other.desktop", "text/x-test;");
+        let user = find("user.desktop").unwrap();
+        let other = find("other.desktop").unwrap();
+        let mime: Mime = "text/x-test".parse().unwrap();
+        set_default(&mime, &user).unwrap();
+        let read = || fs::read_to_string(xdg.dir.path().join("config/mimeapps.list")).unwrap();
+        assert_eq!(
+            read(),
+            "[Default Applications]\ntext/x-test=user.desktop;\n\n\
+             [Added Associations]\ntext/x-test=user.desktop;\n"
+        );
+
+        // Everything else is kept as it was
+        xdg.write(
+            "config/mimeapps.list",
+            "# Chosen by the user\n\
+             [Default Applications]\n\
+             image/png=viewer.desktop;\n\
+             \n\
+             [Added Associations]\n\
+             text/x-test=other.desktop;user.desktop;\n\
+             image/png=viewer.desktop;\n\
+             \n\
+             [Removed Associations]\n\
+             text/x-test=broken.desktop;\n\
+             \n\
+             [Custom Group]\n\
+             Key[es]=Valor\n",
+        );
+        set_default(&mime, &user).unwrap();
+        assert_eq!(
+            read(),
+            "# Chosen by the user\n\
+             [Default Applications]\n\
+             text/x-test=user.desktop;\n\
+             image/png=viewer.desktop;\n\
+             \n\
+             [Added Associations]\n\
+             text/x-test=user.desktop;other.desktop;\n\
+             image/png=viewer.desktop;\n\
+             \n\
+             [Removed Associations]\n\
+             text/x-test=broken.desktop;\n\
+             \n\
+             [Custom Group]\n\
+             Key[es]=Valor\n"
+        );
+        assert_eq!(default_id("text/x-test").unwrap(), "user.desktop");
+        set_default(&mime, &other).unwrap();
+        assert!(read().contains(
+            "[Default Applications]\ntext/x-test=other.desktop;\nimage/png=viewer.desktop;\n"
+        ));
+        assert!(read().contains("[Added Associations]\ntext/x-test=other.desktop;user.desktop;\n"));
+        assert_eq!(default_id("text/x-test").unwrap(), "other.desktop");
+
+        // A list that can't be parsed isn't overwritten
+        xdg.write("config/mimeapps.list", "[Default Applications\n");
+        assert!(set_default(&mime, &user).is_err());
+        assert_eq!(read(), "[Default Applications\n");
+    }
+
+    #[test]
+    fn parent_types() {
+        let xdg = Xdg::new();
+        xdg.write("share/mime/subclasses", "text/x-child text/x-test\n");
+        xdg.write("share/mime/aliases", "text/x-alias text/x-child\n");
+        xdg.install("child.desktop", "text/x-child;");
+        xdg.install("parent.desktop", "text/x-test;");
+        xdg.install("plain.desktop", "text/plain;");
+        xdg.install("binary.desktop", "application/octet-stream;");
+        xdg.install("image.desktop", "image/png;");
+        xdg.install("removed.desktop", "text/x-test;");
+        xdg.write(
+            "share/applications/hidden.desktop",
+            "[Desktop Entry]\nType=Application\nName=Hidden\nExec=hidden %f\n\
+             MimeType=text/x-test;\nNoDisplay=true\n",
+        );
+        xdg.write(
+            "share/applications/unlisted.desktop",
+            "[Desktop Entry]\nType=Application\nName=Unlisted\nExec=unlisted %f\n\
+             MimeType=text/x-test;\nNoDisplay=true\n",
+        );
+        xdg.write(
+            "config/mimeapps.list",
+            "[Added Associations]\ntext/x-test=hidden.desktop;\n\
+             [Removed Associations]\ntext/x-test=removed.desktop;\n",
+        );
+        assert_eq!(
+            mime_hierarchy("TEXT/X-ALIAS"),
+            vec![
+                "text/x-child",
+                "text/x-test",
+                "text/plain",
+                "application/octet-stream"
+            ]
+        );
+        let ids = |mime: &str| -> Vec<String> {
+            for_mime(&mime.parse().unwrap())
+                .into_iter()
+                .map(|app| app.id)
+                .collect()
+        };
+        // The most specific types first, the hidden applications only when associated
+        assert_eq!(
+            ids("text/x-alias"),
+            vec![
+                "child.desktop",
+                "hidden.desktop",
+                "parent.desktop",
+                "plain.desktop",
+                "binary.desktop"
+            ]
+        );
+        assert_eq!(
+            ids("text/x-test"),
+            vec![
+                "hidden.desktop",
+                "parent.desktop",
+                "plain.desktop",
+                "binary.desktop"
+            ]
+        );
+        assert_eq!(ids("image/png"), vec!["image.desktop", "binary.desktop"]);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::file_operations::{self, Progress, TransferMode};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+lazy_static! {
+    /// The clipboard shared by every view of the application.
+    pub static ref CLIPBOARD: Mutex<Clipboard> = Mutex::new(Clipboard::default());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardMode {
+    Copy,
+    Cut,
+}
+
+/// Holds the paths that were copied or cut, waiting to be pasted somewhere.
+#[derive(Debug, Default)]
+pub struct Clipboard {
+    content: Option<(ClipboardMode, Vec<PathBuf>)>,
+}
+
+impl Clipboard {
+    pub fn copy(&mut self, paths: Vec<PathBuf>) {
+        self.set(ClipboardMode::Copy, paths);
+    }
+
+    pub fn cut(&mut self, paths: Vec<PathBuf>) {
+        self.set(ClipboardMode::Cut, paths);
+    }
+
+    fn set(&mut self, mode: ClipboardMode, paths: Vec<PathBuf>) {
+        self.content = if paths.is_empty() {
+            None
+        } else {
+            Some((mode, paths))
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_none()
+    }
+
+    /// Takes what has to be pasted, a cut content can only be pasted once so it's removed from
+    /// the clipboard while a copied one stays.
+    pub fn take_paste(&mut self) -> Option<Paste> {
+        let (mode, paths) = match self.content.take()? {
+            (ClipboardMode::Copy, paths) => {
+                self.content = Some((ClipboardMode::Copy, paths.clone()));
+                (TransferMode::Copy, paths)
+            }
+            (ClipboardMode::Cut, paths) => (TransferMode::Move, paths),
+        };
+        Some(Paste { mode, paths })
+    }
+}
+
+/// A pending paste, it can be run in any thread.
+#[derive(Debug, Clone)]
+pub struct Paste {
+    pub mode: TransferMode,
+    pub paths: Vec<PathBuf>,
+}
+
+impl Paste {
+    pub fn run(
+        &self,
+        destination: impl AsRef<Path>,
+        report: impl FnMut(&Progress),
+    ) -> Result<(), io::Error> {
+        file_operations::transfer(self.mode, &self.paths, destination.as_ref(), report)
+    }
+}
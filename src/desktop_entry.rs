@@ -0,0 +1,432 @@
+use log::warn;
+use std::{collections::HashMap, env, error::Error, fmt, fs, io, path::Path};
+
+/// A file in the key-file format of the Desktop Entry specification, the one of `.desktop`,
+/// `.thumbnailer` and `mimeapps.list` files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DesktopEntry {
+    groups: Vec<Group>,
+}
+
+/// A `[Group]` of a key file with its entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Group {
+    name: String,
+    /// The raw values by key and locale, the unlocalized value has no locale.
+    entries: HashMap<(String, Option<String>), String>,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    /// A malformed line, `line` and `column` start at 1.
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "{}", e),
+            ParseError::Syntax {
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}: {}", line, column, message),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+impl DesktopEntry {
+    pub fn open(path: impl AsRef<Path>) -> Result<DesktopEntry, ParseError> {
+        DesktopEntry::parse(&fs::read(path)?)
+    }
+
+    /// Parses a key file, the content should be UTF-8 but files in legacy encodings are read as
+    /// Latin-1 instead of being rejected. Duplicated groups are merged and the first value of a
+    /// duplicated key is kept, like GLib does.
+    pub fn parse(content: &[u8]) -> Result<DesktopEntry, ParseError> {
+        let content = match std::str::from_utf8(content) {
+            Ok(content) => content.to_owned(),
+            Err(_) => content.iter().map(|b| *b as char).collect(),
+        };
+        let mut entry = DesktopEntry::default();
+        // The group the entries are added to
+        let mut current = None;
+        for (index, line) in content.lines().enumerate() {
+            let error = |column: usize, message: &str| ParseError::Syntax {
+                line: index + 1,
+                column: column + 1,
+                message: message.to_owned(),
+            };
+            let indent = line.len() - line.trim_start().len();
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                if !line.ends_with(']') || line.len() < 3 {
+                    return Err(error(indent, "Malformed group header"));
+                }
+                let name = &line[1..line.len() - 1];
+                if name.contains(|c: char| c == '[' || c == ']' || c.is_control()) {
+                    return Err(error(indent + 1, "Invalid character in group name"));
+                }
+                match entry.groups.iter().position(|group| group.name == name) {
+                    Some(position) => {
+                        warn!("Duplicated group {} on line {}", name, index + 1);
+                        current = Some(position);
+                    }
+                    None => {
+                        current = Some(entry.groups.len());
+                        entry.groups.push(Group {
+                            name: name.to_owned(),
+                            ..Group::default()
+                        });
+                    }
+                }
+                continue;
+            }
+            let separator = match line.find('=') {
+                Some(separator) => separator,
+                None => {
+                    return Err(error(indent, "Expected a group, an entry or a comment"));
+                }
+            };
+            let group = match current.map(|current| &mut entry.groups[current]) {
+                Some(group) => group,
+                None => {
+                    return Err(error(indent, "Entry outside of any group"));
+                }
+            };
+            let key = line[..separator].trim_end();
+            let (key, locale) = match key.find('[') {
+                Some(start) if key.ends_with(']') && start + 2 < key.len() => (
+                    &key[..start],
+                    Some(key[start + 1..key.len() - 1].to_owned()),
+                ),
+                Some(start) => {
+                    return Err(error(indent + start, "Malformed locale"));
+                }
+                None => (key, None),
+            };
+            if key.is_empty() {
+                return Err(error(indent, "Empty key"));
+            }
+            if let Some(i) = key.find(|c: char| c.is_whitespace() || c == ']' || c.is_control()) {
+                return Err(error(indent + i, "Invalid character in key"));
+            }
+            let value = line[separator + 1..].trim_start().to_owned();
+            let id = (key.to_owned(), locale);
+            if group.entries.contains_key(&id) {
+                warn!("Duplicated key {} on line {}", key, index + 1);
+                continue;
+            }
+            group.entries.insert(id, value);
+        }
+        Ok(entry)
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+}
+
+impl Group {
+    /// The value of `key` as written in the file.
+    pub fn raw(&self, key: &str) -> Option<&str> {
+        self.entries
+            .get(&(key.to_owned(), None))
+            .map(|value| &value[..])
+    }
+
+    /// The value of `key` with its escape sequences replaced.
+    pub fn string(&self, key: &str) -> Option<String> {
+        self.raw(key).map(unescape)
+    }
+
+    /// The values of the `;` separated list `key`.
+    pub fn strings(&self, key: &str) -> Option<Vec<String>> {
+        self.raw(key).map(split_list)
+    }
+
+    pub fn boolean(&self, key: &str) -> Option<bool> {
+        match self.raw(key)? {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The value of `key` that matches best `locale`, written like
+    /// `lang_COUNTRY.ENCODING@MODIFIER`. The current locale is used when it's `None`.
+    pub fn locale_string(&self, key: &str, locale: Option<&str>) -> Option<String> {
+        self.locale_raw(key, locale).map(unescape)
+    }
+
+    fn locale_raw(&self, key: &str, locale: Option<&str>) -> Option<&str> {
+        let locale = match locale {
+            Some(locale) => Some(locale.to_owned()),
+            None => current_locale(),
+        };
+        for candidate in locale.as_deref().map(locale_candidates).unwrap_or_default() {
+            if let Some(value) = self.entries.get(&(key.to_owned(), Some(candidate))) {
+                return Some(value);
+            }
+        }
+        self.raw(key)
+    }
+}
+
+/// The locale of the messages, from the environment.
+pub fn current_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .filter(|value| value != "C" && value != "POSIX")
+}
+
+/// The locale keys that match `locale` in order of preference: `lang_COUNTRY@MODIFIER`,
+/// `lang_COUNTRY`, `lang@MODIFIER` and `lang`, the encoding is ignored.
+fn locale_candidates(locale: &str) -> Vec<String> {
+    let (locale, modifier) = match locale.find('@') {
+        Some(i) => (&locale[..i], Some(&locale[i + 1..])),
+        None => (locale, None),
+    };
+    let locale = locale.split('.').next().unwrap_or(locale);
+    let (lang, country) = match locale.find('_') {
+        Some(i) => (&locale[..i], Some(&locale[i + 1..])),
+        None => (locale, None),
+    };
+    let mut candidates = Vec::new();
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        candidates.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        candidates.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        candidates.push(format!("{}@{}", lang, modifier));
+    }
+    candidates.push(lang.to_owned());
+    candidates
+}
+
+/// Splits the value of an `Exec` key into arguments. Arguments can be quoted with double quotes,
+/// inside them `"`, `` ` ``, `$` and `\` are escaped with a backslash.
+pub fn split_exec(exec: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = exec.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| *c == ' ' || *c == '\t') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut arg = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' | '\t' => {
+                    break;
+                }
+                '"' => loop {
+                    match chars.next()? {
+                        '"' => {
+                            break;
+                        }
+                        '\\' => match chars.next()? {
+                            c @ '"' | c @ '`' | c @ '$' | c @ '\\' => arg.push(c),
+                            c => {
+                                warn!("Invalid escape sequence \\{} in {}", c, exec);
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                        },
+                        c => arg.push(c),
+                    }
+                },
+                c => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+    Some(args)
+}
+
+/// Replaces the escape sequences `\s`, `\n`, `\t`, `\r` and `\\`, unknown ones are kept.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Splits a list separated by `;`, where `\;` is a literal semicolon, and unescapes its values.
+fn split_list(value: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(';') => current.push(';'),
+                Some(c) => {
+                    current.push('\\');
+                    current.push(c);
+                }
+                None => current.push('\\'),
+            },
+            ';' => values.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    values.push(current);
+    values
+        .iter()
+        .map(|v| unescape(v.trim()))
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> DesktopEntry {
+        DesktopEntry::parse(content.as_bytes()).unwrap()
+    }
+
+    /// The line and column of the syntax error of `content`.
+    fn error_position(content: &str) -> (usize, usize) {
+        match DesktopEntry::parse(content.as_bytes()) {
+            Err(ParseError::Syntax { line, column, .. }) => (line, column),
+            other => panic!("Expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        let entry = parse(
+            "[Desktop Entry]\n\
+             Name=A\\sb\\tc\\nd\\re\\\\f\\x\n\
+             Keywords=a\\;b; c ;\\sd;;\n\
+             Trailing=a\\\n",
+        );
+        let group = entry.group("Desktop Entry").unwrap();
+        assert_eq!(group.raw("Name").unwrap(), "A\\sb\\tc\\nd\\re\\\\f\\x");
+        assert_eq!(group.string("Name").unwrap(), "A b\tc\nd\re\\f\\x");
+        assert_eq!(group.strings("Keywords").unwrap(), ["a;b", "c", " d"]);
+        assert_eq!(group.string("Trailing").unwrap(), "a\\");
+    }
+
+    #[test]
+    fn booleans() {
+        let entry = parse("[Desktop Entry]\nA=true\nB=0\nC=yes\n");
+        let group = entry.group("Desktop Entry").unwrap();
+        assert_eq!(group.boolean("A"), Some(true));
+        assert_eq!(group.boolean("B"), Some(false));
+        assert_eq!(group.boolean("C"), None);
+        assert_eq!(group.boolean("D"), None);
+    }
+
+    #[test]
+    fn locale_keys() {
+        let entry = parse(
+            "[Desktop Entry]\n\
+             Name=Files\n\
+             Name[fr]=Fichiers\n\
+             Name[fr_CA]=Fichiers du Canada\n\
+             Name[sr@latin]=Datoteke\n\
+             Name[sr_RS@latin]=Datoteke Srbije\n",
+        );
+        let group = entry.group("Desktop Entry").unwrap();
+        let name = |locale| group.locale_string("Name", Some(locale)).unwrap();
+        assert_eq!(name("fr_FR.UTF-8"), "Fichiers");
+        assert_eq!(name("fr_CA.UTF-8"), "Fichiers du Canada");
+        assert_eq!(name("sr_RS.UTF-8@latin"), "Datoteke Srbije");
+        assert_eq!(name("sr_BA@latin"), "Datoteke");
+        assert_eq!(name("de_DE"), "Files");
+        assert_eq!(group.string("Name").unwrap(), "Files");
+    }
+
+    #[test]
+    fn comments_and_latin1() {
+        let entry =
+            DesktopEntry::parse(b"# Comment\n\n[Group]\n  # Indented\nName=Caf\xe9\n").unwrap();
+        assert_eq!(
+            entry.group("Group").unwrap().string("Name").unwrap(),
+            "Café"
+        );
+    }
+
+    #[test]
+    fn duplicates_keep_the_first_value() {
+        let entry = parse(
+            "[Desktop Entry]\n\
+             Name=First\n\
+             Name=Second\n\
+             [Other]\n\
+             Name=Other\n\
+             [Desktop Entry]\n\
+             Name=Third\n\
+             Exec=app\n",
+        );
+        let group = entry.group("Desktop Entry").unwrap();
+        assert_eq!(group.string("Name").unwrap(), "First");
+        assert_eq!(group.string("Exec").unwrap(), "app");
+        assert_eq!(
+            entry.group("Other").unwrap().string("Name").unwrap(),
+            "Other"
+        );
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error_position("Name=Files\n"), (1, 1));
+        assert_eq!(error_position("[Desktop Entry]\n\n  Name\n"), (3, 3));
+        assert_eq!(error_position("[Desktop Entry\n"), (1, 1));
+        assert_eq!(error_position("[Desktop]Entry]\n"), (1, 2));
+        assert_eq!(error_position("[Group]\nName[fr=Fichiers\n"), (2, 5));
+        assert_eq!(error_position("[Group]\n=value\n"), (2, 1));
+        assert_eq!(error_position("[Group]\nA key=value\n"), (2, 2));
+    }
+
+    #[test]
+    fn exec_arguments() {
+        assert_eq!(
+            split_exec("app  --name \"My \\\"App\\\" \\$HOME\" %f").unwrap(),
+            ["app", "--name", "My \"App\" $HOME", "%f"]
+        );
+        assert!(split_exec("app \"unterminated").is_none());
+    }
+}
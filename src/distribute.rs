@@ -1,19 +1,30 @@
 use crate::distribute_layout::DistributeLayout;
 use orbtk::prelude::*;
 
+/// The order in which the children of a `Distribute` are laid out, when it lists all of them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChildrenOrder(pub Vec<Entity>);
+
+into_property_source!(ChildrenOrder);
+
 widget!(
     /// The `Distribute` defines a layout that is used to stack its children vertical or horizontal.
     ///
     /// **style:** `distribute`
     Distribute {
         /// Margin between widgets in the stack.
-        spacing: f64
+        spacing: f64,
+
+        /// Sets or shares the order of the children, they are laid out in the tree order otherwise.
+        order: ChildrenOrder
     }
 );
 
 impl Template for Distribute {
     fn template(self, _: Entity, _: &mut BuildContext) -> Self {
-        self.name("Distribute").style("distribute")
+        self.name("Distribute")
+            .style("distribute")
+            .order(ChildrenOrder::default())
     }
 
     fn layout(&self) -> Box<dyn Layout> {
@@ -1,7 +1,11 @@
+use crate::distribute::ChildrenOrder;
 use orbtk::prelude::*;
 use std::cell::Cell;
 use std::collections::BTreeMap;
 
+/// The size of the slot of every child, they're laid out in rows of slots.
+pub const SLOT_SIZE: (f64, f64) = (64.0, 64.0);
+
 /// Stacks visual the children widgets vertical or horizontal.
 #[derive(Default, IntoLayout)]
 pub struct DistributeLayout {
@@ -79,14 +83,19 @@ impl Layout for DistributeLayout {
             return (0.0, 0.0);
         }
 
-        let slots_size = (64.0, 64.0);
+        let slots_size = SLOT_SIZE;
 
         let cols = (parent_size.0 / slots_size.0) as u32;
         let mut col = 0;
         let mut row = 0;
 
-        for index in 0..ecm.entity_store().children[&entity].len() {
-            let child = ecm.entity_store().children[&entity][index];
+        let mut children = ecm.entity_store().children[&entity].clone();
+        if let Ok(order) = ecm.component_store().get::<ChildrenOrder>("order", entity) {
+            if order.0.len() == children.len() {
+                children = order.0.clone();
+            }
+        }
+        for child in children {
             if let Some(child_layout) = layouts.get(&child) {
                 child_layout.arrange(
                     render_context_2_d,
@@ -0,0 +1,138 @@
+use super::{fill, read_block, Cursor, Tags, FRONT_COVER};
+use std::{
+    convert::TryInto,
+    error::Error,
+    io::{self, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+const STREAMINFO: u8 = 0;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+/// Reads the metadata blocks of a FLAC stream.
+pub(super) fn read(file: &mut (impl Read + Seek), tags: &mut Tags) -> Result<(), Box<dyn Error>> {
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    if &magic != b"fLaC" {
+        return Err("Not a FLAC stream".into());
+    }
+    loop {
+        let mut header = [0; 4];
+        file.read_exact(&mut header)?;
+        let last = header[0] & 0x80 != 0;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        match header[0] & 0x7F {
+            STREAMINFO => {
+                tags.metadata.duration = stream_duration(&read_block(file, len)?);
+            }
+            VORBIS_COMMENT => {
+                read_comments(&read_block(file, len)?, tags)?;
+            }
+            PICTURE if tags.read_covers => {
+                let block = read_block(file, len)?;
+                let (kind, picture) = read_picture(&block)?;
+                tags.add_picture(picture, kind == FRONT_COVER);
+            }
+            _ => {
+                file.seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+        if last {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn stream_duration(streaminfo: &[u8]) -> Option<Duration> {
+    // The sample rate takes 20 bits and the count of samples the last 36 bits of these 8 bytes
+    let bits = u64::from_be_bytes(streaminfo.get(10..18)?.try_into().unwrap());
+    let sample_rate = bits >> 44;
+    let samples = bits & 0xF_FFFF_FFFF;
+    if sample_rate == 0 || samples == 0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(samples as f64 / sample_rate as f64))
+}
+
+/// Reads Vorbis comments, as used by FLAC, Vorbis and Opus. Their pictures are stored in
+/// `METADATA_BLOCK_PICTURE` as base64 encoded FLAC picture blocks.
+pub(super) fn read_comments(data: &[u8], tags: &mut Tags) -> io::Result<()> {
+    let mut cursor = Cursor::new(data);
+    let vendor_len = cursor.u32_le()? as usize;
+    cursor.take(vendor_len)?;
+    let count = cursor.u32_le()?;
+    for _ in 0..count {
+        let len = cursor.u32_le()? as usize;
+        let comment = cursor.take(len)?;
+        let separator = match comment.iter().position(|b| *b == b'=') {
+            Some(separator) => separator,
+            None => {
+                continue;
+            }
+        };
+        let key = String::from_utf8_lossy(&comment[..separator]).to_ascii_uppercase();
+        let value = &comment[separator + 1..];
+        let metadata = &mut tags.metadata;
+        match &key[..] {
+            "TITLE" => fill(&mut metadata.title, &String::from_utf8_lossy(value)),
+            "ARTIST" => fill(&mut metadata.artist, &String::from_utf8_lossy(value)),
+            "ALBUM" => fill(&mut metadata.album, &String::from_utf8_lossy(value)),
+            "METADATA_BLOCK_PICTURE" if tags.read_covers => {
+                if let Some(block) = decode_base64(value) {
+                    if let Ok((kind, picture)) = read_picture(&block) {
+                        tags.add_picture(picture, kind == FRONT_COVER);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The kind and the data of a picture block.
+pub(super) fn read_picture(block: &[u8]) -> io::Result<(u32, &[u8])> {
+    let mut cursor = Cursor::new(block);
+    let kind = cursor.u32_be()?;
+    let mime_len = cursor.u32_be()? as usize;
+    cursor.take(mime_len)?;
+    let description_len = cursor.u32_be()? as usize;
+    cursor.take(description_len)?;
+    // Width, height, color depth and number of colors
+    cursor.take(16)?;
+    let len = cursor.u32_be()? as usize;
+    Ok((kind, cursor.take(len)?))
+}
+
+fn decode_base64(text: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                break;
+            }
+            c if c.is_ascii_whitespace() => {
+                continue;
+            }
+            _ => {
+                return None;
+            }
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
@@ -0,0 +1,364 @@
+use super::{fill, read_block, read_up_to, Cursor, Tags, FRONT_COVER};
+use std::{
+    borrow::Cow,
+    error::Error,
+    io::{self, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+/// How far the first MPEG frame is searched after the tags.
+const FRAME_SEARCH_LIMIT: usize = 64 * 1024;
+
+/// Reads the ID3v2 tag at the start of the file, returning where the audio data starts.
+pub(super) fn read_v2(
+    file: &mut (impl Read + Seek),
+    tags: &mut Tags,
+) -> Result<u64, Box<dyn Error>> {
+    let mut header = [0; 10];
+    file.read_exact(&mut header)?;
+    let version = header[3];
+    let flags = header[5];
+    let size = syncsafe(&header[6..10]) as u64;
+    // ID3v2.4 tags can end with a footer
+    let footer = if version == 4 && flags & 0x10 != 0 {
+        10
+    } else {
+        0
+    };
+    let end = 10 + size + footer;
+    if !(2..=4).contains(&version) {
+        return Ok(end);
+    }
+    let mut body = read_block(file, size)?;
+    if version < 4 && flags & 0x80 != 0 {
+        body = resynchronize(&body);
+    }
+    let mut cursor = Cursor::new(&body);
+    if version >= 3 && flags & 0x40 != 0 {
+        let extended_size = match version {
+            3 => cursor.u32_be()? as usize,
+            _ => (syncsafe(cursor.take(4)?) as usize).saturating_sub(4),
+        };
+        cursor.take(extended_size)?;
+    }
+    loop {
+        let header_size = if version == 2 { 6 } else { 10 };
+        if cursor.remaining() < header_size {
+            break;
+        }
+        let id = cursor.take(if version == 2 { 3 } else { 4 })?;
+        // The padding is made of zeros
+        if id[0] == 0 {
+            break;
+        }
+        let size = match version {
+            2 => {
+                let size = cursor.take(3)?;
+                u32::from_be_bytes([0, size[0], size[1], size[2]])
+            }
+            3 => cursor.u32_be()?,
+            _ => syncsafe(cursor.take(4)?),
+        } as usize;
+        let flags = if version == 2 {
+            0
+        } else {
+            let flags = cursor.take(2)?;
+            flags[1]
+        };
+        let data = match cursor.take(size) {
+            Ok(data) => data,
+            Err(_) => {
+                break;
+            }
+        };
+        let data: Cow<[u8]> = match version {
+            2 => data.into(),
+            3 => {
+                // Compressed or encrypted
+                if flags & 0xC0 != 0 {
+                    continue;
+                }
+                let group = if flags & 0x20 != 0 { 1 } else { 0 };
+                match data.get(group..) {
+                    Some(data) => data.into(),
+                    None => {
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                // Compressed or encrypted
+                if flags & 0x0C != 0 {
+                    continue;
+                }
+                // The group identifier and the data length indicator precede the data
+                let group = if flags & 0x40 != 0 { 1 } else { 0 };
+                let data_length = if flags & 0x01 != 0 { 4 } else { 0 };
+                let data = match data.get(group + data_length..) {
+                    Some(data) => data,
+                    None => {
+                        continue;
+                    }
+                };
+                if flags & 0x02 != 0 {
+                    resynchronize(data).into()
+                } else {
+                    data.into()
+                }
+            }
+        };
+        read_frame(id, &data, tags);
+    }
+    Ok(end)
+}
+
+fn read_frame(id: &[u8], data: &[u8], tags: &mut Tags) {
+    let metadata = &mut tags.metadata;
+    match id {
+        b"TT2" | b"TIT2" => fill(&mut metadata.title, &decode_text(data).unwrap_or_default()),
+        b"TP1" | b"TPE1" => fill(&mut metadata.artist, &decode_text(data).unwrap_or_default()),
+        b"TAL" | b"TALB" => fill(&mut metadata.album, &decode_text(data).unwrap_or_default()),
+        b"TLE" | b"TLEN" => {
+            if let Some(millis) = decode_text(data).and_then(|text| text.parse::<u64>().ok()) {
+                metadata.duration = Some(Duration::from_millis(millis));
+            }
+        }
+        b"PIC" | b"APIC" => {
+            if let Some((kind, picture)) = read_picture(data, id.len() == 3) {
+                tags.add_picture(picture, kind as u32 == FRONT_COVER);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The kind and the data of an attached picture.
+fn read_picture(data: &[u8], v2: bool) -> Option<(u8, &[u8])> {
+    let (&encoding, rest) = data.split_first()?;
+    // The image format is a 3 characters code in ID3v2.2 and a MIME type in later versions
+    let rest = if v2 {
+        rest.get(3..)?
+    } else {
+        &rest[rest.iter().position(|b| *b == 0)? + 1..]
+    };
+    let (&kind, rest) = rest.split_first()?;
+    Some((kind, skip_terminated(rest, encoding)?))
+}
+
+/// Skips a string terminated by a null of the width of `encoding`.
+fn skip_terminated(data: &[u8], encoding: u8) -> Option<&[u8]> {
+    match encoding {
+        1 | 2 => {
+            let end = data.chunks(2).position(|pair| pair == [0, 0])?;
+            data.get(end * 2 + 2..)
+        }
+        _ => data.get(data.iter().position(|b| *b == 0)? + 1..),
+    }
+}
+
+/// Decodes the first value of a text frame.
+fn decode_text(data: &[u8]) -> Option<String> {
+    let (&encoding, text) = data.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|b| *b as char).collect(),
+        1 => match text {
+            [0xFE, 0xFF, text @ ..] => decode_utf16(text, u16::from_be_bytes),
+            [0xFF, 0xFE, text @ ..] => decode_utf16(text, u16::from_le_bytes),
+            _ => decode_utf16(text, u16::from_le_bytes),
+        },
+        2 => decode_utf16(text, u16::from_be_bytes),
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => {
+            return None;
+        }
+    };
+    // ID3v2.4 separates multiple values with nulls
+    text.split('\0').next().map(|value| value.to_owned())
+}
+
+fn decode_utf16(data: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let units = data.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    std::char::decode_utf16(units)
+        .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 7) | (*byte & 0x7F) as u32)
+}
+
+/// Removes the zeros inserted after every 0xFF by the unsynchronisation scheme.
+fn resynchronize(data: &[u8]) -> Vec<u8> {
+    let mut resynchronized = Vec::with_capacity(data.len());
+    let mut previous = 0;
+    for byte in data {
+        if !(previous == 0xFF && *byte == 0) {
+            resynchronized.push(*byte);
+        }
+        previous = *byte;
+    }
+    resynchronized
+}
+
+/// Reads the ID3v1 tag and the duration of an MPEG audio stream that starts at `start`.
+pub(super) fn read_mpeg(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    len: u64,
+    tags: &mut Tags,
+) -> Result<(), Box<dyn Error>> {
+    let mut end = len;
+    if len >= start + 128 {
+        file.seek(SeekFrom::Start(len - 128))?;
+        let mut tag = [0; 128];
+        file.read_exact(&mut tag)?;
+        if tag.starts_with(b"TAG") {
+            end -= 128;
+            let latin1 = |field: &[u8]| field.iter().map(|b| *b as char).collect::<String>();
+            let metadata = &mut tags.metadata;
+            fill(&mut metadata.title, &latin1(&tag[3..33]));
+            fill(&mut metadata.artist, &latin1(&tag[33..63]));
+            fill(&mut metadata.album, &latin1(&tag[63..93]));
+        }
+    }
+    if tags.metadata.duration.is_none() {
+        tags.metadata.duration = mpeg_duration(file, start, end)?;
+    }
+    Ok(())
+}
+
+/// The header of an MPEG audio frame.
+struct FrameHeader {
+    /// 1 for MPEG-1, 2 for MPEG-2 and MPEG-2.5.
+    version: u8,
+    layer: u8,
+    /// In kbit/s, 0 for the free format.
+    bitrate: u32,
+    sample_rate: u32,
+    mono: bool,
+}
+
+impl FrameHeader {
+    fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version_bits = (bytes[1] >> 3) & 3;
+        let layer = match (bytes[1] >> 1) & 3 {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => {
+                return None;
+            }
+        };
+        let bitrate_index = (bytes[2] >> 4) as usize;
+        let rate_index = ((bytes[2] >> 2) & 3) as usize;
+        if version_bits == 1 || bitrate_index == 15 || rate_index == 3 {
+            return None;
+        }
+        let version = if version_bits == 3 { 1 } else { 2 };
+        let bitrates: [u32; 15] = match (version, layer) {
+            (1, 1) => [
+                0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+            ],
+            (1, 2) => [
+                0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+            ],
+            (1, _) => [
+                0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+            ],
+            (_, 1) => [
+                0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+            ],
+            _ => [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        };
+        let sample_rate = [44100, 48000, 32000][rate_index]
+            / match version_bits {
+                3 => 1,
+                2 => 2,
+                _ => 4,
+            };
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate: bitrates[bitrate_index],
+            sample_rate,
+            mono: bytes[3] >> 6 == 3,
+        })
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, 2) => 576,
+            _ => 1152,
+        }
+    }
+
+    /// The size of the side information that precedes the Xing header.
+    fn side_info_size(&self) -> usize {
+        match (self.version, self.mono) {
+            (1, false) => 32,
+            (1, true) | (2, false) => 17,
+            _ => 9,
+        }
+    }
+}
+
+pub(super) fn is_frame_header(bytes: &[u8]) -> bool {
+    FrameHeader::parse(bytes).is_some()
+}
+
+/// The duration of an MPEG audio stream, from the frame count of its Xing or VBRI header or
+/// else from its bitrate.
+fn mpeg_duration(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+) -> io::Result<Option<Duration>> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = vec![0; FRAME_SEARCH_LIMIT];
+    let read = read_up_to(file, &mut buf)?;
+    buf.truncate(read);
+    let (offset, header) = match (0..buf.len())
+        .find_map(|i| FrameHeader::parse(&buf[i..]).map(|header| (i, header)))
+    {
+        Some(found) => found,
+        None => {
+            return Ok(None);
+        }
+    };
+    let field = |at: usize| {
+        buf.get(at..at + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let frames_duration = |frames: u32| {
+        Duration::from_secs_f64(
+            frames as f64 * header.samples_per_frame() as f64 / header.sample_rate as f64,
+        )
+    };
+    let xing = offset + 4 + header.side_info_size();
+    if matches!(buf.get(xing..xing + 4), Some(b"Xing") | Some(b"Info")) {
+        if let (Some(flags), Some(frames)) = (field(xing + 4), field(xing + 8)) {
+            if flags & 1 != 0 {
+                return Ok(Some(frames_duration(frames)));
+            }
+        }
+    }
+    let vbri = offset + 4 + 32;
+    if buf.get(vbri..vbri + 4) == Some(b"VBRI") {
+        if let Some(frames) = field(vbri + 14) {
+            return Ok(Some(frames_duration(frames)));
+        }
+    }
+    if header.bitrate == 0 {
+        return Ok(None);
+    }
+    let bytes = end.saturating_sub(start + offset as u64);
+    Ok(Some(Duration::from_secs_f64(
+        bytes as f64 * 8.0 / (header.bitrate as f64 * 1000.0),
+    )))
+}
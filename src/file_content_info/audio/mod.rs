@@ -0,0 +1,296 @@
+use std::{
+    convert::TryInto,
+    error::Error,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+mod flac;
+mod id3;
+mod mp4;
+mod ogg;
+
+/// The largest block that is read in memory, like a cover or the metadata of an MP4 file.
+const BLOCK_LIMIT: u64 = 32 * 1024 * 1024;
+/// The kind of picture of the front cover, in ID3v2 and FLAC.
+const FRONT_COVER: u32 = 3;
+
+/// The tags of an audio file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// What is read from an audio file.
+#[derive(Debug, Default)]
+struct Tags {
+    metadata: AudioMetadata,
+    /// The preferred picture and whether it's the front cover.
+    cover: Option<(Vec<u8>, bool)>,
+    /// Whether the pictures are read, they are skipped otherwise.
+    read_covers: bool,
+}
+
+impl Tags {
+    /// Whether a picture is better than the current one, the front cover is preferred and
+    /// otherwise the first picture.
+    fn wants_picture(&self, front_cover: bool) -> bool {
+        self.read_covers
+            && match self.cover {
+                Some((_, front)) => front_cover && !front,
+                None => true,
+            }
+    }
+
+    fn add_picture(&mut self, data: &[u8], front_cover: bool) {
+        if self.wants_picture(front_cover) && !data.is_empty() {
+            self.cover = Some((data.to_vec(), front_cover));
+        }
+    }
+}
+
+/// Reads the tags of MP3, FLAC, Ogg Vorbis, Opus and MP4 files, `None` is returned for other
+/// formats.
+pub fn read_metadata(path: impl AsRef<Path>) -> Result<Option<AudioMetadata>, Box<dyn Error>> {
+    Ok(read(path.as_ref(), false)?.map(|tags| tags.metadata))
+}
+
+/// Reads the embedded cover art of an audio file, the front cover is preferred.
+pub fn read_cover(path: impl AsRef<Path>) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    Ok(read(path.as_ref(), true)?
+        .and_then(|tags| tags.cover)
+        .map(|(data, _)| data))
+}
+
+fn read(path: &Path, read_covers: bool) -> Result<Option<Tags>, Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let len = file.get_ref().metadata()?.len();
+    let mut tags = Tags {
+        read_covers,
+        ..Tags::default()
+    };
+    let mut magic = [0; 12];
+    let read = read_up_to(&mut file, &mut magic)?;
+    let magic = &magic[..read];
+    file.seek(SeekFrom::Start(0))?;
+    if magic.starts_with(b"ID3") {
+        let end = id3::read_v2(&mut file, &mut tags)?;
+        let mut next = [0; 4];
+        file.seek(SeekFrom::Start(end))?;
+        let is_flac = read_up_to(&mut file, &mut next)? == 4 && &next == b"fLaC";
+        file.seek(SeekFrom::Start(end))?;
+        if is_flac {
+            flac::read(&mut file, &mut tags)?;
+        } else {
+            id3::read_mpeg(&mut file, end, len, &mut tags)?;
+        }
+    } else if magic.starts_with(b"fLaC") {
+        flac::read(&mut file, &mut tags)?;
+    } else if magic.starts_with(b"OggS") {
+        ogg::read(&mut file, len, &mut tags)?;
+    } else if magic.get(4..8) == Some(b"ftyp") {
+        mp4::read(&mut file, len, &mut tags)?;
+    } else if id3::is_frame_header(magic) {
+        id3::read_mpeg(&mut file, 0, len, &mut tags)?;
+    } else {
+        return Ok(None);
+    }
+    Ok(Some(tags))
+}
+
+/// Sets `field` to `value` unless it's already set or the value is empty.
+fn fill(field: &mut Option<String>, value: &str) {
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if field.is_none() && !value.is_empty() {
+        *field = Some(value.to_owned());
+    }
+}
+
+/// Fills `buf` as much as possible, returning how many bytes were read.
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => {
+                break;
+            }
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+    Ok(read)
+}
+
+/// Reads a block of `len` bytes, up to `BLOCK_LIMIT`.
+fn read_block(reader: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    if len > BLOCK_LIMIT {
+        return Err(invalid_data("Block too large"));
+    }
+    let mut block = vec![0; len as usize];
+    reader.read_exact(&mut block)?;
+    Ok(block)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the fields of a block in memory.
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Cursor<'a> {
+        Cursor { data }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(invalid_data("Truncated block"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32_be(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64_be(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    fn fixture(name: &str) -> PathBuf {
+        crate::test_fixture("audio", name)
+    }
+
+    fn metadata(name: &str) -> AudioMetadata {
+        read_metadata(fixture(name)).unwrap().unwrap()
+    }
+
+    fn assert_tags(metadata: &AudioMetadata, title: &str, artist: &str, album: &str) {
+        assert_eq!(metadata.title.as_deref(), Some(title));
+        assert_eq!(metadata.artist.as_deref(), Some(artist));
+        assert_eq!(metadata.album.as_deref(), Some(album));
+    }
+
+    fn assert_duration(metadata: &AudioMetadata, seconds: f64) {
+        let duration = metadata.duration.unwrap().as_secs_f64();
+        assert!((duration - seconds).abs() < 0.001, "{} s", duration);
+    }
+
+    #[test]
+    fn id3() {
+        let v23 = metadata("id3v23.mp3");
+        assert_tags(&v23, "Tïtle", "Artist", "Album");
+        // From the frame count of the Xing header
+        assert_duration(&v23, 100.0 * 1152.0 / 44100.0);
+        // Only the first value is kept, the unsynchronisation of the frame is removed
+        let v24 = metadata("id3v24.mp3");
+        assert_eq!(v24.title.as_deref(), Some("Title"));
+        assert_eq!(v24.artist.as_deref(), Some("Artÿist"));
+        assert_duration(&v24, 61.5);
+        assert_tags(&metadata("id3v22.mp3"), "Title", "Artist", "Album");
+        // From the bitrate
+        let v1 = metadata("id3v1.mp3");
+        assert_tags(&v1, "Title", "Artist", "Album");
+        assert_duration(&v1, 417.0 * 10.0 * 8.0 / 128_000.0);
+    }
+
+    #[test]
+    fn flac() {
+        let flac = metadata("tagged.flac");
+        assert_tags(&flac, "Title", "Artist", "Album");
+        assert_duration(&flac, 10.0);
+        // The ID3v2 tag is read before the FLAC stream
+        let id3 = metadata("id3.flac");
+        assert_tags(&id3, "ID3 title", "Artist", "Album");
+        assert_duration(&id3, 10.0);
+    }
+
+    #[test]
+    fn ogg() {
+        let vorbis = metadata("vorbis.ogg");
+        assert_tags(&vorbis, "Title", "Artist", "Album");
+        assert_duration(&vorbis, 5.0);
+        let opus = metadata("opus.opus");
+        assert_eq!(opus.title.as_deref(), Some("Opus title"));
+        assert_duration(&opus, 3.0);
+    }
+
+    #[test]
+    fn mp4() {
+        let mp4 = metadata("tagged.m4a");
+        assert_tags(&mp4, "Title", "Artist", "Album");
+        assert_duration(&mp4, 4.5);
+    }
+
+    #[test]
+    fn front_covers() {
+        for name in &["id3v23.mp3", "tagged.flac", "vorbis.ogg", "tagged.m4a"] {
+            let cover = read_cover(fixture(name)).unwrap().unwrap();
+            assert!(cover.starts_with(PNG_SIGNATURE), "{}", name);
+        }
+        assert!(read_cover(fixture("id3v22.mp3")).unwrap().is_none());
+    }
+
+    #[test]
+    fn other_formats() {
+        assert!(read_metadata(crate::test_fixture("images", "red.png"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn truncated_files() {
+        for name in &["truncated.flac", "truncated.ogg", "truncated.m4a"] {
+            assert!(read_metadata(fixture(name)).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn oversized_blocks() {
+        for name in &[
+            "oversized-tag.mp3",
+            "oversized-count.flac",
+            "oversized.m4a",
+            "oversized-box.m4a",
+        ] {
+            assert!(read_metadata(fixture(name)).is_err(), "{}", name);
+        }
+        // The frames before the oversized one are kept
+        let metadata = metadata("oversized-frame.mp3");
+        assert_eq!(metadata.title.as_deref(), Some("Title"));
+        assert_eq!(metadata.artist, None);
+    }
+}
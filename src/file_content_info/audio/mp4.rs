@@ -0,0 +1,131 @@
+use super::{fill, invalid_data, read_block, Cursor, Tags};
+use std::{
+    error::Error,
+    io::{self, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+/// Reads the iTunes metadata and the duration of the movie box of an MP4 file.
+pub(super) fn read(
+    file: &mut (impl Read + Seek),
+    len: u64,
+    tags: &mut Tags,
+) -> Result<(), Box<dyn Error>> {
+    let mut position = 0;
+    while position + 8 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+        let mut header_len = 8;
+        let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // The box extends to the end of the file
+            0 => len - position,
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                header_len = 16;
+                u64::from_be_bytes(size)
+            }
+            size => size as u64,
+        };
+        if size < header_len {
+            return Err(invalid_data("Invalid MP4 box").into());
+        }
+        if &header[4..8] == b"moov" {
+            let movie = read_block(file, size - header_len)?;
+            read_movie(&movie, tags)?;
+            break;
+        }
+        position += size;
+    }
+    Ok(())
+}
+
+/// The boxes contained in `data`, with their type.
+fn children(data: &[u8]) -> io::Result<Vec<(&[u8], &[u8])>> {
+    let mut cursor = Cursor::new(data);
+    let mut children = Vec::new();
+    while cursor.remaining() >= 8 {
+        let size = cursor.u32_be()? as u64;
+        let kind = cursor.take(4)?;
+        let content_len = match size {
+            0 => Some(cursor.remaining() as u64),
+            1 => cursor.u64_be()?.checked_sub(16),
+            size => size.checked_sub(8),
+        }
+        .ok_or_else(|| invalid_data("Invalid MP4 box"))?;
+        children.push((kind, cursor.take(content_len as usize)?));
+    }
+    Ok(children)
+}
+
+fn read_movie(movie: &[u8], tags: &mut Tags) -> io::Result<()> {
+    for (kind, content) in children(movie)? {
+        match kind {
+            b"mvhd" => tags.metadata.duration = movie_duration(content)?,
+            b"udta" => {
+                for (kind, content) in children(content)? {
+                    if kind == b"meta" {
+                        read_meta(content, tags)?;
+                    }
+                }
+            }
+            b"meta" => read_meta(content, tags)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn movie_duration(header: &[u8]) -> io::Result<Option<Duration>> {
+    let mut cursor = Cursor::new(header);
+    let version = cursor.u8()?;
+    // The flags and the creation and modification times
+    let (timescale, duration) = if version == 1 {
+        cursor.take(3 + 16)?;
+        (cursor.u32_be()?, cursor.u64_be()?)
+    } else {
+        cursor.take(3 + 8)?;
+        (cursor.u32_be()?, cursor.u32_be()? as u64)
+    };
+    // The duration is all ones when unknown
+    if timescale == 0 || duration == u64::MAX || duration == u32::MAX as u64 {
+        return Ok(None);
+    }
+    Ok(Some(Duration::from_secs_f64(
+        duration as f64 / timescale as f64,
+    )))
+}
+
+fn read_meta(meta: &[u8], tags: &mut Tags) -> io::Result<()> {
+    // The metadata box has a version and flags, except in QuickTime files where it directly
+    // starts with its handler box
+    let meta = if meta.get(4..8) == Some(b"hdlr") {
+        meta
+    } else {
+        meta.get(4..).unwrap_or_default()
+    };
+    for (kind, content) in children(meta)? {
+        if kind != b"ilst" {
+            continue;
+        }
+        for (item, content) in children(content)? {
+            for (kind, data) in children(content)? {
+                // The type and the locale precede the value
+                if kind != b"data" || data.len() < 8 {
+                    continue;
+                }
+                let value = &data[8..];
+                let metadata = &mut tags.metadata;
+                match item {
+                    b"\xA9nam" => fill(&mut metadata.title, &String::from_utf8_lossy(value)),
+                    b"\xA9ART" => fill(&mut metadata.artist, &String::from_utf8_lossy(value)),
+                    b"\xA9alb" => fill(&mut metadata.album, &String::from_utf8_lossy(value)),
+                    b"covr" => tags.add_picture(value, true),
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
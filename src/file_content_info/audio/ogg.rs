@@ -0,0 +1,137 @@
+use super::{flac, invalid_data, read_block, read_up_to, Tags, BLOCK_LIMIT};
+use std::{
+    convert::TryInto,
+    error::Error,
+    io::{self, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+/// How far from the end of the file the last page is searched.
+const LAST_PAGE_SEARCH: u64 = 64 * 1024;
+/// The rate of the granule positions of Opus streams.
+const OPUS_RATE: u32 = 48000;
+
+/// Reads the comments and the duration of an Ogg Vorbis or Opus stream, the first logical
+/// stream of the file is used.
+pub(super) fn read(
+    file: &mut (impl Read + Seek),
+    len: u64,
+    tags: &mut Tags,
+) -> Result<(), Box<dyn Error>> {
+    let (serial, packets) = read_first_packets(file, 2)?;
+    let identification = &packets[0];
+    let (comments, sample_rate, pre_skip) = if identification.starts_with(b"\x01vorbis") {
+        let rate = identification
+            .get(12..16)
+            .ok_or("Truncated Vorbis header")?;
+        (
+            packets[1].strip_prefix(b"\x03vorbis"),
+            u32::from_le_bytes(rate.try_into().unwrap()),
+            0,
+        )
+    } else if identification.starts_with(b"OpusHead") {
+        let pre_skip = identification.get(10..12).ok_or("Truncated Opus header")?;
+        (
+            packets[1].strip_prefix(b"OpusTags"),
+            OPUS_RATE,
+            u16::from_le_bytes(pre_skip.try_into().unwrap()),
+        )
+    } else {
+        // Not an audio codec with Vorbis comments
+        return Ok(());
+    };
+    if let Some(comments) = comments {
+        flac::read_comments(comments, tags)?;
+    }
+    if sample_rate > 0 {
+        if let Some(granule) = last_granule(file, len, serial)? {
+            let samples = granule.saturating_sub(pre_skip as u64);
+            tags.metadata.duration =
+                Some(Duration::from_secs_f64(samples as f64 / sample_rate as f64));
+        }
+    }
+    Ok(())
+}
+
+/// The header of an Ogg page, followed by its segment table.
+struct PageHeader {
+    serial: u32,
+    segments: Vec<u8>,
+}
+
+fn read_page_header(file: &mut impl Read) -> io::Result<Option<PageHeader>> {
+    let mut header = [0; 27];
+    match read_up_to(file, &mut header)? {
+        0 => {
+            return Ok(None);
+        }
+        27 => {}
+        _ => {
+            return Err(invalid_data("Truncated Ogg page"));
+        }
+    }
+    if !header.starts_with(b"OggS") {
+        return Err(invalid_data("Invalid Ogg page"));
+    }
+    let segments = read_block(file, header[26] as u64)?;
+    Ok(Some(PageHeader {
+        serial: u32::from_le_bytes(header[14..18].try_into().unwrap()),
+        segments,
+    }))
+}
+
+/// Reads the first `count` packets of the first logical stream, with its serial number.
+fn read_first_packets(
+    file: &mut (impl Read + Seek),
+    count: usize,
+) -> io::Result<(u32, Vec<Vec<u8>>)> {
+    let mut serial = None;
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    while packets.len() < count {
+        let page = match read_page_header(file)? {
+            Some(page) => page,
+            None => {
+                return Err(invalid_data("Truncated Ogg stream"));
+            }
+        };
+        let body_len: u64 = page.segments.iter().map(|len| *len as u64).sum();
+        if *serial.get_or_insert(page.serial) != page.serial {
+            file.seek(SeekFrom::Current(body_len as i64))?;
+            continue;
+        }
+        let body = read_block(file, body_len)?;
+        let mut start = 0;
+        for len in page.segments {
+            packet.extend_from_slice(&body[start..start + len as usize]);
+            start += len as usize;
+            if packet.len() as u64 > BLOCK_LIMIT {
+                return Err(invalid_data("Ogg packet too large"));
+            }
+            // A packet continues in the next segment when this one is full
+            if len < 255 {
+                packets.push(std::mem::take(&mut packet));
+                if packets.len() == count {
+                    break;
+                }
+            }
+        }
+    }
+    Ok((serial.unwrap(), packets))
+}
+
+/// The granule position of the last page of a logical stream, its number of samples.
+fn last_granule(file: &mut (impl Read + Seek), len: u64, serial: u32) -> io::Result<Option<u64>> {
+    let start = len.saturating_sub(LAST_PAGE_SEARCH);
+    file.seek(SeekFrom::Start(start))?;
+    let tail = read_block(file, len - start)?;
+    let granule = tail
+        .windows(18)
+        .rev()
+        .filter(|header| header.starts_with(b"OggS"))
+        .filter(|header| u32::from_le_bytes(header[14..18].try_into().unwrap()) == serial)
+        .map(|header| u64::from_le_bytes(header[6..14].try_into().unwrap()))
+        // Pages where no packet ends have no position
+        .find(|granule| *granule != u64::MAX);
+    Ok(granule)
+}
@@ -0,0 +1,41 @@
+use crate::file_content_info::audio;
+use crate::file_content_info::generators::{mime_types, Cost, ThumbnailGenerator};
+use image::DynamicImage;
+use mime::Mime;
+use std::error::Error;
+use std::path::Path;
+
+/// Uses the cover art embedded in audio files as their thumbnail.
+pub struct AudioCovers {
+    mime_types: Vec<Mime>,
+}
+
+impl AudioCovers {
+    pub fn new() -> AudioCovers {
+        AudioCovers {
+            mime_types: mime_types(&["audio/*"]),
+        }
+    }
+}
+
+impl ThumbnailGenerator for AudioCovers {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Cheap
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        _mime: &Mime,
+        _size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        match audio::read_cover(path)? {
+            Some(cover) => Ok(Some(image::load_from_memory(&cover)?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::file_content_info::generators::{mime_types, Cost, ThumbnailGenerator};
+use image::{DynamicImage, RgbaImage};
+use lazy_static::lazy_static;
+use log::debug;
+use mime::Mime;
+use parking_lot::Mutex;
+use pdfium_render::prelude::*;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+mod opf;
+
+/// Covers larger than this aren't decoded.
+const COVER_LIMIT: u64 = 64 * 1024 * 1024;
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+
+lazy_static! {
+    /// PDFium, loaded at run time once for every document. It isn't thread safe, so the
+    /// documents are rendered one at a time.
+    static ref PDFIUM: Option<Mutex<Pdfium>> = match Pdfium::bind_to_system_library() {
+        Ok(bindings) => Some(Mutex::new(Pdfium::new(bindings))),
+        Err(e) => {
+            debug!("Can't load PDFium: {}", e);
+            None
+        }
+    };
+}
+
+/// Shows the first page of PDF documents and the cover of EPUB books and comic book archives.
+///
+/// PDFium parses the documents in this process, out of the sandbox of the system thumbnailers.
+/// These have a higher priority, so an installed PDF thumbnailer is used instead.
+pub struct DocumentThumbnailer {
+    mime_types: Vec<Mime>,
+}
+
+impl DocumentThumbnailer {
+    pub fn new() -> DocumentThumbnailer {
+        DocumentThumbnailer {
+            mime_types: mime_types(&[
+                "application/pdf",
+                "application/epub+zip",
+                "application/x-cbz",
+                "application/x-cbr",
+                "application/vnd.comicbook+zip",
+                "application/vnd.comicbook-rar",
+            ]),
+        }
+    }
+}
+
+impl ThumbnailGenerator for DocumentThumbnailer {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Expensive
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        _mime: &Mime,
+        size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        let mut magic = [0; 4];
+        File::open(path)?.read_exact(&mut magic)?;
+        match &magic {
+            b"%PDF" => render_pdf(path, size),
+            b"PK\x03\x04" => zip_cover(path),
+            b"Rar!" => rar_cover(path),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn render_pdf(path: &Path, size: u32) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    // The documents keep their glyph without the library
+    let pdfium = match PDFIUM.as_ref() {
+        Some(pdfium) => pdfium.lock(),
+        None => {
+            return Ok(None);
+        }
+    };
+    let document = pdfium.load_pdf_from_file(path, None)?;
+    let page = document.pages().first()?;
+    let config = PdfRenderConfig::new()
+        .set_target_width(size as i32)
+        .set_maximum_height(size as i32);
+    let bitmap = page.render_with_config(&config)?;
+    let image = RgbaImage::from_raw(
+        bitmap.width() as u32,
+        bitmap.height() as u32,
+        bitmap.as_rgba_bytes(),
+    )
+    .ok_or("Invalid page bitmap")?;
+    Ok(Some(DynamicImage::ImageRgba8(image)))
+}
+
+/// The cover of an EPUB book or the first page of a CBZ comic book.
+fn zip_cover(path: &Path) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let names: Vec<String> = archive.file_names().map(|name| name.to_owned()).collect();
+    let cover = if names.iter().any(|name| name == "META-INF/container.xml") {
+        let container = read_text(&mut archive, "META-INF/container.xml")?;
+        let package_path = opf::package_path(&container).ok_or("No package document")?;
+        let package = read_text(&mut archive, &package_path)?;
+        opf::cover_path(&package, &package_path)
+    } else {
+        first_page(&names).map(|name| name.to_owned())
+    };
+    let cover = match cover {
+        Some(cover) => cover,
+        None => {
+            return Ok(None);
+        }
+    };
+    let entry = archive.by_name(&cover)?;
+    if entry.size() > COVER_LIMIT {
+        return Ok(None);
+    }
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry.take(COVER_LIMIT).read_to_end(&mut data)?;
+    Ok(Some(image::load_from_memory(&data)?))
+}
+
+fn read_text(
+    archive: &mut zip::ZipArchive<BufReader<File>>,
+    name: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut text = String::new();
+    archive
+        .by_name(name)?
+        .take(COVER_LIMIT)
+        .read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// The first page of a CBR comic book.
+fn rar_cover(path: &Path) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    let mut names = Vec::new();
+    for entry in unrar::Archive::new(path).open_for_listing()? {
+        let entry = entry?;
+        if entry.is_file() && entry.unpacked_size <= COVER_LIMIT {
+            names.push(entry.filename.to_string_lossy().into_owned());
+        }
+    }
+    let cover = match first_page(&names) {
+        Some(cover) => cover.to_owned(),
+        None => {
+            return Ok(None);
+        }
+    };
+    let mut archive = unrar::Archive::new(path).open_for_processing()?;
+    while let Some(header) = archive.read_header()? {
+        archive = if header.entry().filename.to_string_lossy() == cover {
+            let (data, _) = header.read()?;
+            return Ok(Some(image::load_from_memory(&data)?));
+        } else {
+            header.skip()?
+        };
+    }
+    Ok(None)
+}
+
+/// The first image of a comic book archive, its pages are named in order.
+fn first_page(names: &[String]) -> Option<&str> {
+    names
+        .iter()
+        .filter(|name| {
+            let file_name = name.rsplit('/').next().unwrap_or_default();
+            let is_image = file_name.rsplit('.').next().map_or(false, |ext| {
+                IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str())
+            });
+            is_image && !file_name.starts_with('.') && !name.starts_with("__MACOSX/")
+        })
+        .min_by(|a, b| natural_cmp(a, b))
+        .map(|name| name.as_str())
+}
+
+/// Compares names ignoring the case and with their numbers compared by value, so that
+/// `page2` comes before `page10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => {
+                return Ordering::Equal;
+            }
+            (None, Some(_)) => {
+                return Ordering::Less;
+            }
+            (Some(_), None) => {
+                return Ordering::Greater;
+            }
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+                        digits.push(c);
+                        chars.next();
+                    }
+                    digits.trim_start_matches('0').to_owned()
+                };
+                let (x, y) = (number(&mut a), number(&mut b));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(natural_cmp("page2.jpg", "page10.jpg"), Ordering::Less);
+        assert_eq!(natural_cmp("page010.jpg", "page9.jpg"), Ordering::Greater);
+        assert_eq!(natural_cmp("Page1.jpg", "page1.JPG"), Ordering::Equal);
+        assert_eq!(natural_cmp("a/page1.jpg", "b/page0.jpg"), Ordering::Less);
+        assert_eq!(natural_cmp("page", "page1"), Ordering::Less);
+    }
+
+    #[test]
+    fn first_pages() {
+        let archive = names(&[
+            "__MACOSX/Comic/._page001.jpg",
+            "Comic/.page000.jpg",
+            "Comic/info.txt",
+            "Comic/page10.jpg",
+            "Comic/page2.PNG",
+            "Comic/page3.jpg",
+        ]);
+        assert_eq!(first_page(&archive).unwrap(), "Comic/page2.PNG");
+        assert!(first_page(&names(&["__MACOSX/page1.jpg", "readme"])).is_none());
+    }
+}
@@ -0,0 +1,227 @@
+/// The path of the package document, from `META-INF/container.xml`.
+pub fn package_path(container: &str) -> Option<String> {
+    tags(container)
+        .find(|(name, _)| *name == "rootfile")
+        .and_then(|(_, attributes)| attribute(&attributes, "full-path"))
+}
+
+/// The path in the archive of the cover image declared by the package document at
+/// `package_path`.
+pub fn cover_path(package: &str, package_path: &str) -> Option<String> {
+    let mut items = Vec::new();
+    let mut cover_id = None;
+    for (name, attributes) in tags(package) {
+        match name {
+            "item" => items.push(attributes),
+            "meta" if attribute(&attributes, "name").as_deref() == Some("cover") => {
+                cover_id = attribute(&attributes, "content");
+            }
+            _ => {}
+        }
+    }
+    let is_image = |item: &&Vec<(&str, String)>| {
+        attribute(item, "media-type").map_or(false, |media_type| media_type.starts_with("image/"))
+    };
+    // EPUB 3 marks the cover with a property, EPUB 2 with a meta element and older books only
+    // name it
+    let cover = items
+        .iter()
+        .find(|item| {
+            attribute(item, "properties").map_or(false, |properties| {
+                properties.split_whitespace().any(|p| p == "cover-image")
+            })
+        })
+        .or_else(|| {
+            let cover_id = cover_id.as_ref()?;
+            items
+                .iter()
+                .filter(is_image)
+                .find(|item| attribute(item, "id").as_ref() == Some(cover_id))
+        })
+        .or_else(|| {
+            items.iter().filter(is_image).find(|item| {
+                ["id", "href"].iter().any(|key| {
+                    attribute(item, key)
+                        .map_or(false, |value| value.to_lowercase().contains("cover"))
+                })
+            })
+        })?;
+    let href = attribute(cover, "href")?;
+    let base = match package_path.rfind('/') {
+        Some(end) => &package_path[..end + 1],
+        None => "",
+    };
+    Some(normalize(&format!("{}{}", base, percent_decode(&href))))
+}
+
+fn attribute(attributes: &[(&str, String)], key: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value.clone())
+}
+
+/// The start tags of an XML document with their attributes, the namespace prefixes are
+/// dropped.
+fn tags(xml: &str) -> impl Iterator<Item = (&str, Vec<(&str, String)>)> {
+    xml.split('<').skip(1).filter_map(|tag| {
+        if tag.starts_with(&['/', '?', '!'][..]) {
+            return None;
+        }
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+        let mut attributes = Vec::new();
+        let mut rest = &tag[name_end..];
+        while let Some(equal) = rest.find('=') {
+            let key = rest[..equal].trim();
+            let value = rest[equal + 1..].trim_start();
+            let quote = match value.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => {
+                    break;
+                }
+            };
+            let end = match value[1..].find(quote) {
+                Some(end) => end + 1,
+                None => {
+                    break;
+                }
+            };
+            attributes.push((local_name(key), unescape(&value[1..end])));
+            rest = &value[end + 1..];
+        }
+        Some((local_name(&tag[..name_end]), attributes))
+    })
+}
+
+fn local_name(name: &str) -> &str {
+    match name.rfind(':') {
+        Some(colon) => &name[colon + 1..],
+        None => name,
+    }
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn percent_decode(href: &str) -> String {
+    let bytes = href.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Resolves the `.` and `..` components of a path in an archive.
+fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(manifest: &str, metadata: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\">\n\
+             <metadata>{}</metadata>\n<manifest>{}</manifest>\n</package>",
+            metadata, manifest
+        )
+    }
+
+    #[test]
+    fn package_paths() {
+        let container = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+        assert_eq!(package_path(container).unwrap(), "OEBPS/content.opf");
+        assert_eq!(
+            package_path("<c:rootfile c:full-path='a &amp; b.opf'/>").unwrap(),
+            "a & b.opf"
+        );
+        assert!(package_path("<container><rootfiles/></container>").is_none());
+    }
+
+    #[test]
+    fn epub3_cover() {
+        let manifest = r#"
+<item id="cover" href="images/cover.jpg" media-type="image/jpeg"/>
+<item id="front" href="images/front%20page.png" media-type="image/png" properties="svg cover-image"/>"#;
+        // The property wins over the name
+        assert_eq!(
+            cover_path(&package(manifest, ""), "OEBPS/content.opf").unwrap(),
+            "OEBPS/images/front page.png"
+        );
+    }
+
+    #[test]
+    fn epub2_cover() {
+        let manifest = r#"
+<item id="cover" href="cover.xhtml" media-type="application/xhtml+xml"/>
+<item id="cover-image" href="../Images/Cover.JPG" media-type="image/jpeg"/>
+<item id="art" href="./art.png" media-type="image/png"/>"#;
+        let metadata = r#"<opf:meta name="cover" content="art"/>"#;
+        assert_eq!(
+            cover_path(&package(manifest, metadata), "OPS/Text/content.opf").unwrap(),
+            "OPS/Text/art.png"
+        );
+        // Without metadata the image named after the cover is taken, not the page
+        assert_eq!(
+            cover_path(&package(manifest, ""), "OPS/Text/content.opf").unwrap(),
+            "OPS/Images/Cover.JPG"
+        );
+        // A meta element that points to a page isn't an image
+        let metadata = r#"<meta name="cover" content="cover"/>"#;
+        assert_eq!(
+            cover_path(&package(manifest, metadata), "content.opf").unwrap(),
+            "Images/Cover.JPG"
+        );
+        let manifest = r#"<item id="page" href="page.png" media-type="image/png"/>"#;
+        assert!(cover_path(&package(manifest, ""), "content.opf").is_none());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(normalize("a/./b/../c//d.png"), "a/c/d.png");
+        assert_eq!(normalize("../../cover.png"), "cover.png");
+        assert_eq!(percent_decode("a%20b%C3%A9.png"), "a bé.png");
+        // Invalid escapes are kept as they are
+        assert_eq!(percent_decode("100%.png"), "100%.png");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+    }
+}
@@ -0,0 +1,92 @@
+use super::video::FrameDecoder;
+use ffmpeg_next as ffmpeg;
+use image::RgbaImage;
+use log::warn;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Once;
+
+static INIT: Once = Once::new();
+
+/// Decodes frames with the FFmpeg libraries.
+pub struct FfmpegDecoder;
+
+impl FrameDecoder for FfmpegDecoder {
+    fn decode_frame(
+        &self,
+        path: &Path,
+        position: f64,
+        size: u32,
+    ) -> Result<Option<RgbaImage>, Box<dyn Error>> {
+        INIT.call_once(|| {
+            if let Err(e) = ffmpeg::init() {
+                warn!("Can't initialize FFmpeg: {}", e);
+            }
+            ffmpeg::util::log::set_level(ffmpeg::util::log::Level::Quiet);
+        });
+        let mut input = ffmpeg::format::input(&path)?;
+        let stream = match input.streams().best(ffmpeg::media::Type::Video) {
+            Some(stream) => stream,
+            None => {
+                return Ok(None);
+            }
+        };
+        let index = stream.index();
+        let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+        let mut decoder = context.decoder().video()?;
+        if decoder.width() == 0 || decoder.height() == 0 {
+            return Ok(None);
+        }
+        // Seeks to the key frame before the position, the duration is in microseconds
+        let duration = input.duration();
+        if duration > 0 {
+            let target = (duration as f64 * position) as i64;
+            if input.seek(target, ..target).is_err() {
+                input.seek(0, ..0)?;
+            }
+        }
+        let scale = size as f64 / decoder.width().max(decoder.height()) as f64;
+        let width = ((decoder.width() as f64 * scale).round() as u32).max(1);
+        let height = ((decoder.height() as f64 * scale).round() as u32).max(1);
+        let mut scaler = ffmpeg::software::scaling::Context::get(
+            decoder.format(),
+            decoder.width(),
+            decoder.height(),
+            ffmpeg::format::Pixel::RGBA,
+            width,
+            height,
+            ffmpeg::software::scaling::Flags::BILINEAR,
+        )?;
+        let mut frame = ffmpeg::frame::Video::empty();
+        let mut decoded = false;
+        for (stream, packet) in input.packets() {
+            if stream.index() != index {
+                continue;
+            }
+            decoder.send_packet(&packet)?;
+            if decoder.receive_frame(&mut frame).is_ok() {
+                decoded = true;
+                break;
+            }
+        }
+        if !decoded {
+            decoder.send_eof()?;
+            if decoder.receive_frame(&mut frame).is_err() {
+                return Ok(None);
+            }
+        }
+        let mut rgba = ffmpeg::frame::Video::empty();
+        scaler.run(&frame, &mut rgba)?;
+        // The rows of the frame can be padded
+        let stride = rgba.stride(0);
+        let data = rgba.data(0);
+        let mut image = RgbaImage::new(width, height);
+        for (y, row) in image.rows_mut().enumerate() {
+            let start = y * stride;
+            for (pixel, color) in row.zip(data[start..start + width as usize * 4].chunks_exact(4)) {
+                pixel.0.copy_from_slice(color);
+            }
+        }
+        Ok(Some(image))
+    }
+}
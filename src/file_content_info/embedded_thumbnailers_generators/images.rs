@@ -1,42 +1,123 @@
-use image::DynamicImage;
+use crate::file_content_info::generators::{mime_types, Cost, ThumbnailGenerator};
+use image::{io::Reader, DynamicImage, ImageFormat};
+use mime::Mime;
 use std::error::Error;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 
-pub fn generate_from_path(path: impl AsRef<Path>) -> Result<Option<DynamicImage>, Box<dyn Error>> {
-    let ext = match path
-        .as_ref()
-        .extension()
-        .and_then(|e| e.to_str().map(|s| s.to_owned()))
-    {
-        Some(ext) => ext,
-        None => {
+/// The decoded formats by media type.
+const FORMATS: &[(&str, ImageFormat)] = &[
+    ("image/png", ImageFormat::Png),
+    ("image/jpeg", ImageFormat::Jpeg),
+    ("image/gif", ImageFormat::Gif),
+    ("image/webp", ImageFormat::WebP),
+    ("image/tiff", ImageFormat::Tiff),
+    ("image/x-portable-anymap", ImageFormat::Pnm),
+    ("image/x-portable-bitmap", ImageFormat::Pnm),
+    ("image/x-portable-graymap", ImageFormat::Pnm),
+    ("image/x-portable-pixmap", ImageFormat::Pnm),
+    ("image/x-portable-arbitrarymap", ImageFormat::Pnm),
+    ("image/x-tga", ImageFormat::Tga),
+    ("image/x-dds", ImageFormat::Dds),
+    ("image/bmp", ImageFormat::Bmp),
+    ("image/x-icon", ImageFormat::Ico),
+    ("image/vnd.microsoft.icon", ImageFormat::Ico),
+    ("image/x-farbfeld", ImageFormat::Farbfeld),
+];
+
+/// Decodes the raster formats supported by the image crate.
+pub struct Images {
+    mime_types: Vec<Mime>,
+}
+
+impl Images {
+    pub fn new() -> Images {
+        let types: Vec<&str> = FORMATS.iter().map(|(mime, _)| *mime).collect();
+        Images {
+            mime_types: mime_types(&types),
+        }
+    }
+}
+
+impl ThumbnailGenerator for Images {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Moderate
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        mime: &Mime,
+        _size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        // The format is recognized by its signature, the media type is only a hint for the
+        // formats without one and the file may be mislabelled
+        let mut reader = Reader::new(BufReader::new(File::open(path)?));
+        if let Some((_, format)) = FORMATS.iter().find(|(m, _)| *m == mime.essence_str()) {
+            reader.set_format(*format);
+        }
+        let reader = reader.with_guessed_format()?;
+        if reader.format().is_none() {
             return Ok(None);
         }
-    };
-    match &ext[..] {
-        "png" | "jpg" | "jpeg" | "gif" | "bmp" | "ico" | "tiff" | "tif" | "webp" | "pnm"
-        | "pbm" | "pgm" | "ppm" | "pam" | "dds" | "tga" | "ff" => {
-            use image::*;
-            let file = BufReader::new(File::open(path)?);
-            let format = match &ext[..] {
-                "png" => ImageFormat::Png,
-                "jpg" | "jpeg" => ImageFormat::Jpeg,
-                "gif" => ImageFormat::Gif,
-                "webp" => ImageFormat::WebP,
-                "tiff" | "tif" => ImageFormat::Tiff,
-                "pnm" | "pbm" | "pgm" | "ppm" | "pam" => ImageFormat::Pnm,
-                "tga" => ImageFormat::Tga,
-                "dds" => ImageFormat::Dds,
-                "bmp" => ImageFormat::Bmp,
-                "ico" => ImageFormat::Ico,
-                "ff" => ImageFormat::Farbfeld,
-                _ => unreachable!(),
-            };
-            let img = load(file, format)?;
-            Ok(Some(img))
+        Ok(Some(reader.decode()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    /// Generates the thumbnail of a fixture with the media type of its extension, as
+    /// `for_path` does.
+    fn generate(name: &str) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        let path = crate::test_fixture("images", name);
+        let mime = mime_guess::from_path(&path).first().unwrap();
+        Images::new().generate(&path, &mime, 48)
+    }
+
+    fn assert_decoded(name: &str) {
+        let image = generate(name).unwrap().unwrap();
+        assert_eq!(image.dimensions(), (8, 6), "{}", name);
+        // The top left pixel is blue, the others red
+        assert!(image.get_pixel(0, 0)[2] > 200, "{}", name);
+        assert!(image.get_pixel(4, 3)[0] > 150, "{}", name);
+    }
+
+    #[test]
+    fn extensions_in_any_case() {
+        assert_decoded("red.png");
+        assert_decoded("RED.PNG");
+        assert_decoded("photo.JPG");
+    }
+
+    #[test]
+    fn mislabelled_files() {
+        assert_decoded("png-named.jpg");
+        assert_decoded("jpeg-named.png");
+        assert_decoded("gif-named.bmp");
+    }
+
+    #[test]
+    fn formats_without_signature() {
+        assert_decoded("red.tga");
+    }
+
+    #[test]
+    fn invalid_files() {
+        for name in &[
+            "truncated.png",
+            "truncated.jpg",
+            "empty.png",
+            "text-named.png",
+        ] {
+            assert!(generate(name).is_err(), "{}", name);
         }
-        _ => Ok(None),
     }
 }
@@ -0,0 +1,271 @@
+use crate::file_content_info::generators::{mime_types, Cost, ThumbnailGenerator};
+use image::codecs::jpeg::JpegDecoder;
+use image::{DynamicImage, GenericImageView, ImageDecoder};
+use mime::Mime;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+/// How much the aspect ratios of the embedded thumbnail and of the photo can differ, beyond
+/// that the thumbnail was padded with borders.
+const ASPECT_TOLERANCE: f32 = 0.02;
+const ORIENTATION: u16 = 0x0112;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+
+/// Decodes JPEG photos cheaply: the thumbnail embedded in their EXIF metadata is used when
+/// it's large enough, otherwise the photo is downscaled while decoding. The EXIF orientation
+/// is applied.
+pub struct Jpeg {
+    mime_types: Vec<Mime>,
+}
+
+impl Jpeg {
+    pub fn new() -> Jpeg {
+        Jpeg {
+            mime_types: mime_types(&["image/jpeg"]),
+        }
+    }
+}
+
+impl ThumbnailGenerator for Jpeg {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Cheap
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        _mime: &Mime,
+        size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        let mut file = BufReader::new(File::open(path)?);
+        // Mislabelled files are left to the generators of their real format
+        if !file.fill_buf()?.starts_with(&[0xFF, 0xD8]) {
+            return Ok(None);
+        }
+        let exif = read_exif(&mut file)?;
+        let exif = exif
+            .as_deref()
+            .and_then(|tiff| Exif::parse(tiff).map(|e| (tiff, e)));
+        file.seek(SeekFrom::Start(0))?;
+        let mut decoder = JpegDecoder::new(file)?;
+        let embedded = exif.as_ref().and_then(|(tiff, exif)| {
+            let thumbnail = image::load_from_memory(&tiff[exif.thumbnail.clone()?]).ok()?;
+            let (width, height) = decoder.dimensions();
+            let aspect = |width: u32, height: u32| width as f32 / height.max(1) as f32;
+            let photo_aspect = aspect(width, height);
+            let difference = (aspect(thumbnail.width(), thumbnail.height()) - photo_aspect).abs();
+            let fits = thumbnail.width().max(thumbnail.height()) >= size
+                && difference <= photo_aspect * ASPECT_TOLERANCE;
+            Some(thumbnail).filter(|_| fits)
+        });
+        let image = match embedded {
+            Some(image) => image,
+            None => {
+                let size = size.min(u16::MAX as u32) as u16;
+                decoder.scale(size, size)?;
+                DynamicImage::from_decoder(decoder)?
+            }
+        };
+        let orientation = exif.map_or(1, |(_, exif)| exif.orientation);
+        Ok(Some(orient(image, orientation)))
+    }
+}
+
+/// Reads the TIFF structure of the EXIF segment, which precedes the image data.
+fn read_exif(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut marker = [0; 2];
+    reader.read_exact(&mut marker)?;
+    loop {
+        reader.read_exact(&mut marker)?;
+        if marker[0] != 0xFF {
+            return Ok(None);
+        }
+        match marker[1] {
+            // The start of the image data or its end
+            0xDA | 0xD9 => {
+                return Ok(None);
+            }
+            // Markers without segment
+            0x01 | 0xD0..=0xD8 | 0xFF => {
+                continue;
+            }
+            _ => {}
+        }
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        let len = (u16::from_be_bytes(len) as usize).saturating_sub(2);
+        let mut segment = vec![0; len];
+        reader.read_exact(&mut segment)?;
+        if marker[1] == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            segment.drain(..6);
+            return Ok(Some(segment));
+        }
+    }
+}
+
+/// What's used of the EXIF metadata.
+struct Exif {
+    orientation: u16,
+    /// Where the embedded JPEG thumbnail is in the TIFF structure.
+    thumbnail: Option<Range<usize>>,
+}
+
+impl Exif {
+    fn parse(tiff: &[u8]) -> Option<Exif> {
+        let big_endian = match tiff.get(..2)? {
+            b"MM" => true,
+            b"II" => false,
+            _ => {
+                return None;
+            }
+        };
+        let tiff = Tiff {
+            data: tiff,
+            big_endian,
+        };
+        if tiff.u16_at(2)? != 42 {
+            return None;
+        }
+        let first_ifd = tiff.u32_at(4)? as usize;
+        let (entries, next_ifd) = tiff.ifd(first_ifd)?;
+        let orientation = entries
+            .iter()
+            .find(|(tag, _)| *tag == ORIENTATION)
+            .and_then(|(_, entry)| tiff.u16_at(entry + 8))
+            .filter(|orientation| (1..=8).contains(orientation))
+            .unwrap_or(1);
+        // The second IFD describes the thumbnail
+        let thumbnail = tiff.ifd(next_ifd as usize).and_then(|(entries, _)| {
+            let value = |tag| {
+                let (_, entry) = entries.iter().find(|(t, _)| *t == tag)?;
+                tiff.u32_at(entry + 8).map(|value| value as usize)
+            };
+            let start = value(THUMBNAIL_OFFSET)?;
+            let end = start.checked_add(value(THUMBNAIL_LENGTH)?)?;
+            Some(start..end).filter(|range| range.start > 0 && range.end <= tiff.data.len())
+        });
+        Some(Exif {
+            orientation,
+            thumbnail,
+        })
+    }
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let bytes = [*self.data.get(at)?, *self.data.get(at + 1)?];
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        let high = self.u16_at(at)? as u32;
+        let low = self.u16_at(at + 2)? as u32;
+        Some(if self.big_endian {
+            (high << 16) | low
+        } else {
+            (low << 16) | high
+        })
+    }
+
+    /// The tags of the entries of an image file directory with their position, and the offset
+    /// of the next directory.
+    fn ifd(&self, at: usize) -> Option<(Vec<(u16, usize)>, u32)> {
+        if at == 0 {
+            return None;
+        }
+        let count = self.u16_at(at)? as usize;
+        let entries = (0..count)
+            .map(|i| at + 2 + i * 12)
+            .map(|entry| Some((self.u16_at(entry)?, entry)))
+            .collect::<Option<Vec<_>>>()?;
+        let next = self.u32_at(at + 2 + count * 12).unwrap_or(0);
+        Some((entries, next))
+    }
+}
+
+/// Turns an image stored with the EXIF `orientation` upright.
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(name: &str, size: u32) -> Option<DynamicImage> {
+        let path = crate::test_fixture("images", name);
+        Jpeg::new()
+            .generate(&path, &mime::IMAGE_JPEG, size)
+            .unwrap()
+    }
+
+    /// Whether the photo was decoded rather than its embedded thumbnail, the photo is red
+    /// with a blue top left corner and the thumbnails are green.
+    fn is_photo(image: &DynamicImage) -> bool {
+        let (width, height) = image.dimensions();
+        image.get_pixel(width / 2, height / 2)[0] > 200
+    }
+
+    #[test]
+    fn embedded_thumbnails() {
+        let image = generate("exif-thumbnail.jpg", 48).unwrap();
+        assert_eq!(image.dimensions(), (64, 48));
+        assert!(!is_photo(&image));
+    }
+
+    #[test]
+    fn downscaled_decoding() {
+        // The embedded thumbnail is too small
+        let image = generate("exif-thumbnail.jpg", 90).unwrap();
+        assert_eq!(image.dimensions(), (128, 96));
+        assert!(is_photo(&image));
+        assert!(image.get_pixel(0, 0)[2] > 200);
+        // The embedded thumbnail has borders
+        let image = generate("exif-letterboxed.jpg", 48).unwrap();
+        assert_eq!(image.dimensions(), (64, 48));
+        assert!(is_photo(&image));
+    }
+
+    #[test]
+    fn orientation() {
+        let image = generate("exif-rotated.jpg", 48).unwrap();
+        assert_eq!(image.dimensions(), (48, 64));
+        assert!(!is_photo(&image));
+        // The top left corner is turned to the top right
+        let image = generate("exif-rotated.jpg", 90).unwrap();
+        assert_eq!(image.dimensions(), (96, 128));
+        assert!(image.get_pixel(95, 0)[2] > 200);
+        assert!(image.get_pixel(0, 0)[0] > 200);
+    }
+
+    #[test]
+    fn mislabelled_files() {
+        assert!(generate("png-named.jpg", 48).is_none());
+    }
+}
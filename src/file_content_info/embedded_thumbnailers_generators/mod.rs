@@ -1,12 +1,32 @@
-use image::DynamicImage;
-use std::error::Error;
-use std::path::Path;
+use super::generators::ThumbnailGenerator;
 
+mod audio;
+#[cfg(feature = "document-thumbnails")]
+mod documents;
+#[cfg(feature = "video-thumbnails")]
+mod ffmpeg;
 mod images;
+mod jpeg;
+mod svg;
+mod text;
+#[cfg(feature = "video-thumbnails")]
+mod video;
 
-pub fn generate_from_path(path: impl AsRef<Path>) -> Result<Option<DynamicImage>, Box<dyn Error>> {
-    if let Some(image) = images::generate_from_path(path)? {
-        return Ok(Some(image));
-    }
-    Ok(None)
+/// The built-in generators, with those whose backends are enabled.
+pub fn generators() -> Vec<Box<dyn ThumbnailGenerator>> {
+    #[allow(unused_mut)]
+    let mut generators: Vec<Box<dyn ThumbnailGenerator>> = vec![
+        Box::new(images::Images::new()),
+        Box::new(jpeg::Jpeg::new()),
+        Box::new(audio::AudioCovers::new()),
+        Box::new(svg::Svg::new()),
+        Box::new(text::Text::new()),
+    ];
+    #[cfg(feature = "video-thumbnails")]
+    generators.push(Box::new(video::VideoThumbnailer::new(
+        ffmpeg::FfmpegDecoder,
+    )));
+    #[cfg(feature = "document-thumbnails")]
+    generators.push(Box::new(documents::DocumentThumbnailer::new()));
+    generators
 }
@@ -0,0 +1,236 @@
+use crate::file_content_info::generators::{mime_types, Cost, ThumbnailGenerator};
+use flate2::read::GzDecoder;
+use image::{DynamicImage, Rgba, RgbaImage};
+use lazy_static::lazy_static;
+use log::debug;
+use mime::Mime;
+use resvg::{tiny_skia, usvg};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Documents larger than this aren't rendered, compressed documents once expanded too.
+const SIZE_LIMIT: u64 = 4 * 1024 * 1024;
+/// The most elements a document can have, both in its source and once parsed.
+const ELEMENTS_LIMIT: usize = 20_000;
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+lazy_static! {
+    /// The system fonts, loaded once for the texts of every document.
+    static ref FONTS: Arc<usvg::fontdb::Database> = {
+        let mut fonts = usvg::fontdb::Database::new();
+        fonts.load_system_fonts();
+        Arc::new(fonts)
+    };
+}
+
+/// Renders SVG documents, compressed or not, fitting in `size` while keeping their aspect ratio.
+/// Too complex documents aren't rendered and external resources are never loaded.
+pub struct Svg {
+    mime_types: Vec<Mime>,
+}
+
+impl Svg {
+    pub fn new() -> Svg {
+        Svg {
+            mime_types: mime_types(&["image/svg+xml", "image/svg+xml-compressed"]),
+        }
+    }
+}
+
+impl ThumbnailGenerator for Svg {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Moderate
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        _mime: &Mime,
+        size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        render(path, size)
+    }
+}
+
+fn render(path: &Path, size: u32) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    let data = match read_limited(File::open(path)?)? {
+        // Compressed documents could expand without limit, they're read up to it
+        Some(data) if data.starts_with(&GZIP_MAGIC) => read_limited(GzDecoder::new(&data[..]))?,
+        data => data,
+    };
+    match data {
+        Some(data) => render_data(&data, size),
+        None => {
+            debug!("Not rendering {}, it's too large", path.display());
+            Ok(None)
+        }
+    }
+}
+
+/// Reads the whole `reader`, `None` if it has more than `SIZE_LIMIT` bytes.
+fn read_limited(reader: impl Read) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut data = Vec::new();
+    reader.take(SIZE_LIMIT + 1).read_to_end(&mut data)?;
+    Ok(Some(data).filter(|data| data.len() as u64 <= SIZE_LIMIT))
+}
+
+fn render_data(data: &[u8], size: u32) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    if data.iter().filter(|b| **b == b'<').count() > ELEMENTS_LIMIT {
+        debug!("Not rendering a document with too many elements");
+        return Ok(None);
+    }
+    let options = usvg::Options {
+        resources_dir: None,
+        fontdb: FONTS.clone(),
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+            // Neither files nor URLs are loaded, only the embedded images
+            resolve_string: Box::new(|_, _| None),
+        },
+        ..usvg::Options::default()
+    };
+    let tree = usvg::Tree::from_data(data, &options)?;
+    if count_nodes(tree.root()) > ELEMENTS_LIMIT {
+        debug!("Not rendering a document with too many elements");
+        return Ok(None);
+    }
+    let document_size = tree.size();
+    let scale = size as f32 / document_size.width().max(document_size.height());
+    let width = (document_size.width() * scale).round().max(1.0) as u32;
+    let height = (document_size.height() * scale).round().max(1.0) as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("Invalid document size")?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    // The pixels of the pixmap have their alpha premultiplied
+    let mut image = RgbaImage::new(width, height);
+    for (pixel, color) in image.pixels_mut().zip(pixmap.pixels()) {
+        let color = color.demultiply();
+        *pixel = Rgba([color.red(), color.green(), color.blue(), color.alpha()]);
+    }
+    Ok(Some(DynamicImage::ImageRgba8(image)))
+}
+
+fn count_nodes(group: &usvg::Group) -> usize {
+    group
+        .children()
+        .iter()
+        .map(|node| match node {
+            usvg::Node::Group(group) => 1 + count_nodes(group),
+            _ => 1,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    fn svg(width: u32, height: u32, content: &str) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+             xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+             width=\"{}\" height=\"{}\">{}</svg>",
+            width, height, content
+        )
+    }
+
+    fn render_svg(svg: &str, size: u32) -> Option<RgbaImage> {
+        render_data(svg.as_bytes(), size)
+            .unwrap()
+            .map(|image| image.into_rgba8())
+    }
+
+    fn render_file(data: &[u8]) -> Option<RgbaImage> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        render(file.path(), 32)
+            .unwrap()
+            .map(|image| image.into_rgba8())
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        let halves = "<rect width=\"50%\" height=\"100%\" fill=\"red\"/>\
+                      <rect x=\"50%\" width=\"50%\" height=\"100%\" fill=\"blue\"/>";
+        let image = render_svg(&svg(200, 100, halves), 64).unwrap();
+        assert_eq!(image.dimensions(), (64, 32));
+        assert_eq!(*image.get_pixel(8, 16), RED);
+        assert_eq!(*image.get_pixel(56, 16), BLUE);
+        assert_eq!(
+            render_svg(&svg(50, 100, halves), 64).unwrap().dimensions(),
+            (32, 64)
+        );
+        // Small documents are scaled up
+        let image = render_svg(&svg(10, 10, halves), 128).unwrap();
+        assert_eq!(image.dimensions(), (128, 128));
+        assert_eq!(*image.get_pixel(120, 64), BLUE);
+        assert_eq!(
+            render_svg(&svg(1000, 1, halves), 64).unwrap().dimensions(),
+            (64, 1)
+        );
+    }
+
+    #[test]
+    fn external_images() {
+        let png = crate::test_fixture("images", "red.png");
+        for href in &[
+            format!("{}", png.display()),
+            format!("file://{}", png.display()),
+            "https://example.com/red.png".to_owned(),
+        ] {
+            let image = format!(
+                "<image xlink:href=\"{}\" width=\"100%\" height=\"100%\"/>",
+                href
+            );
+            let image = render_svg(&svg(16, 16, &image), 16).unwrap();
+            assert!(image.pixels().all(|pixel| pixel[3] == 0), "{}", href);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let rect = "<rect width=\"1\" height=\"1\"/>";
+        // The tags of the svg element count too
+        assert!(render_svg(&svg(16, 16, &rect.repeat(ELEMENTS_LIMIT - 2)), 16).is_some());
+        assert!(render_svg(&svg(16, 16, &rect.repeat(ELEMENTS_LIMIT - 1)), 16).is_none());
+        // Few elements in the source can make many once the references are followed
+        let group = format!("<g id=\"g\">{}</g>", rect.repeat(200));
+        let uses = "<use xlink:href=\"#g\"/>".repeat(200);
+        let defs = format!("<defs>{}</defs>{}", group, uses);
+        assert!(render_svg(&svg(16, 16, &defs), 16).is_none());
+
+        let document = svg(16, 16, "<rect width=\"16\" height=\"16\" fill=\"red\"/>");
+        assert!(render_file(document.as_bytes()).is_some());
+        let padding = format!("<!--{}-->", " ".repeat(SIZE_LIMIT as usize));
+        let large = svg(16, 16, &padding);
+        assert!(render_file(large.as_bytes()).is_none());
+
+        // Compressed documents are limited once expanded
+        let image = render_file(&gzip(document.as_bytes())).unwrap();
+        assert_eq!(*image.get_pixel(16, 16), RED);
+        let compressed = gzip(large.as_bytes());
+        assert!((compressed.len() as u64) < SIZE_LIMIT);
+        assert!(render_file(&compressed).is_none());
+    }
+}
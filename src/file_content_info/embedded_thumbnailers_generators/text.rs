@@ -0,0 +1,327 @@
+use crate::file_content_info::generators::{mime_types, Cost, ThumbnailGenerator};
+use image::{DynamicImage, Rgba, RgbaImage};
+use mime::Mime;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// How much of the file is read, enough to fill the page.
+const READ_LIMIT: u64 = 16 * 1024;
+const PAGE_WIDTH: u32 = 192;
+const PAGE_HEIGHT: u32 = 256;
+const MARGIN: u32 = 8;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 8;
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+const TAB_WIDTH: usize = 4;
+const PAPER: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const BORDER: Rgba<u8> = Rgba([192, 192, 192, 255]);
+const INK: Rgba<u8> = Rgba([48, 48, 48, 255]);
+
+/// The application types that are text, besides `text/*`.
+const TEXT_SUBTYPES: &[&str] = &[
+    "json",
+    "xml",
+    "javascript",
+    "x-javascript",
+    "ecmascript",
+    "x-sh",
+    "x-shellscript",
+    "x-csh",
+    "x-perl",
+    "x-python",
+    "x-ruby",
+    "x-php",
+    "x-httpd-php",
+    "toml",
+    "x-toml",
+    "x-yaml",
+    "yaml",
+    "sql",
+    "x-tex",
+    "x-latex",
+    "rtf",
+    "x-desktop",
+    "x-subrip",
+];
+
+/// Renders the first lines of text files in a page with a monospace font, binary files are
+/// refused.
+pub struct Text {
+    mime_types: Vec<Mime>,
+}
+
+impl Text {
+    pub fn new() -> Text {
+        let mut types = vec!["text/*".to_owned()];
+        types.extend(
+            TEXT_SUBTYPES
+                .iter()
+                .map(|subtype| format!("application/{}", subtype)),
+        );
+        let types: Vec<&str> = types.iter().map(|mime| mime.as_str()).collect();
+        Text {
+            mime_types: mime_types(&types),
+        }
+    }
+}
+
+impl ThumbnailGenerator for Text {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Cheap
+    }
+
+    /// The XML and JSON based formats are text too.
+    fn supports(&self, mime: &Mime) -> bool {
+        is_text(mime)
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        _mime: &Mime,
+        _size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        render_file(path)
+    }
+}
+
+fn render_file(path: &Path) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    let mut content = Vec::new();
+    File::open(path)?
+        .take(READ_LIMIT)
+        .read_to_end(&mut content)?;
+    let text = match decode(&content) {
+        Some(text) => text,
+        None => {
+            return Ok(None);
+        }
+    };
+    Ok(Some(DynamicImage::ImageRgba8(render(&text))))
+}
+
+fn is_text(mime: &Mime) -> bool {
+    if mime.type_() == mime::TEXT {
+        return true;
+    }
+    mime.type_() == mime::APPLICATION
+        && (TEXT_SUBTYPES.contains(&mime.subtype().as_str())
+            || mime
+                .suffix()
+                .map_or(false, |suffix| suffix == mime::XML || suffix == mime::JSON))
+}
+
+/// Decodes the start of a text file: UTF-8 and UTF-16 are recognized by their byte order mark,
+/// other content is read as UTF-8 or else as Latin-1. `None` is returned for binary content.
+fn decode(content: &[u8]) -> Option<String> {
+    let text = if let Some(content) = content.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        decode_utf8(content)
+    } else if let Some(content) = content.strip_prefix(&[0xFF, 0xFE]) {
+        decode_utf16(content, u16::from_le_bytes)
+    } else if let Some(content) = content.strip_prefix(&[0xFE, 0xFF]) {
+        decode_utf16(content, u16::from_be_bytes)
+    } else if content.contains(&0) {
+        return None;
+    } else if std::str::from_utf8(content).is_ok() || is_truncated_utf8(content) {
+        decode_utf8(content)
+    } else {
+        content.iter().map(|b| *b as char).collect()
+    };
+    let controls = text
+        .chars()
+        .filter(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+        .count();
+    // A few stray control characters are tolerated
+    if controls * 20 > text.chars().count() {
+        return None;
+    }
+    Some(text)
+}
+
+/// Whether `content` is valid UTF-8 up to a sequence cut by the read limit.
+fn is_truncated_utf8(content: &[u8]) -> bool {
+    match std::str::from_utf8(content) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+fn decode_utf8(content: &[u8]) -> String {
+    match std::str::from_utf8(content) {
+        Ok(text) => text.to_owned(),
+        Err(e) if e.error_len().is_none() => {
+            String::from_utf8_lossy(&content[..e.valid_up_to()]).into_owned()
+        }
+        Err(_) => String::from_utf8_lossy(content).into_owned(),
+    }
+}
+
+fn decode_utf16(content: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let units = content.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    std::char::decode_utf16(units)
+        .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn render(text: &str) -> RgbaImage {
+    let mut page = RgbaImage::from_pixel(PAGE_WIDTH, PAGE_HEIGHT, PAPER);
+    for x in 0..PAGE_WIDTH {
+        page.put_pixel(x, 0, BORDER);
+        page.put_pixel(x, PAGE_HEIGHT - 1, BORDER);
+    }
+    for y in 0..PAGE_HEIGHT {
+        page.put_pixel(0, y, BORDER);
+        page.put_pixel(PAGE_WIDTH - 1, y, BORDER);
+    }
+    let columns = ((PAGE_WIDTH - 2 * MARGIN) / CELL_WIDTH) as usize;
+    let rows = ((PAGE_HEIGHT - 2 * MARGIN) / CELL_HEIGHT) as usize;
+    let lines = text
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .take(rows);
+    for (row, line) in lines.enumerate() {
+        let mut column = 0;
+        for c in line.chars() {
+            if column >= columns {
+                break;
+            }
+            if c == '\t' {
+                column += TAB_WIDTH - column % TAB_WIDTH;
+                continue;
+            }
+            if c.is_control() {
+                continue;
+            }
+            let x = MARGIN + column as u32 * CELL_WIDTH;
+            let y = MARGIN + row as u32 * CELL_HEIGHT;
+            draw_glyph(&mut page, x, y, glyph(c));
+            column += 1;
+        }
+    }
+    page
+}
+
+fn draw_glyph(page: &mut RgbaImage, x: u32, y: u32, glyph: [u8; 5]) {
+    for (dx, column) in glyph.iter().enumerate() {
+        for dy in 0..GLYPH_HEIGHT {
+            if column & (1 << dy) != 0 {
+                page.put_pixel(x + dx as u32, y + dy, INK);
+            }
+        }
+    }
+}
+
+/// The columns of the glyph of `c`, the least significant bit is the top row. Characters
+/// outside of ASCII are drawn as a box.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        ' '..='~' => FONT[c as usize - ' ' as usize],
+        _ => [0x7F, 0x41, 0x41, 0x41, 0x7F],
+    }
+}
+
+/// A 5x8 font for the printable ASCII characters.
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '\''
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x14, 0x08, 0x3E, 0x08, 0x14], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x80, 0x80, 0x80, 0x80, 0x80], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x18, 0xA4, 0xA4, 0xA4, 0x7C], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x40, 0x80, 0x84, 0x7D, 0x00], // 'j'
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0xFC, 0x24, 0x24, 0x24, 0x18], // 'p'
+    [0x18, 0x24, 0x24, 0x28, 0xFC], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x1C, 0xA0, 0xA0, 0xA0, 0x7C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
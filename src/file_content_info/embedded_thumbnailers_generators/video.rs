@@ -0,0 +1,160 @@
+use crate::file_content_info::generators::{mime_types, Cost, ThumbnailGenerator};
+use image::{DynamicImage, Rgba, RgbaImage};
+use mime::Mime;
+use std::error::Error;
+use std::path::Path;
+
+/// Where the frame is taken, as a fraction of the duration, to skip the opening black frames
+/// and titles.
+const FRAME_POSITION: f64 = 0.1;
+const FILM: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const PERFORATION: Rgba<u8> = Rgba([224, 224, 224, 255]);
+
+/// Decodes single frames of videos.
+pub trait FrameDecoder: Send + Sync {
+    /// Decodes the frame at `position`, a fraction of the duration, scaled to fit in `size`.
+    fn decode_frame(
+        &self,
+        path: &Path,
+        position: f64,
+        size: u32,
+    ) -> Result<Option<RgbaImage>, Box<dyn Error>>;
+}
+
+/// Shows a frame of videos framed by a film strip.
+pub struct VideoThumbnailer<D> {
+    decoder: D,
+    mime_types: Vec<Mime>,
+}
+
+impl<D: FrameDecoder> VideoThumbnailer<D> {
+    pub fn new(decoder: D) -> VideoThumbnailer<D> {
+        VideoThumbnailer {
+            decoder,
+            mime_types: mime_types(&["video/*"]),
+        }
+    }
+}
+
+impl<D: FrameDecoder> ThumbnailGenerator for VideoThumbnailer<D> {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Expensive
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        _mime: &Mime,
+        size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        let mut frame = match self.decoder.decode_frame(path, FRAME_POSITION, size)? {
+            Some(frame) => frame,
+            None => {
+                return Ok(None);
+            }
+        };
+        add_film_strip(&mut frame);
+        Ok(Some(DynamicImage::ImageRgba8(frame)))
+    }
+}
+
+/// Draws perforated film bands over the left and right edges of `frame`.
+fn add_film_strip(frame: &mut RgbaImage) {
+    let (width, height) = frame.dimensions();
+    let band = (width / 10).max(2);
+    if band * 2 >= width {
+        return;
+    }
+    let hole = (band / 2).max(1);
+    let pitch = hole * 2;
+    let hole_start = (band - hole) / 2;
+    for y in 0..height {
+        let in_hole_row = (y + hole / 2) % pitch < hole;
+        for x in (0..band).chain(width - band..width) {
+            let x_in_band = if x < band { x } else { x - (width - band) };
+            let in_hole = in_hole_row && x_in_band >= hole_start && x_in_band < hole_start + hole;
+            frame.put_pixel(x, y, if in_hole { PERFORATION } else { FILM });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PICTURE: Rgba<u8> = Rgba([200, 40, 40, 255]);
+
+    /// Decodes frames of `width` by `height` filled with `PICTURE`, or no frame without them.
+    struct FakeDecoder {
+        dimensions: Option<(u32, u32)>,
+    }
+
+    impl FrameDecoder for FakeDecoder {
+        fn decode_frame(
+            &self,
+            _path: &Path,
+            position: f64,
+            size: u32,
+        ) -> Result<Option<RgbaImage>, Box<dyn Error>> {
+            assert_eq!(position, FRAME_POSITION);
+            assert_eq!(size, 128);
+            Ok(self
+                .dimensions
+                .map(|(width, height)| RgbaImage::from_pixel(width, height, PICTURE)))
+        }
+    }
+
+    fn generate(dimensions: Option<(u32, u32)>) -> Option<RgbaImage> {
+        VideoThumbnailer::new(FakeDecoder { dimensions })
+            .generate(
+                Path::new("video.mkv"),
+                &"video/x-matroska".parse().unwrap(),
+                128,
+            )
+            .unwrap()
+            .map(|frame| frame.into_rgba8())
+    }
+
+    #[test]
+    fn film_strip() {
+        let frame = generate(Some((128, 72))).unwrap();
+        assert_eq!(frame.dimensions(), (128, 72));
+        let band = 12;
+        for (x, y, pixel) in frame.enumerate_pixels() {
+            if x < band || x >= 128 - band {
+                assert!(*pixel == FILM || *pixel == PERFORATION, "{} {}", x, y);
+            } else {
+                assert_eq!(*pixel, PICTURE);
+            }
+        }
+        // Both bands are perforated along the whole height
+        for x in &[band / 2, 128 - band / 2] {
+            let holes = (0..72)
+                .filter(|y| *frame.get_pixel(*x, *y) == PERFORATION)
+                .count();
+            assert!(holes > 72 / 3 && holes < 72 * 2 / 3, "{} holes", holes);
+        }
+        assert_eq!(*frame.get_pixel(0, 0), FILM);
+    }
+
+    #[test]
+    fn narrow_frames() {
+        for dimensions in &[(4, 72), (3, 10), (1, 1)] {
+            let frame = generate(Some(*dimensions)).unwrap();
+            assert!(frame.pixels().all(|pixel| *pixel == PICTURE));
+        }
+        // The thinnest bands are two pixels wide
+        let frame = generate(Some((5, 10))).unwrap();
+        assert_eq!(*frame.get_pixel(2, 0), PICTURE);
+        assert_ne!(*frame.get_pixel(1, 0), PICTURE);
+    }
+
+    #[test]
+    fn without_frame() {
+        assert!(generate(None).is_none());
+    }
+}
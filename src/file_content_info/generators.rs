@@ -0,0 +1,216 @@
+use super::embedded_thumbnailers_generators;
+use image::DynamicImage;
+use lazy_static::lazy_static;
+use mime::Mime;
+use parking_lot::RwLock;
+use std::{cmp::Reverse, error::Error, path::Path};
+
+lazy_static! {
+    /// Every thumbnail generator, the built-in ones are registered from the start.
+    pub static ref GENERATORS: RwLock<Registry> = {
+        let mut registry = Registry::default();
+        #[cfg(target_os = "linux")]
+        registry.register(Box::new(super::linux::SystemThumbnailers::new()));
+        for generator in embedded_thumbnailers_generators::generators() {
+            registry.register(generator);
+        }
+        RwLock::new(registry)
+    };
+}
+
+/// How expensive generating a thumbnail is, the cheapest generators are tried first among
+/// those of the same priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cost {
+    /// Reads a small part of the file, like embedded artwork.
+    Cheap,
+    /// Decodes the whole file.
+    Moderate,
+    /// Runs an external program or a heavy decoder.
+    Expensive,
+}
+
+/// Generates thumbnails of some kinds of files.
+pub trait ThumbnailGenerator: Send + Sync {
+    /// The media types handled, `type/*` matches every subtype of a type.
+    fn mime_types(&self) -> &[Mime];
+
+    fn cost(&self) -> Cost;
+
+    /// Generators of higher priority are tried first.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Whether files of this media type are handled.
+    fn supports(&self, mime: &Mime) -> bool {
+        self.mime_types().iter().any(|pattern| {
+            pattern.type_() == mime.type_()
+                && (pattern.subtype() == mime::STAR || pattern.subtype() == mime.subtype())
+        })
+    }
+
+    /// Generates the thumbnail of `path` fitting in `size`, `None` when the file has none.
+    fn generate(
+        &self,
+        path: &Path,
+        mime: &Mime,
+        size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>>;
+}
+
+/// The thumbnail generators, ordered by priority and cost.
+#[derive(Default)]
+pub struct Registry {
+    generators: Vec<Box<dyn ThumbnailGenerator>>,
+}
+
+impl Registry {
+    /// Adds a generator after those of the same priority and cost.
+    pub fn register(&mut self, generator: Box<dyn ThumbnailGenerator>) {
+        let index = self
+            .generators
+            .iter()
+            .position(|other| rank(&**other) > rank(&*generator))
+            .unwrap_or(self.generators.len());
+        self.generators.insert(index, generator);
+    }
+
+    /// Tries the generators of `mime` in order until one makes a thumbnail, the first error
+    /// is returned when none does.
+    pub fn generate(
+        &self,
+        path: &Path,
+        mime: &Mime,
+        size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        let mut failure = None;
+        for generator in self.generators.iter().filter(|g| g.supports(mime)) {
+            match generator.generate(path, mime, size) {
+                Ok(Some(thumbnail)) => {
+                    return Ok(Some(thumbnail));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
+
+fn rank(generator: &dyn ThumbnailGenerator) -> (Reverse<i32>, Cost) {
+    (Reverse(generator.priority()), generator.cost())
+}
+
+/// Parses a list of media types, for the declarations of the generators.
+pub fn mime_types(types: &[&str]) -> Vec<Mime> {
+    types.iter().filter_map(|mime| mime.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    struct Fake {
+        name: u8,
+        mime_types: Vec<Mime>,
+        cost: Cost,
+        priority: i32,
+        result: Result<bool, &'static str>,
+    }
+
+    fn fake(name: u8, types: &[&str], cost: Cost, priority: i32) -> Box<Fake> {
+        Box::new(Fake {
+            name,
+            mime_types: mime_types(types),
+            cost,
+            priority,
+            result: Ok(true),
+        })
+    }
+
+    impl ThumbnailGenerator for Fake {
+        fn mime_types(&self) -> &[Mime] {
+            &self.mime_types
+        }
+
+        fn cost(&self) -> Cost {
+            self.cost
+        }
+
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        fn generate(
+            &self,
+            _path: &Path,
+            _mime: &Mime,
+            _size: u32,
+        ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+            match self.result {
+                // The generator is told apart by the width of its thumbnail
+                Ok(true) => Ok(Some(DynamicImage::ImageRgba8(RgbaImage::new(
+                    self.name as u32,
+                    1,
+                )))),
+                Ok(false) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+
+    fn generated_by(registry: &Registry, mime: &str) -> Option<u32> {
+        registry
+            .generate(Path::new("file"), &mime.parse().unwrap(), 48)
+            .unwrap()
+            .map(|thumbnail| thumbnail.width())
+    }
+
+    #[test]
+    fn dispatches_on_mime_types() {
+        let mut registry = Registry::default();
+        registry.register(fake(1, &["image/png"], Cost::Moderate, 0));
+        registry.register(fake(2, &["audio/*"], Cost::Cheap, 0));
+        assert_eq!(generated_by(&registry, "image/png"), Some(1));
+        assert_eq!(generated_by(&registry, "audio/flac"), Some(2));
+        assert_eq!(generated_by(&registry, "image/jpeg"), None);
+        assert_eq!(generated_by(&registry, "video/mp4"), None);
+    }
+
+    #[test]
+    fn orders_by_priority_then_cost() {
+        let mut registry = Registry::default();
+        registry.register(fake(1, &["image/*"], Cost::Expensive, 0));
+        registry.register(fake(2, &["image/*"], Cost::Cheap, 0));
+        assert_eq!(generated_by(&registry, "image/png"), Some(2));
+        registry.register(fake(3, &["image/*"], Cost::Expensive, 1));
+        assert_eq!(generated_by(&registry, "image/png"), Some(3));
+        // The first registered wins among equals
+        registry.register(fake(4, &["image/*"], Cost::Expensive, 1));
+        assert_eq!(generated_by(&registry, "image/png"), Some(3));
+    }
+
+    #[test]
+    fn falls_back_after_failures() {
+        let mut registry = Registry::default();
+        let mut failing = fake(1, &["image/png"], Cost::Cheap, 1);
+        failing.result = Err("failed");
+        let mut empty = fake(2, &["image/png"], Cost::Cheap, 0);
+        empty.result = Ok(false);
+        registry.register(failing);
+        registry.register(empty);
+        let error = registry
+            .generate(Path::new("file"), &"image/png".parse().unwrap(), 48)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "failed");
+        registry.register(fake(3, &["image/png"], Cost::Expensive, 0));
+        assert_eq!(generated_by(&registry, "image/png"), Some(3));
+    }
+}
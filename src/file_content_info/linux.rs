@@ -1,69 +1,119 @@
+use super::generators::{Cost, ThumbnailGenerator};
+use super::temp_files::TempFile;
+use super::ContentInfoError;
+use crate::desktop_entry::{split_exec, DesktopEntry};
+use crate::uri::file_uri;
+use crate::xdg::{data_dirs, find_executable};
 use image::DynamicImage;
 use lazy_static::lazy_static;
-use log::error;
+use log::{debug, error, warn};
 use mime::Mime;
+use parking_lot::Mutex;
 use std::{
-    collections::HashMap,
-    env::temp_dir,
+    collections::{HashMap, HashSet},
+    env,
     error::Error,
-    fs::{remove_file, File},
-    io::{self, BufRead, BufReader, Read},
-    path::Path,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
+/// Wall-clock time a thumbnailer has to finish before being killed.
+const THUMBNAILER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time between two checks of a running thumbnailer.
+const THUMBNAILER_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Maximum address space of a thumbnailer, in bytes.
+const THUMBNAILER_MEMORY_LIMIT: u64 = 1024 * 1024 * 1024;
+/// Maximum CPU time of a thumbnailer, in seconds.
+const THUMBNAILER_CPU_LIMIT: u64 = 10;
+/// What the thumbnailers can read of the system when they're isolated: the programs, the
+/// libraries and the configuration they need, the home directories aren't visible.
+const SANDBOX_SYSTEM_PATHS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/libx32",
+    "/etc/ld.so.cache",
+    "/etc/alternatives",
+    "/etc/fonts",
+    "/var/cache/fontconfig",
+];
+
 lazy_static! {
+    /// bubblewrap, when it's installed and works the thumbnailers run isolated by it. Setting
+    /// `REACTOR_NO_SANDBOX` disables the isolation.
+    static ref SANDBOX: Option<PathBuf> = {
+        if env::var_os("REACTOR_NO_SANDBOX").is_some() {
+            return None;
+        }
+        let bwrap = find_executable("bwrap")?;
+        let works = Command::new(&bwrap)
+            .args(sandbox_system_args())
+            .args(&["--unshare-all", "--", "true"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        if !works {
+            warn!("{} doesn't work, thumbnailers will run without isolation", bwrap.display());
+        }
+        Some(bwrap).filter(|_| works)
+    };
+    /// The files that a thumbnailer failed to process, with their modification time so they're
+    /// tried again once they change.
+    static ref FAILED: Mutex<HashSet<(PathBuf, Option<SystemTime>)>> = Mutex::new(HashSet::new());
     static ref THUMBNAILERS: HashMap<Mime, Vec<String>> = {
         let mut thumbnailers = HashMap::new();
-        let folders = ["/usr/share/thumbnailers/"];
-        for folder in folders.iter() {
-            let dir = match Path::new(folder).read_dir() {
+        // A file shadows the ones with the same name in less important directories
+        let mut seen = HashSet::new();
+        for folder in data_dirs().iter().map(|dir| dir.join("thumbnailers")) {
+            let dir = match folder.read_dir() {
                 Ok(dir) => dir,
                 Err(_) => {
                     continue;
                 }
             };
-            for file in dir {
-                let file = match file {
-                    Ok(file) => file,
-                    Err(_) => {
+            let mut files: Vec<_> = dir.filter_map(|file| file.ok()).collect();
+            files.sort_by_key(|file| file.file_name());
+            for file in files {
+                if !file.path().is_file() || !seen.insert(file.file_name()) {
+                    continue;
+                }
+                let entry = match DesktopEntry::open(file.path()) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        debug!("Ignoring {}: {}", file.path().display(), e);
                         continue;
                     }
                 };
-                match file.file_type() {
-                    Ok(t) if t.is_file() => {}
-                    _ => {
+                if let Some(try_exec) = entry
+                    .group("Thumbnailer Entry")
+                    .and_then(|values| values.string("TryExec"))
+                {
+                    if find_executable(&try_exec).is_none() {
+                        debug!("Ignoring {}, {} is missing", file.path().display(), try_exec);
                         continue;
                     }
-                };
-                let config = match parse_config_file(file.path()) {
-                    Ok(config) => config,
-                    Err(_) => {
+                }
+                let (exec, mimes) = match read_thumbnailer(&entry) {
+                    Some(thumbnailer) => thumbnailer,
+                    None => {
+                        debug!("Ignoring {}, it isn't a valid thumbnailer", file.path().display());
                         continue;
                     }
                 };
-                for (header, values) in config {
-                    if header != "Thumbnailer Entry" {
-                        continue;
-                    }
-                    let exec = match values.get("Exec").filter(|e| !e.is_empty()) {
-                        Some(exec) => exec
-                            .split(' ')
-                            .map(|s| s.to_owned())
-                            .collect::<Vec<String>>(),
-                        None => {
-                            continue;
-                        }
-                    };
-                    let mime = match values.get("MimeType") {
-                        Some(mime) => mime,
-                        _ => {
-                            continue;
-                        }
-                    };
-                    for mime in mime.split(';').filter_map(|v| v.parse().ok()) {
-                        thumbnailers.insert(mime, exec.clone());
-                    }
+                for mime in mimes {
+                    thumbnailers.entry(mime).or_insert_with(|| exec.clone());
                 }
             }
         }
@@ -71,123 +121,330 @@ lazy_static! {
     };
 }
 
-// Support other enconding than UTF-8
-fn parse_config_file(
-    path: impl AsRef<Path>,
-) -> Result<Vec<(String, HashMap<String, String>)>, io::Error> {
-    let mut sections = Vec::new();
-    let mut lines = BufReader::new(File::open(path)?).lines();
-    while let Some(mut line) = lines.next().transpose()?.map(|line| line.trim().to_owned()) {
-        if line.is_empty() {
-            while let Some(nline) = lines
-                .next()
-                .transpose()?
-                .map(|nline| nline.trim().to_owned())
-            {
-                if !nline.is_empty() {
-                    line = nline;
-                    break;
-                }
-            }
-        }
-        if line.len() < 3 || !line.starts_with('[') || !line.ends_with(']') {
-            return Ok(Vec::new());
-        }
-        let header = line[1..line.len() - 1].to_owned();
-        let mut entries = HashMap::new();
-        while let Some(line) = lines.next().transpose()? {
-            let mut line = line.splitn(2, '=');
-            let key = match line.next() {
-                Some(key) => key.trim_end().to_owned(),
-                None => {
+/// Reads the arguments of the `Exec` key and the MIME types of a `.thumbnailer` file.
+fn read_thumbnailer(entry: &DesktopEntry) -> Option<(Vec<String>, Vec<Mime>)> {
+    let values = entry.group("Thumbnailer Entry")?;
+    let exec = values
+        .string("Exec")
+        .and_then(|exec| split_exec(&exec))
+        .filter(|exec| !exec.is_empty())?;
+    let mimes = values
+        .strings("MimeType")?
+        .iter()
+        .filter_map(|mime| mime.parse().ok())
+        .collect();
+    Some((exec, mimes))
+}
+
+/// Replaces the field codes of the thumbnailer command `exec`: `%u` is the URI of the input,
+/// `%i` its path, `%o` the path of the output, `%s` the size and `%%` a percent.
+fn thumbnailer_args(exec: &[String], input: &Path, output: &Path, size: u32) -> Vec<String> {
+    exec.iter()
+        .map(|arg| {
+            let mut expanded = String::new();
+            let mut chars = arg.chars();
+            while let Some(c) = chars.next() {
+                if c != '%' {
+                    expanded.push(c);
                     continue;
                 }
-            };
-            let value = match line.next() {
-                Some(value) => value.trim_start().to_owned(),
-                None => {
-                    return Ok(Vec::new());
+                match chars.next() {
+                    Some('u') => expanded.push_str(&file_uri(input)),
+                    Some('i') => expanded.push_str(&input.to_string_lossy()),
+                    Some('o') => expanded.push_str(&output.to_string_lossy()),
+                    Some('s') => expanded.push_str(&size.to_string()),
+                    Some('%') => expanded.push('%'),
+                    code => {
+                        debug!("Unknown field code %{} in {}", code.unwrap_or(' '), arg);
+                    }
                 }
-            };
-            entries.insert(key, value);
-        }
-        sections.push((header, entries));
-    }
-    Ok(sections)
+            }
+            expanded
+        })
+        .collect()
 }
 
-pub fn for_path(
-    path: impl AsRef<Path>,
-    mime: Mime,
-    size: u32,
-) -> Result<Option<DynamicImage>, Box<dyn Error>> {
-    if path
-        .as_ref()
-        .extension()
-        .and_then(|e| e.to_str())
-        .filter(|e| e.to_ascii_lowercase() == "desktop")
-        .is_some()
-    {
-        let config = parse_config_file(path.as_ref())?;
-        for (header, values) in config {
-            if header != "Desktop Entry" {
+/// The bubblewrap arguments that make the system paths visible, the symlinks of the merged /usr
+/// layouts are recreated as they are.
+fn sandbox_system_args() -> Vec<OsString> {
+    let mut args = Vec::new();
+    for path in SANDBOX_SYSTEM_PATHS {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
                 continue;
             }
-            if let Some(ref _icon) = values.get("Icon") {
-                // TODO
+        };
+        if metadata.file_type().is_symlink() {
+            if let Ok(target) = fs::read_link(path) {
+                args.push("--symlink".into());
+                args.push(target.into());
+                args.push(path.into());
             }
+        } else {
+            args.push("--ro-bind".into());
+            args.push(path.into());
+            args.push(path.into());
         }
     }
-    if let Some(ref cmd) = THUMBNAILERS.get(&mime) {
-        let mut output_path = temp_dir().join("reactor_thumbnail_output0");
-        let mut count = 0;
-        while output_path.exists() {
-            output_path.pop();
-            count += 1;
-            output_path.push(&format!("reactor_thumbnail_output{}", count));
+    args
+}
+
+/// Runs the thumbnailer command `args` with resource limits, isolated when possible, killing it
+/// if it doesn't finish in time. Returns whether it succeeded.
+fn run_thumbnailer(args: &[String], input: &Path, output: &Path) -> Result<bool, io::Error> {
+    let mut command = match *SANDBOX {
+        Some(ref bwrap) => {
+            let output_dir = output.parent().unwrap_or(output);
+            let mut command = Command::new(bwrap);
+            // Only the system, the input file and the output directory are visible, everything
+            // is read-only but the output directory, with a private /tmp and without network
+            command
+                .args(sandbox_system_args())
+                .args(&["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"])
+                .arg("--ro-bind")
+                .args(&[input, input])
+                .arg("--bind")
+                .args(&[output_dir, output_dir]);
+            // Thumbnailers installed elsewhere, like in the home directory, are made visible too
+            let program = find_executable(&args[0]);
+            if let Some(ref program) = program {
+                command.arg("--ro-bind").args(&[program, program]);
+            }
+            command
+                .args(&["--unshare-all", "--die-with-parent", "--"])
+                .arg(program.as_deref().unwrap_or_else(|| Path::new(&args[0])))
+                .args(&args[1..]);
+            command
         }
-        let mut args: Vec<String> = cmd[1..].to_vec();
-        let input_arg = format!("{}", path.as_ref().display());
-        let mut input_arg_used = false;
-        let output_arg = format!("{}", output_path.display());
-        let mut output_arg_used = false;
-        for arg in args.iter_mut() {
-            match &arg[..] {
-                "%u" => {
-                    *arg = input_arg.clone();
-                    input_arg_used = true;
-                }
-                "%o" => {
-                    *arg = output_arg.clone();
-                    output_arg_used = true;
-                }
-                "%s" => {
-                    *arg = format!("{}", size);
-                }
-                _ => {}
+        None => {
+            let mut command = Command::new(&args[0]);
+            command.args(&args[1..]);
+            command
+        }
+    };
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    unsafe {
+        command.pre_exec(|| {
+            // Its own process group lets killing the thumbnailer with its children
+            libc::setpgid(0, 0);
+            let limit = |resource, value: u64| {
+                let limit = libc::rlimit {
+                    rlim_cur: value as libc::rlim_t,
+                    rlim_max: value as libc::rlim_t,
+                };
+                libc::setrlimit(resource, &limit);
+            };
+            limit(libc::RLIMIT_AS, THUMBNAILER_MEMORY_LIMIT);
+            limit(libc::RLIMIT_CPU, THUMBNAILER_CPU_LIMIT);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    let deadline = Instant::now() + THUMBNAILER_TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.success());
+        }
+        if Instant::now() >= deadline {
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
             }
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
         }
-        if !input_arg_used {
-            args.push(input_arg);
+        thread::sleep(THUMBNAILER_POLL_INTERVAL);
+    }
+}
+
+/// The thumbnailers installed in the system, described by `.thumbnailer` files.
+pub struct SystemThumbnailers {
+    mime_types: Vec<Mime>,
+}
+
+impl SystemThumbnailers {
+    pub fn new() -> SystemThumbnailers {
+        SystemThumbnailers {
+            mime_types: THUMBNAILERS.keys().cloned().collect(),
         }
-        if !output_arg_used {
-            args.push(output_arg);
+    }
+}
+
+impl ThumbnailGenerator for SystemThumbnailers {
+    fn mime_types(&self) -> &[Mime] {
+        &self.mime_types
+    }
+
+    fn cost(&self) -> Cost {
+        Cost::Expensive
+    }
+
+    /// They're preferred to the built-in generators, the user installed them.
+    fn priority(&self) -> i32 {
+        1
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        mime: &Mime,
+        size: u32,
+    ) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+        for_path(path, mime, size)
+    }
+}
+
+fn for_path(path: &Path, mime: &Mime, size: u32) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    if let Some(ref exec) = THUMBNAILERS.get(mime) {
+        let input = path.canonicalize()?;
+        let failure = (
+            input.clone(),
+            input.metadata().and_then(|m| m.modified()).ok(),
+        );
+        if FAILED.lock().contains(&failure) {
+            return Err(ContentInfoError::PreviouslyFailed.into());
         }
-        let res = Command::new(&cmd.first().unwrap())
-            .args(args)
-            .stderr(Stdio::null())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .output()?;
-        if !res.status.success() {
-            let _ = remove_file(output_path);
-            return Ok(None);
+        let output = TempFile::new(".png")?;
+        let args = thumbnailer_args(exec, &input, output.path(), size);
+        let error = match run_thumbnailer(&args, &input, output.path()) {
+            Ok(true) => None,
+            Ok(false) => Some(ContentInfoError::ThumbnailerFailed(format!(
+                "{} exited with an error",
+                args[0]
+            ))),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Some(
+                ContentInfoError::ThumbnailerTimedOut(format!("{}: {}", args[0], e)),
+            ),
+            Err(e) => Some(ContentInfoError::Unreadable(format!("{}: {}", args[0], e))),
+        };
+        if let Some(error) = error {
+            // Not retried until the file changes, in this session
+            FAILED.lock().insert(failure);
+            return Err(error.into());
         }
         let mut img_buf = vec![];
-        File::open(&output_path)?.read_to_end(&mut img_buf)?;
+        File::open(output.path())?.read_to_end(&mut img_buf)?;
         let img = image::load_from_memory(&img_buf)?;
-        let _ = remove_file(output_path);
         return Ok(Some(img));
     }
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> DesktopEntry {
+        DesktopEntry::open(&crate::test_fixture("thumbnailers", name)).unwrap()
+    }
+
+    fn command(name: &str) -> Vec<String> {
+        let (exec, _) = read_thumbnailer(&fixture(name)).unwrap();
+        thumbnailer_args(
+            &exec,
+            Path::new("/home/user/My Files/a b.pdf"),
+            Path::new("/tmp/out.png"),
+            256,
+        )
+    }
+
+    #[test]
+    fn evince() {
+        assert_eq!(
+            command("evince.thumbnailer"),
+            [
+                "evince-thumbnailer",
+                "-s",
+                "256",
+                "file:///home/user/My%20Files/a%20b.pdf",
+                "/tmp/out.png"
+            ]
+        );
+        let (_, mimes) = read_thumbnailer(&fixture("evince.thumbnailer")).unwrap();
+        assert!(mimes.contains(&"application/pdf".parse().unwrap()));
+        assert!(mimes.contains(&"image/vnd.djvu".parse().unwrap()));
+    }
+
+    #[test]
+    fn input_path_and_flags() {
+        assert_eq!(
+            command("ffmpegthumbnailer.thumbnailer"),
+            [
+                "ffmpegthumbnailer",
+                "-i",
+                "/home/user/My Files/a b.pdf",
+                "-o",
+                "/tmp/out.png",
+                "-s",
+                "256",
+                "-f"
+            ]
+        );
+    }
+
+    #[test]
+    fn no_paths_are_appended() {
+        assert_eq!(
+            command("gdk-pixbuf-thumbnailer.thumbnailer"),
+            [
+                "gdk-pixbuf-thumbnailer",
+                "-s",
+                "256",
+                "file:///home/user/My%20Files/a%20b.pdf",
+                "/tmp/out.png"
+            ]
+        );
+        assert_eq!(
+            command("gnome-epub-thumbnailer.thumbnailer"),
+            [
+                "gnome-epub-thumbnailer",
+                "-s",
+                "256",
+                "file:///home/user/My%20Files/a%20b.pdf",
+                "/tmp/out.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_arguments() {
+        assert_eq!(
+            command("flatpak-app.thumbnailer"),
+            [
+                "/usr/bin/flatpak",
+                "run",
+                "--command=app-thumbnailer",
+                "org.example.App",
+                "--size=256",
+                "--title",
+                "My \"App\" $HOME",
+                "/home/user/My Files/a b.pdf",
+                "/tmp/out.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn embedded_codes_and_percent() {
+        assert_eq!(
+            command("percent.thumbnailer"),
+            [
+                "convert",
+                "-resize",
+                "256x256%",
+                "/home/user/My Files/a b.pdf[0]",
+                "png:/tmp/out.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_thumbnailers() {
+        assert!(read_thumbnailer(&fixture("unterminated-quote.thumbnailer")).is_none());
+        assert!(read_thumbnailer(&fixture("no-exec.thumbnailer")).is_none());
+        assert!(read_thumbnailer(&fixture("desktop-entry.thumbnailer")).is_none());
+    }
+}
@@ -1,45 +1,245 @@
-use image::{DynamicImage, RgbaImage};
+use image::{DynamicImage, ImageError, RgbaImage};
+use lazy_static::lazy_static;
+use log::{debug, warn};
 use mime::Mime;
-use std::{error::Error, path::Path};
+use parking_lot::Mutex;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+mod audio;
 mod embedded_thumbnailers_generators;
+pub mod generators;
+mod scheduler;
+mod thumbnail_cache;
+#[cfg(feature = "thumbnail-database")]
+mod thumbnail_database;
+
+pub use audio::AudioMetadata;
+use generators::GENERATORS;
+pub use scheduler::{Generation, SCHEDULER};
+use thumbnail_cache::{ThumbnailSize, THUMBNAIL_CACHE};
 
 #[cfg(target_os = "linux")]
 mod linux;
+#[cfg(target_os = "linux")]
+mod temp_files;
+
+/// Time between two removals of the stale thumbnails.
+const VACUUM_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How many files are remembered as logged, once it's reached they're forgotten all at once.
+const LOGGED_LIMIT: usize = 1024;
+
+lazy_static! {
+    /// The files whose content couldn't be read and were already logged, up to `LOGGED_LIMIT`.
+    static ref LOGGED: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct FileContentInfo {
     pub thumbnail: Option<RgbaImage>,
     pub mime: Option<Mime>,
+    /// The tags of audio files.
+    pub audio: Option<AudioMetadata>,
+    /// Why the content of the file couldn't be read, the thumbnail is unavailable when it's set.
+    pub error: Option<ContentInfoError>,
 }
 
-pub fn for_path(
-    path: impl AsRef<Path>,
-    thumbnails_size: u32,
-) -> Result<FileContentInfo, Box<dyn Error>> {
-    let mime = mime_guess::from_path(path.as_ref())
-        .first()
-        .or(tree_magic::from_filepath(path.as_ref()).parse().ok());
-    let mut thumbnail: Option<DynamicImage> = None;
+/// Why the content of a file couldn't be read.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ContentInfoError {
+    /// The file can't be read, e.g. for a lack of permissions.
+    Unreadable(String),
+    /// The content is malformed or of an unsupported kind.
+    Corrupted(String),
+    /// The thumbnailer of the file type failed.
+    ThumbnailerFailed(String),
+    /// The thumbnailer of the file type didn't finish in time, it may do it in another attempt.
+    ThumbnailerTimedOut(String),
+    /// Generating the thumbnail already failed for this version of the file.
+    PreviouslyFailed,
+}
+
+impl ContentInfoError {
+    fn from_error(e: &(dyn Error + 'static)) -> ContentInfoError {
+        if let Some(e) = e.downcast_ref::<ContentInfoError>() {
+            return e.clone();
+        }
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            return ContentInfoError::Unreadable(e.to_string());
+        }
+        match e.downcast_ref::<ImageError>() {
+            Some(ImageError::IoError(e)) => ContentInfoError::Unreadable(e.to_string()),
+            _ => ContentInfoError::Corrupted(e.to_string()),
+        }
+    }
+
+    /// Whether the thumbnail can't be generated until the file changes, a file that can't be read
+    /// now or a thumbnailer that ran out of time can succeed later.
+    fn is_lasting(&self) -> bool {
+        matches!(
+            self,
+            ContentInfoError::Corrupted(_) | ContentInfoError::ThumbnailerFailed(_)
+        )
+    }
+}
+
+impl fmt::Display for ContentInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentInfoError::Unreadable(message) => write!(f, "Unreadable: {}", message),
+            ContentInfoError::Corrupted(message) => write!(f, "Corrupted: {}", message),
+            ContentInfoError::ThumbnailerFailed(message) => {
+                write!(f, "Thumbnailer failed: {}", message)
+            }
+            ContentInfoError::ThumbnailerTimedOut(message) => {
+                write!(f, "Thumbnailer timed out: {}", message)
+            }
+            ContentInfoError::PreviouslyFailed => write!(f, "Failed in a previous attempt"),
+        }
+    }
+}
+
+impl Error for ContentInfoError {}
+
+/// Releases what the generation of content information left behind, to be called when exiting.
+pub fn cleanup() {
     #[cfg(target_os = "linux")]
-    if let Some(mime) = mime.clone() {
-        if let Some(t) = linux::for_path(path.as_ref(), mime, thumbnails_size)? {
-            thumbnail = Some(t);
+    temp_files::cleanup();
+}
+
+/// Removes the cached thumbnails of the files that don't exist anymore, if it wasn't done
+/// recently. The modification time of a stamp file records when it was done.
+pub fn vacuum_thumbnails() {
+    let cache = match THUMBNAIL_CACHE.as_ref() {
+        Some(cache) => cache,
+        None => {
+            return;
         }
+    };
+    let stamp = match dirs::cache_dir() {
+        Some(dir) => dir.join("reactor").join("thumbnails-vacuumed"),
+        None => {
+            return;
+        }
+    };
+    let recent = stamp
+        .metadata()
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.elapsed().ok())
+        .map_or(false, |elapsed| elapsed < VACUUM_INTERVAL);
+    if recent {
+        return;
     }
+    match cache.vacuum() {
+        Ok(removed) => {
+            debug!("Removed {} stale thumbnails", removed);
+            let written = stamp
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&stamp, b""));
+            if let Err(e) = written {
+                warn!("Can't write {}: {}", stamp.display(), e);
+            }
+        }
+        Err(e) => {
+            warn!("Can't vacuum the thumbnail cache: {}", e);
+        }
+    }
+}
+
+/// Reads the content information of the file at `path`, what can't be read is reported in its
+/// `error` and logged once per file.
+pub fn for_path(path: impl AsRef<Path>, thumbnails_size: u32) -> FileContentInfo {
+    let path = path.as_ref();
+    let mime = mime_guess::from_path(path)
+        .first()
+        .or(tree_magic::from_filepath(path).parse().ok());
+    let (thumbnail, error) = match thumbnail_for_path(path, mime.clone(), thumbnails_size) {
+        Ok(thumbnail) => (thumbnail, None),
+        Err(e) => {
+            let error = ContentInfoError::from_error(&*e);
+            if error != ContentInfoError::PreviouslyFailed && is_first_failure(path) {
+                warn!("Can't read the content of {}: {}", path.display(), e);
+            }
+            (None, Some(error))
+        }
+    };
+    let is_audio = mime
+        .as_ref()
+        .map_or(false, |mime| mime.type_() == mime::AUDIO);
+    let audio = if is_audio {
+        match audio::read_metadata(path) {
+            Ok(audio) => audio,
+            Err(e) => {
+                debug!("Can't read the tags of {}: {}", path.display(), e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    FileContentInfo {
+        thumbnail,
+        mime,
+        audio,
+        error,
+    }
+}
+
+/// Whether the failure of `path` wasn't logged yet, a file can be logged again after many others
+/// were.
+fn is_first_failure(path: &Path) -> bool {
+    let mut logged = LOGGED.lock();
+    if logged.contains(path) {
+        return false;
+    }
+    if logged.len() >= LOGGED_LIMIT {
+        logged.clear();
+    }
+    logged.insert(path.to_path_buf())
+}
+
+fn thumbnail_for_path(
+    path: &Path,
+    mime: Option<Mime>,
+    thumbnails_size: u32,
+) -> Result<Option<RgbaImage>, Box<dyn Error>> {
+    let cache = THUMBNAIL_CACHE.as_ref();
+    let cache_size = ThumbnailSize::for_pixels(thumbnails_size);
+    let mut thumbnail = cache
+        .and_then(|cache| cache.load(path, cache_size))
+        .map(DynamicImage::ImageRgba8);
     if thumbnail.is_none() {
-        let ext = match path
-            .as_ref()
-            .extension()
-            .and_then(|e| e.to_str().map(|s| s.to_owned()))
-        {
-            Some(ext) => ext,
-            None => "".to_owned(),
+        if cache.map_or(false, |cache| cache.has_failed(path)) {
+            return Err(ContentInfoError::PreviouslyFailed.into());
+        }
+        let generated = match mime {
+            Some(ref mime) => GENERATORS.read().generate(path, mime, cache_size.pixels()),
+            None => Ok(None),
         };
-        if let Some(t) = embedded_thumbnailers_generators::generate_from_path(path.as_ref())? {
-            thumbnail = Some(t);
+        if let Some(cache) = cache {
+            let pixels = cache_size.pixels();
+            let stored = match generated {
+                Ok(Some(ref t)) => {
+                    cache.save(path, cache_size, &t.thumbnail(pixels, pixels).into_rgba8())
+                }
+                Ok(None) => Ok(()),
+                Err(ref e) if ContentInfoError::from_error(&**e).is_lasting() => {
+                    cache.save_failure(path)
+                }
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = stored {
+                debug!("Can't cache the thumbnail of {}: {}", path.display(), e);
+            }
         }
+        thumbnail = generated?;
     }
-    let thumbnail = thumbnail.map(|t| t.thumbnail(thumbnails_size, thumbnails_size).into_rgba());
-    Ok(FileContentInfo { thumbnail, mime })
+    Ok(thumbnail.map(|t| t.thumbnail(thumbnails_size, thumbnails_size).into_rgba8()))
 }
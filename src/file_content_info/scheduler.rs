@@ -0,0 +1,353 @@
+use lazy_static::lazy_static;
+use log::warn;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+lazy_static! {
+    /// The scheduler of the content info jobs, its number of threads is taken from
+    /// `REACTOR_THUMBNAIL_JOBS` and defaults to half of the available cores.
+    pub static ref SCHEDULER: Scheduler = Scheduler::new(worker_count());
+}
+
+/// Identifies the jobs submitted for one listing of a directory, they are cancelled together
+/// when the listing is left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Generation(u64);
+
+/// Runs the content info jobs in its own threads. The jobs of the visible items run first, the
+/// rest in the order they were submitted.
+pub struct Scheduler {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct Queue {
+    next_generation: u64,
+    next_sequence: u64,
+    /// The pending jobs ordered by priority and then by submission.
+    jobs: BTreeMap<(Priority, u64), Job>,
+    /// Where every pending job is in `jobs`.
+    keys: HashMap<(Generation, PathBuf), (Priority, u64)>,
+    /// The paths shown on screen of every generation.
+    visible: HashMap<Generation, HashSet<PathBuf>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Priority {
+    Visible,
+    Hidden,
+}
+
+struct Job {
+    generation: Generation,
+    path: PathBuf,
+    run: Box<dyn FnOnce() + Send>,
+}
+
+impl Scheduler {
+    /// Starts a scheduler that runs up to `threads` jobs at once.
+    pub fn new(threads: usize) -> Scheduler {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            available: Condvar::new(),
+        });
+        for i in 0..threads.max(1) {
+            let shared = shared.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("content-info-{}", i))
+                .spawn(move || shared.work());
+            if let Err(e) = spawned {
+                warn!("Can't start a content info thread: {}", e);
+            }
+        }
+        Scheduler { shared }
+    }
+
+    /// A new generation for the jobs of a listing.
+    pub fn generation(&self) -> Generation {
+        let mut queue = self.shared.queue.lock();
+        queue.next_generation += 1;
+        Generation(queue.next_generation)
+    }
+
+    /// Queues `run` as the job of `path`, it replaces the pending job of the same path and
+    /// generation.
+    pub fn submit(
+        &self,
+        generation: Generation,
+        path: PathBuf,
+        run: impl FnOnce() + Send + 'static,
+    ) {
+        let mut queue = self.shared.queue.lock();
+        let priority = match queue.visible.get(&generation) {
+            Some(visible) if visible.contains(&path) => Priority::Visible,
+            _ => Priority::Hidden,
+        };
+        queue.next_sequence += 1;
+        let key = (priority, queue.next_sequence);
+        if let Some(old_key) = queue.keys.insert((generation, path.clone()), key) {
+            queue.jobs.remove(&old_key);
+        }
+        queue.jobs.insert(
+            key,
+            Job {
+                generation,
+                path,
+                run: Box::new(run),
+            },
+        );
+        drop(queue);
+        self.shared.available.notify_one();
+    }
+
+    /// Makes the jobs of `paths` run before the other ones of `generation`, replacing the
+    /// previously visible paths.
+    pub fn set_visible(&self, generation: Generation, paths: impl IntoIterator<Item = PathBuf>) {
+        let mut queue = self.shared.queue.lock();
+        let visible: HashSet<PathBuf> = paths.into_iter().collect();
+        let old_visible = queue.visible.remove(&generation).unwrap_or_default();
+        for path in old_visible.difference(&visible) {
+            queue.reprioritize(generation, path, Priority::Hidden);
+        }
+        for path in visible.difference(&old_visible) {
+            queue.reprioritize(generation, path, Priority::Visible);
+        }
+        queue.visible.insert(generation, visible);
+    }
+
+    /// Drops the pending jobs of `generation`, the running ones are left to finish.
+    pub fn cancel(&self, generation: Generation) {
+        let mut queue = self.shared.queue.lock();
+        queue.visible.remove(&generation);
+        let keys: Vec<(Priority, u64)> = queue
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.generation == generation)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in keys {
+            if let Some(job) = queue.jobs.remove(&key) {
+                queue.keys.remove(&(job.generation, job.path));
+            }
+        }
+    }
+}
+
+impl Queue {
+    fn reprioritize(&mut self, generation: Generation, path: &Path, priority: Priority) {
+        let key = match self.keys.get_mut(&(generation, path.to_path_buf())) {
+            Some(key) => key,
+            None => {
+                return;
+            }
+        };
+        let old_key = *key;
+        key.0 = priority;
+        if let Some(job) = self.jobs.remove(&old_key) {
+            self.jobs.insert((priority, old_key.1), job);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        let key = *self.jobs.keys().next()?;
+        let job = self.jobs.remove(&key)?;
+        self.keys.remove(&(job.generation, job.path.clone()));
+        Some(job)
+    }
+}
+
+impl Shared {
+    fn work(&self) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock();
+                loop {
+                    if let Some(job) = queue.pop() {
+                        break job;
+                    }
+                    self.available.wait(&mut queue);
+                }
+            };
+            // A panicking job must not take the thread with it
+            if panic::catch_unwind(AssertUnwindSafe(job.run)).is_err() {
+                warn!("The content info job of {} panicked", job.path.display());
+            }
+        }
+    }
+}
+
+fn worker_count() -> usize {
+    if let Ok(jobs) = env::var("REACTOR_THUMBNAIL_JOBS") {
+        match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => {
+                return jobs;
+            }
+            _ => {
+                warn!("Ignoring invalid REACTOR_THUMBNAIL_JOBS {:?}", jobs);
+            }
+        }
+    }
+    thread::available_parallelism().map_or(1, |cores| (cores.get() / 2).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scheduler without threads, so the tests take the jobs out of the queue themselves.
+    fn idle_scheduler() -> Scheduler {
+        Scheduler {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(Queue::default()),
+                available: Condvar::new(),
+            }),
+        }
+    }
+
+    fn submit(scheduler: &Scheduler, generation: Generation, name: &str) {
+        scheduler.submit(generation, PathBuf::from(name), || {});
+    }
+
+    /// The paths of the pending jobs in the order they would run.
+    fn drain(scheduler: &Scheduler) -> Vec<PathBuf> {
+        let mut queue = scheduler.shared.queue.lock();
+        let mut paths = Vec::new();
+        while let Some(job) = queue.pop() {
+            paths.push(job.path);
+        }
+        paths
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn visible_jobs_first() {
+        let scheduler = idle_scheduler();
+        let generation = scheduler.generation();
+        scheduler.set_visible(generation, paths(&["c", "e"]));
+        for name in &["a", "b", "c", "d", "e"] {
+            submit(&scheduler, generation, name);
+        }
+        assert_eq!(drain(&scheduler), paths(&["c", "e", "a", "b", "d"]));
+
+        // Jobs already pending are promoted too
+        for name in &["a", "b", "c"] {
+            submit(&scheduler, generation, name);
+        }
+        scheduler.set_visible(generation, paths(&["b"]));
+        assert_eq!(drain(&scheduler), paths(&["b", "a", "c"]));
+    }
+
+    #[test]
+    fn set_visible_demotes() {
+        let scheduler = idle_scheduler();
+        let generation = scheduler.generation();
+        scheduler.set_visible(generation, paths(&["a", "b"]));
+        for name in &["a", "b", "c"] {
+            submit(&scheduler, generation, name);
+        }
+        scheduler.set_visible(generation, paths(&["c"]));
+        assert_eq!(drain(&scheduler), paths(&["c", "a", "b"]));
+
+        // The demoted paths stay hidden when they are submitted again
+        for name in &["a", "c"] {
+            submit(&scheduler, generation, name);
+        }
+        assert_eq!(drain(&scheduler), paths(&["c", "a"]));
+    }
+
+    #[test]
+    fn cancel_drops_the_generation() {
+        let scheduler = idle_scheduler();
+        let left = scheduler.generation();
+        let current = scheduler.generation();
+        scheduler.set_visible(left, paths(&["a"]));
+        submit(&scheduler, left, "a");
+        submit(&scheduler, current, "b");
+        submit(&scheduler, left, "c");
+        submit(&scheduler, current, "d");
+        scheduler.cancel(left);
+        assert_eq!(drain(&scheduler), paths(&["b", "d"]));
+        assert!(scheduler.shared.queue.lock().keys.is_empty());
+
+        // A cancelled generation can't make its paths visible again
+        submit(&scheduler, left, "a");
+        assert_eq!(
+            scheduler
+                .shared
+                .queue
+                .lock()
+                .jobs
+                .keys()
+                .next()
+                .map(|key| key.0),
+            Some(Priority::Hidden)
+        );
+    }
+
+    #[test]
+    fn submit_replaces_the_pending_job() {
+        let scheduler = idle_scheduler();
+        let generation = scheduler.generation();
+        let other = scheduler.generation();
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        for (generation, run) in &[(generation, 1), (other, 2), (generation, 3)] {
+            let runs = runs.clone();
+            let run = *run;
+            scheduler.submit(*generation, PathBuf::from("a"), move || {
+                runs.lock().push(run)
+            });
+        }
+        submit(&scheduler, generation, "b");
+        loop {
+            let job = scheduler.shared.queue.lock().pop();
+            match job {
+                Some(job) => (job.run)(),
+                None => {
+                    break;
+                }
+            }
+        }
+        // The replaced job never runs, the one of the other generation is kept
+        assert_eq!(*runs.lock(), vec![2, 3]);
+    }
+
+    #[test]
+    fn runs_the_jobs() {
+        let scheduler = Scheduler::new(2);
+        let generation = scheduler.generation();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for i in 0..8 {
+            let sender = sender.clone();
+            scheduler.submit(generation, PathBuf::from(i.to_string()), move || {
+                if i == 3 {
+                    panic!("a failing job");
+                }
+                let _ = sender.send(i);
+            });
+        }
+        let mut done: Vec<i32> = (0..7)
+            .map(|_| {
+                receiver
+                    .recv_timeout(std::time::Duration::from_secs(5))
+                    .unwrap()
+            })
+            .collect();
+        done.sort_unstable();
+        assert_eq!(done, vec![0, 1, 2, 4, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,211 @@
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use std::{
+    env, fs,
+    fs::{File, OpenOptions},
+    io,
+    os::unix::{
+        fs::{DirBuilderExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const PREFIX: &str = "reactor-";
+/// The file locked by the process that owns a temporary directory, while it's running.
+const LOCK_NAME: &str = ".lock";
+
+lazy_static! {
+    /// The temporary directory of this process, only accessible by the user.
+    static ref TEMP_DIR: Result<LockedDir, String> = create_temp_dir().map_err(|e| {
+        warn!("Can't create the temporary directory: {}", e);
+        e.to_string()
+    });
+}
+
+/// A directory locked until the process exits.
+struct LockedDir {
+    path: PathBuf,
+    _lock: File,
+}
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A file in the temporary directory of the process, removed when dropped.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Creates an empty file with an unique name ending with `suffix`.
+    pub fn new(suffix: &str) -> Result<TempFile, io::Error> {
+        let dir = &TEMP_DIR
+            .as_ref()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.clone()))?
+            .path;
+        loop {
+            let path = dir.join(format!(
+                "{}{}",
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                suffix
+            ));
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)
+            {
+                Ok(_) => {
+                    return Ok(TempFile { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Removes the temporary directory of the process, to be called when exiting.
+pub fn cleanup() {
+    if let Ok(ref dir) = *TEMP_DIR {
+        if let Err(e) = fs::remove_dir_all(&dir.path) {
+            warn!("Can't remove {}: {}", dir.path.display(), e);
+        }
+    }
+}
+
+fn create_temp_dir() -> Result<LockedDir, io::Error> {
+    remove_stale_dirs(&env::temp_dir());
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+        let dir = env::temp_dir().join(format!(
+            "{}{}-{:x}",
+            PREFIX,
+            process::id(),
+            nanos.wrapping_add(attempt)
+        ));
+        match fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => {
+                let lock = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(dir.join(LOCK_NAME))?;
+                if !try_lock(&lock) {
+                    return Err(io::Error::last_os_error());
+                }
+                return Ok(LockedDir {
+                    path: dir,
+                    _lock: lock,
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Removes the temporary directories in `temp_dir` of the processes that didn't exit cleanly,
+/// the ones whose lock isn't held anymore.
+fn remove_stale_dirs(temp_dir: &Path) {
+    let entries = match temp_dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => {
+            return;
+        }
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let is_temp_dir = entry
+            .file_name()
+            .to_str()
+            .map_or(false, |name| name.starts_with(PREFIX));
+        let owned = entry
+            .metadata()
+            .map(|meta| {
+                use std::os::unix::fs::MetadataExt;
+                meta.is_dir() && meta.uid() == unsafe { libc::getuid() }
+            })
+            .unwrap_or(false);
+        if !is_temp_dir || !owned {
+            continue;
+        }
+        // Without its lock file the directory may have just been created
+        let lock = match File::open(entry.path().join(LOCK_NAME)) {
+            Ok(lock) => lock,
+            Err(_) => {
+                continue;
+            }
+        };
+        if try_lock(&lock) {
+            debug!("Removing the stale directory {}", entry.path().display());
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+/// Takes the exclusive lock of `file` without waiting, it's released when the file is closed.
+fn try_lock(file: &File) -> bool {
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(parent: &Path, name: &str) -> PathBuf {
+        let dir = parent.join(name);
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("0.png"), b"").unwrap();
+        dir
+    }
+
+    #[test]
+    fn stale_dirs() {
+        let parent = tempfile::tempdir().unwrap();
+        let stale = temp_dir(parent.path(), "reactor-1-a");
+        fs::write(stale.join(LOCK_NAME), b"").unwrap();
+        let running = temp_dir(parent.path(), "reactor-2-b");
+        let lock = File::create(running.join(LOCK_NAME)).unwrap();
+        assert!(try_lock(&lock));
+        let unlocked = temp_dir(parent.path(), "reactor-3-c");
+        let other = temp_dir(parent.path(), "other-4-d");
+        fs::write(other.join(LOCK_NAME), b"").unwrap();
+
+        remove_stale_dirs(parent.path());
+        assert!(!stale.exists());
+        assert!(running.join("0.png").exists());
+        assert!(unlocked.exists());
+        assert!(other.exists());
+
+        // The lock is released with the process
+        drop(lock);
+        remove_stale_dirs(parent.path());
+        assert!(!running.exists());
+    }
+}
@@ -0,0 +1,498 @@
+use crate::uri::{decode_path, file_uri};
+use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use lazy_static::lazy_static;
+use log::debug;
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::UNIX_EPOCH,
+};
+
+lazy_static! {
+    /// Where the thumbnails are kept, the thumbnails directory shared with the other applications
+    /// of the desktop unless `REACTOR_THUMBNAIL_CACHE=database` selects the thumbnail database.
+    pub static ref THUMBNAIL_CACHE: Option<Box<dyn ThumbnailStore>> = open_store();
+}
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The sizes of the Thumbnail Managing Standard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailSize {
+    Normal,
+    Large,
+    XLarge,
+    XXLarge,
+}
+
+impl ThumbnailSize {
+    /// The smallest size that holds thumbnails of `pixels`, the largest one if none does.
+    pub fn for_pixels(pixels: u32) -> ThumbnailSize {
+        [
+            ThumbnailSize::Normal,
+            ThumbnailSize::Large,
+            ThumbnailSize::XLarge,
+        ]
+        .iter()
+        .copied()
+        .find(|size| size.pixels() >= pixels)
+        .unwrap_or(ThumbnailSize::XXLarge)
+    }
+
+    pub fn pixels(self) -> u32 {
+        match self {
+            ThumbnailSize::Normal => 128,
+            ThumbnailSize::Large => 256,
+            ThumbnailSize::XLarge => 512,
+            ThumbnailSize::XXLarge => 1024,
+        }
+    }
+
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            ThumbnailSize::Normal => "normal",
+            ThumbnailSize::Large => "large",
+            ThumbnailSize::XLarge => "x-large",
+            ThumbnailSize::XXLarge => "xx-large",
+        }
+    }
+}
+
+/// A place where thumbnails are kept between runs.
+pub trait ThumbnailStore: Send + Sync {
+    /// The stored thumbnail of `path`, if it's still valid.
+    fn load(&self, path: &Path, size: ThumbnailSize) -> Option<RgbaImage>;
+
+    /// Stores `image` as the thumbnail of `path`, it should already fit in `size`.
+    fn save(
+        &self,
+        path: &Path,
+        size: ThumbnailSize,
+        image: &RgbaImage,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Whether generating a thumbnail of `path` already failed with its current version.
+    fn has_failed(&self, path: &Path) -> bool;
+
+    /// Records that a thumbnail of `path` can't be generated, until it's modified.
+    fn save_failure(&self, path: &Path) -> Result<(), Box<dyn Error>>;
+
+    /// Removes the thumbnails whose source doesn't exist anymore, returning how many were
+    /// removed.
+    fn vacuum(&self) -> Result<usize, Box<dyn Error>>;
+}
+
+fn open_store() -> Option<Box<dyn ThumbnailStore>> {
+    let cache_dir = dirs::cache_dir()?;
+    #[cfg(feature = "thumbnail-database")]
+    if std::env::var("REACTOR_THUMBNAIL_CACHE").as_deref() == Ok("database") {
+        let path = cache_dir.join("reactor").join("thumbnails.redb");
+        match super::thumbnail_database::ThumbnailDatabase::open(&path) {
+            Ok(database) => {
+                return Some(Box::new(database));
+            }
+            Err(e) => {
+                log::warn!("Can't open {}: {}", path.display(), e);
+            }
+        }
+    }
+    Some(Box::new(FreedesktopCache::new(
+        cache_dir.join("thumbnails"),
+    )))
+}
+
+/// What identifies the version of a file in a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    /// The canonical path of the file.
+    pub path: PathBuf,
+    pub uri: String,
+    /// The modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+    pub size: u64,
+    pub inode: u64,
+}
+
+impl Source {
+    pub fn new(path: &Path) -> Option<Source> {
+        let path = path.canonicalize().ok()?;
+        let meta = path.metadata().ok()?;
+        let mtime = meta
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&meta);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Some(Source {
+            uri: file_uri(&path),
+            path,
+            mtime,
+            size: meta.len(),
+            inode,
+        })
+    }
+}
+
+/// The signature that starts every PNG file.
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// The size of the largest text chunk read, the URIs are far shorter.
+const MAX_TEXT_LEN: usize = 64 * 1024;
+/// Where removable and network volumes are mounted, their files disappear while they're
+/// unmounted.
+#[cfg(unix)]
+const MEDIA_DIRS: &[&str] = &["/media", "/run/media", "/mnt"];
+
+/// A cache following the freedesktop Thumbnail Managing Standard, the thumbnails are PNG files
+/// named after the MD5 of the URI of their source, which they record with its modification time.
+#[derive(Debug, Clone)]
+pub struct FreedesktopCache {
+    root: PathBuf,
+}
+
+impl FreedesktopCache {
+    pub fn new(root: impl Into<PathBuf>) -> FreedesktopCache {
+        FreedesktopCache { root: root.into() }
+    }
+
+    fn failures_dir(&self) -> PathBuf {
+        self.root
+            .join("fail")
+            .join(concat!("reactor-", env!("CARGO_PKG_VERSION")))
+    }
+
+    /// The identity of `path`, the thumbnails themselves can't be cached.
+    fn source(&self, path: &Path) -> Option<Source> {
+        Some(Source::new(path)?).filter(|source| !source.path.starts_with(&self.root))
+    }
+}
+
+impl ThumbnailStore for FreedesktopCache {
+    fn load(&self, path: &Path, size: ThumbnailSize) -> Option<RgbaImage> {
+        let source = self.source(path)?;
+        let thumbnail = self.root.join(size.dir_name()).join(file_name(&source.uri));
+        match read_thumbnail(&thumbnail, &source) {
+            Ok(image) => image,
+            Err(e) => {
+                debug!("Ignoring {}: {}", thumbnail.display(), e);
+                None
+            }
+        }
+    }
+
+    fn save(
+        &self,
+        path: &Path,
+        size: ThumbnailSize,
+        image: &RgbaImage,
+    ) -> Result<(), Box<dyn Error>> {
+        let source = match self.source(path) {
+            Some(source) => source,
+            None => {
+                return Ok(());
+            }
+        };
+        write_thumbnail(&self.root.join(size.dir_name()), &source, image)
+    }
+
+    fn has_failed(&self, path: &Path) -> bool {
+        let source = match self.source(path) {
+            Some(source) => source,
+            None => {
+                return false;
+            }
+        };
+        let failure = self.failures_dir().join(file_name(&source.uri));
+        matches!(read_thumbnail(&failure, &source), Ok(Some(_)))
+    }
+
+    fn save_failure(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let source = match self.source(path) {
+            Some(source) => source,
+            None => {
+                return Ok(());
+            }
+        };
+        write_thumbnail(&self.failures_dir(), &source, &RgbaImage::new(1, 1))
+    }
+
+    fn vacuum(&self) -> Result<usize, Box<dyn Error>> {
+        let mut dirs: Vec<PathBuf> = [
+            ThumbnailSize::Normal,
+            ThumbnailSize::Large,
+            ThumbnailSize::XLarge,
+            ThumbnailSize::XXLarge,
+        ]
+        .iter()
+        .map(|size| self.root.join(size.dir_name()))
+        .collect();
+        dirs.push(self.failures_dir());
+        let mut removed = 0;
+        for dir in dirs {
+            let entries = match dir.read_dir() {
+                Ok(entries) => entries,
+                Err(_) => {
+                    continue;
+                }
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let uri = match read_uri(&entry.path()) {
+                    Ok(Some(uri)) => uri,
+                    _ => {
+                        continue;
+                    }
+                };
+                let path = match uri.strip_prefix("file://") {
+                    Some(path) => decode_path(path),
+                    // Only the local files can be checked
+                    None => {
+                        continue;
+                    }
+                };
+                if path.map_or(false, |path| is_gone(&path))
+                    && fs::remove_file(entry.path()).is_ok()
+                {
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn file_name(uri: &str) -> String {
+    format!("{:x}.png", md5::compute(uri.as_bytes()))
+}
+
+/// Whether the file at `path` surely doesn't exist anymore, when its whole directory is missing
+/// it could be on an unmounted volume.
+pub(super) fn is_gone(path: &Path) -> bool {
+    path.symlink_metadata().is_err()
+        && path
+            .parent()
+            .map_or(false, |parent| parent.is_dir() && is_volume_mounted(parent))
+}
+
+/// Whether the volume of `dir` is mounted, when it's in a media directory. An unmounted volume
+/// can leave its mount point there, on the file system of the media directory.
+#[cfg(unix)]
+fn is_volume_mounted(dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let device = |path: &Path| path.metadata().map(|meta| meta.dev()).ok();
+    match MEDIA_DIRS
+        .iter()
+        .map(Path::new)
+        .find(|media| dir.starts_with(media) && dir != *media)
+    {
+        Some(media) => device(dir) != device(media),
+        None => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_volume_mounted(_dir: &Path) -> bool {
+    true
+}
+
+/// Reads the URI recorded in the thumbnail at `path` from its text chunks, without decoding the
+/// image.
+fn read_uri(path: &Path) -> io::Result<Option<String>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut signature = [0; 8];
+    file.read_exact(&mut signature)?;
+    if signature != PNG_SIGNATURE {
+        return Ok(None);
+    }
+    loop {
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        match &header[4..] {
+            b"IEND" => {
+                return Ok(None);
+            }
+            b"tEXt" if len <= MAX_TEXT_LEN => {
+                let mut text = vec![0; len];
+                file.read_exact(&mut text)?;
+                if let Some(uri) = text.strip_prefix(b"Thumb::URI\0") {
+                    // Latin-1
+                    return Ok(Some(uri.iter().map(|b| *b as char).collect()));
+                }
+            }
+            // The image data is skipped
+            _ => file.seek_relative(len as i64)?,
+        }
+        // The CRC
+        file.seek_relative(4)?;
+    }
+}
+
+/// A thumbnail file with the information it records.
+struct Png {
+    uri: Option<String>,
+    mtime: Option<u64>,
+    image: Option<RgbaImage>,
+}
+
+/// Reads the thumbnail at `path`, `None` if it belongs to another version of `source`.
+fn read_thumbnail(path: &Path, source: &Source) -> Result<Option<RgbaImage>, Box<dyn Error>> {
+    Ok(read_png(path)?
+        .filter(|png| {
+            png.uri.as_deref() == Some(&source.uri[..]) && png.mtime == Some(source.mtime)
+        })
+        .and_then(|png| png.image))
+}
+
+fn read_png(path: &Path) -> Result<Option<Png>, Box<dyn Error>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e.into());
+        }
+    };
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf)?;
+    // The text chunks can follow the image data
+    reader.finish()?;
+    let mut uri = None;
+    let mut mtime = None;
+    let info = reader.info();
+    let texts = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .chain(
+            info.compressed_latin1_text
+                .iter()
+                .filter_map(|chunk| Some((chunk.keyword.clone(), chunk.get_text().ok()?))),
+        )
+        .chain(
+            info.utf8_text
+                .iter()
+                .filter_map(|chunk| Some((chunk.keyword.clone(), chunk.get_text().ok()?))),
+        );
+    for (keyword, text) in texts {
+        match &keyword[..] {
+            "Thumb::URI" => uri = Some(text),
+            "Thumb::MTime" => mtime = text.trim().parse::<u64>().ok(),
+            _ => {}
+        }
+    }
+    buf.truncate(frame.buffer_size());
+    let (width, height) = (frame.width, frame.height);
+    let image = match frame.color_type {
+        png::ColorType::Rgba => {
+            RgbaImage::from_raw(width, height, buf).map(DynamicImage::ImageRgba8)
+        }
+        png::ColorType::Rgb => RgbImage::from_raw(width, height, buf).map(DynamicImage::ImageRgb8),
+        png::ColorType::GrayscaleAlpha => {
+            GrayAlphaImage::from_raw(width, height, buf).map(DynamicImage::ImageLumaA8)
+        }
+        png::ColorType::Grayscale => {
+            GrayImage::from_raw(width, height, buf).map(DynamicImage::ImageLuma8)
+        }
+        png::ColorType::Indexed => None,
+    };
+    Ok(Some(Png {
+        uri,
+        mtime,
+        image: image.map(|image| image.into_rgba8()),
+    }))
+}
+
+/// Writes `image` as the thumbnail of `source` in `dir`. It's written in a temporary file that
+/// replaces the old thumbnail at once, so other programs never read a partial thumbnail.
+fn write_thumbnail(dir: &Path, source: &Source, image: &RgbaImage) -> Result<(), Box<dyn Error>> {
+    create_private_dir(dir)?;
+    let path = dir.join(file_name(&source.uri));
+    let temp_path = dir.join(format!(
+        ".reactor-{}-{}.png",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&temp_path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk("Thumb::URI".to_owned(), source.uri.clone())?;
+        encoder.add_text_chunk("Thumb::MTime".to_owned(), source.mtime.to_string())?;
+        encoder.add_text_chunk("Thumb::Size".to_owned(), source.size.to_string())?;
+        encoder.add_text_chunk("Software".to_owned(), "Reactor".to_owned())?;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(image.as_raw())?;
+        writer.finish()?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn create_private_dir(dir: &Path) -> Result<(), io::Error> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        crate::test_fixture("thumbnails", name)
+    }
+
+    #[test]
+    fn recorded_uris() {
+        assert_eq!(
+            read_uri(&fixture("uri-first.png")).unwrap().unwrap(),
+            "file:///home/user/a%20b.png"
+        );
+        // The text chunks can follow the image data
+        assert_eq!(
+            read_uri(&fixture("uri-last.png")).unwrap().unwrap(),
+            "file:///home/user/café.png"
+        );
+        assert!(read_uri(&fixture("no-uri.png")).unwrap().is_none());
+        assert!(
+            read_uri(&crate::test_fixture("images", "exif-thumbnail.jpg"))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn missing_sources() {
+        assert!(!is_gone(&fixture("uri-first.png")));
+        assert!(is_gone(&fixture("missing.png")));
+        // The whole directory could be on an unmounted volume
+        assert!(!is_gone(&fixture("missing/missing.png")));
+    }
+}
@@ -0,0 +1,566 @@
+use super::thumbnail_cache::{is_gone, Source, ThumbnailSize, ThumbnailStore};
+use image::{codecs::png::PngEncoder, ColorType, ImageFormat, RgbaImage};
+use log::debug;
+use parking_lot::Mutex;
+use redb::{Database, Durability, ReadableTable, Table, TableDefinition, WriteTransaction};
+use std::{convert::TryInto, error::Error, fs, mem, path::Path};
+
+/// The thumbnails by source path and size, each record holds the identity of the source and the
+/// thumbnail as PNG.
+const THUMBNAILS: TableDefinition<(&[u8], u8), &[u8]> = TableDefinition::new("thumbnails");
+/// The last access of each thumbnail, kept apart so loads don't rewrite the thumbnails.
+const LAST_ACCESSES: TableDefinition<(&[u8], u8), u64> = TableDefinition::new("last_accesses");
+/// The keys of `THUMBNAILS` ordered from the least recently used.
+const ACCESSES: TableDefinition<(u64, &[u8], u8), ()> = TableDefinition::new("accesses");
+/// The identity of the sources whose thumbnail can't be generated.
+const FAILURES: TableDefinition<&[u8], (u64, u64, u64)> = TableDefinition::new("failures");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+/// The size of the records after which the least recently used are evicted by default.
+const SIZE_LIMIT: u64 = 256 * 1024 * 1024;
+const HEADER_SIZE: usize = 24;
+/// How many accesses to thumbnails are kept in memory before they're written at once.
+const ACCESS_BATCH: usize = 64;
+
+/// A cache that keeps the thumbnails in a single embedded B-tree file. Sources are identified by
+/// path, inode, modification time and size, and the least recently used thumbnails are evicted
+/// when the cache grows over its size limit. Every save is an atomic transaction, so a crash never
+/// leaves a partial thumbnail. Loads only read, the accesses are written in batches.
+pub struct ThumbnailDatabase {
+    database: Database,
+    /// The size of the records after which the least recently used are evicted.
+    size_limit: u64,
+    /// The accesses that aren't written yet, from the oldest.
+    pending_accesses: Mutex<Vec<(Vec<u8>, u8)>>,
+}
+
+/// The version of a source recorded with its thumbnail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Identity {
+    inode: u64,
+    mtime: u64,
+    size: u64,
+}
+
+impl ThumbnailDatabase {
+    pub fn open(path: &Path) -> Result<ThumbnailDatabase, Box<dyn Error>> {
+        ThumbnailDatabase::with_size_limit(path, SIZE_LIMIT)
+    }
+
+    fn with_size_limit(path: &Path, size_limit: u64) -> Result<ThumbnailDatabase, Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let database = Database::create(path)?;
+        let txn = database.begin_write()?;
+        txn.open_table(THUMBNAILS)?;
+        txn.open_table(LAST_ACCESSES)?;
+        txn.open_table(ACCESSES)?;
+        txn.open_table(FAILURES)?;
+        txn.open_table(META)?;
+        txn.commit()?;
+        Ok(ThumbnailDatabase {
+            database,
+            size_limit,
+            pending_accesses: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn try_load(
+        &self,
+        source: &Source,
+        size: ThumbnailSize,
+    ) -> Result<Option<RgbaImage>, Box<dyn Error>> {
+        let path = path_key(&source.path);
+        let key = (&path[..], size_key(size));
+        let record = match self
+            .database
+            .begin_read()?
+            .open_table(THUMBNAILS)?
+            .get(key)?
+        {
+            Some(record) => record.value().to_vec(),
+            None => {
+                return Ok(None);
+            }
+        };
+        let (identity, png) = decode_record(&record).ok_or("corrupted record")?;
+        // An outdated thumbnail is replaced by the next save or removed by the vacuum
+        if identity != Identity::of(source) {
+            return Ok(None);
+        }
+        let accesses = {
+            let mut pending = self.pending_accesses.lock();
+            pending.push((path.clone(), key.1));
+            if pending.len() < ACCESS_BATCH {
+                None
+            } else {
+                Some(mem::take(&mut *pending))
+            }
+        };
+        if let Some(accesses) = accesses {
+            if let Err(e) = self.write_accesses(&accesses) {
+                debug!("Can't record the accesses to the thumbnails: {}", e);
+            }
+        }
+        Ok(Some(
+            image::load_from_memory_with_format(png, ImageFormat::Png)?.into_rgba8(),
+        ))
+    }
+
+    fn write_accesses(&self, accesses: &[(Vec<u8>, u8)]) -> Result<(), Box<dyn Error>> {
+        let mut txn = self.database.begin_write()?;
+        // Losing the access times in a crash doesn't harm the cache
+        txn.set_durability(Durability::None);
+        Tables::open(&txn)?.touch_all(accesses)?;
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+impl ThumbnailStore for ThumbnailDatabase {
+    fn load(&self, path: &Path, size: ThumbnailSize) -> Option<RgbaImage> {
+        let source = Source::new(path)?;
+        match self.try_load(&source, size) {
+            Ok(thumbnail) => thumbnail,
+            Err(e) => {
+                debug!("Can't load the thumbnail of {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn save(
+        &self,
+        path: &Path,
+        size: ThumbnailSize,
+        image: &RgbaImage,
+    ) -> Result<(), Box<dyn Error>> {
+        let source = match Source::new(path) {
+            Some(source) => source,
+            None => {
+                return Ok(());
+            }
+        };
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).encode(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ColorType::Rgba8,
+        )?;
+        let path = path_key(&source.path);
+        let key = (&path[..], size_key(size));
+        // The pending accesses are written first, so they count for the eviction
+        let accesses = mem::take(&mut *self.pending_accesses.lock());
+        let txn = self.database.begin_write()?;
+        {
+            let mut tables = Tables::open(&txn)?;
+            tables.touch_all(&accesses)?;
+            tables.remove(key)?;
+            let record = encode_record(Identity::of(&source), &png);
+            tables.thumbnails.insert(key, &record[..])?;
+            tables.touch(key)?;
+            tables.add_bytes(record.len() as i64)?;
+            while tables.bytes()? > self.size_limit {
+                let (path, size) = match tables.accesses.first()? {
+                    Some((oldest, _)) => {
+                        let (_, path, size) = oldest.value();
+                        (path.to_vec(), size)
+                    }
+                    None => {
+                        break;
+                    }
+                };
+                tables.remove((&path[..], size))?;
+            }
+            txn.open_table(FAILURES)?.remove(&path[..])?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn has_failed(&self, path: &Path) -> bool {
+        let source = match Source::new(path) {
+            Some(source) => source,
+            None => {
+                return false;
+            }
+        };
+        let failure = (|| -> Result<_, Box<dyn Error>> {
+            let failures = self.database.begin_read()?.open_table(FAILURES)?;
+            let failure = failures.get(&path_key(&source.path)[..])?;
+            Ok(failure.map(|failure| failure.value()))
+        })();
+        match failure {
+            Ok(Some((inode, mtime, size))) => {
+                Identity { inode, mtime, size } == Identity::of(&source)
+            }
+            Ok(None) => false,
+            Err(e) => {
+                debug!("Can't read the failures of {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    fn save_failure(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let source = match Source::new(path) {
+            Some(source) => source,
+            None => {
+                return Ok(());
+            }
+        };
+        let identity = Identity::of(&source);
+        let txn = self.database.begin_write()?;
+        txn.open_table(FAILURES)?.insert(
+            &path_key(&source.path)[..],
+            (identity.inode, identity.mtime, identity.size),
+        )?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn vacuum(&self) -> Result<usize, Box<dyn Error>> {
+        let mut stale = Vec::new();
+        let mut stale_failures = Vec::new();
+        {
+            let txn = self.database.begin_read()?;
+            for entry in txn.open_table(THUMBNAILS)?.iter()? {
+                let (key, record) = entry?;
+                let (path, size) = key.value();
+                let identity = decode_record(record.value()).map(|(identity, _)| identity);
+                if is_stale(path, identity) {
+                    stale.push((path.to_vec(), size));
+                }
+            }
+            for entry in txn.open_table(FAILURES)?.iter()? {
+                let (path, identity) = entry?;
+                let (inode, mtime, size) = identity.value();
+                if is_stale(path.value(), Some(Identity { inode, mtime, size })) {
+                    stale_failures.push(path.value().to_vec());
+                }
+            }
+        }
+        let txn = self.database.begin_write()?;
+        {
+            let mut tables = Tables::open(&txn)?;
+            for (path, size) in &stale {
+                tables.remove((&path[..], *size))?;
+            }
+            let mut failures = txn.open_table(FAILURES)?;
+            for path in &stale_failures {
+                failures.remove(&path[..])?;
+            }
+        }
+        txn.commit()?;
+        Ok(stale.len() + stale_failures.len())
+    }
+}
+
+impl Identity {
+    fn of(source: &Source) -> Identity {
+        Identity {
+            inode: source.inode,
+            mtime: source.mtime,
+            size: source.size,
+        }
+    }
+}
+
+/// The identity of the file at the canonical path `path`, `None` if it's gone.
+fn current_identity(path: &[u8]) -> Option<Identity> {
+    let path = path_from_key(path)?;
+    let source = Source::new(&path)?;
+    Some(Identity::of(&source)).filter(|_| path == source.path)
+}
+
+/// Whether what's recorded for `path` with `identity` has to be removed, a file that can't be
+/// found is kept until it's surely gone, it can be on an unmounted volume.
+fn is_stale(path: &[u8], identity: Option<Identity>) -> bool {
+    match current_identity(path) {
+        Some(current) => Some(current) != identity,
+        None => path_from_key(path).map_or(true, |path| is_gone(&path)),
+    }
+}
+
+/// The tables that hold the thumbnails, opened in a write transaction.
+struct Tables<'txn> {
+    thumbnails: Table<'txn, (&'static [u8], u8), &'static [u8]>,
+    last_accesses: Table<'txn, (&'static [u8], u8), u64>,
+    accesses: Table<'txn, (u64, &'static [u8], u8), ()>,
+    meta: Table<'txn, &'static str, u64>,
+}
+
+impl<'txn> Tables<'txn> {
+    fn open(txn: &'txn WriteTransaction) -> Result<Tables<'txn>, Box<dyn Error>> {
+        Ok(Tables {
+            thumbnails: txn.open_table(THUMBNAILS)?,
+            last_accesses: txn.open_table(LAST_ACCESSES)?,
+            accesses: txn.open_table(ACCESSES)?,
+            meta: txn.open_table(META)?,
+        })
+    }
+
+    /// Marks the thumbnail at `key` as the most recently used.
+    fn touch(&mut self, key: (&[u8], u8)) -> Result<(), Box<dyn Error>> {
+        let clock = self.meta("clock")? + 1;
+        self.meta.insert("clock", clock)?;
+        if let Some(access) = self.last_accesses.insert(key, clock)? {
+            self.accesses.remove((access.value(), key.0, key.1))?;
+        }
+        self.accesses.insert((clock, key.0, key.1), ())?;
+        Ok(())
+    }
+
+    /// Marks the thumbnails at `keys` as used in this order, skipping the ones that were removed.
+    fn touch_all(&mut self, keys: &[(Vec<u8>, u8)]) -> Result<(), Box<dyn Error>> {
+        for (path, size) in keys {
+            let key = (&path[..], *size);
+            if self.thumbnails.get(key)?.is_some() {
+                self.touch(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, key: (&[u8], u8)) -> Result<(), Box<dyn Error>> {
+        let size = match self.thumbnails.remove(key)? {
+            Some(record) => record.value().len() as i64,
+            None => {
+                return Ok(());
+            }
+        };
+        if let Some(access) = self.last_accesses.remove(key)? {
+            self.accesses.remove((access.value(), key.0, key.1))?;
+        }
+        self.add_bytes(-size)
+    }
+
+    /// The size of all the records.
+    fn bytes(&self) -> Result<u64, Box<dyn Error>> {
+        self.meta("bytes")
+    }
+
+    fn add_bytes(&mut self, delta: i64) -> Result<(), Box<dyn Error>> {
+        let bytes = (self.bytes()? as i64 + delta).max(0) as u64;
+        self.meta.insert("bytes", bytes)?;
+        Ok(())
+    }
+
+    fn meta(&self, key: &str) -> Result<u64, Box<dyn Error>> {
+        Ok(self.meta.get(key)?.map_or(0, |value| value.value()))
+    }
+}
+
+fn encode_record(identity: Identity, png: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_SIZE + png.len());
+    for value in &[identity.inode, identity.mtime, identity.size] {
+        record.extend_from_slice(&value.to_le_bytes());
+    }
+    record.extend_from_slice(png);
+    record
+}
+
+fn decode_record(record: &[u8]) -> Option<(Identity, &[u8])> {
+    if record.len() < HEADER_SIZE {
+        return None;
+    }
+    let field = |i: usize| u64::from_le_bytes(record[i * 8..i * 8 + 8].try_into().unwrap());
+    let identity = Identity {
+        inode: field(0),
+        mtime: field(1),
+        size: field(2),
+    };
+    Some((identity, &record[HEADER_SIZE..]))
+}
+
+fn size_key(size: ThumbnailSize) -> u8 {
+    match size {
+        ThumbnailSize::Normal => 0,
+        ThumbnailSize::Large => 1,
+        ThumbnailSize::XLarge => 2,
+        ThumbnailSize::XXLarge => 3,
+    }
+}
+
+#[cfg(unix)]
+fn path_key(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_key(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_key(key: &[u8]) -> Option<std::path::PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(std::ffi::OsStr::from_bytes(key).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_key(key: &[u8]) -> Option<std::path::PathBuf> {
+    std::str::from_utf8(key).ok().map(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use redb::ReadableTableMetadata;
+    use std::path::PathBuf;
+
+    fn image(shade: u8) -> RgbaImage {
+        RgbaImage::from_pixel(4, 4, Rgba([shade, shade, shade, 255]))
+    }
+
+    fn create(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn bytes(database: &ThumbnailDatabase) -> u64 {
+        let txn = database.database.begin_read().unwrap();
+        let meta = txn.open_table(META).unwrap();
+        let bytes = meta.get("bytes").unwrap().map_or(0, |value| value.value());
+        bytes
+    }
+
+    /// The size of all the records, counted from the thumbnails table.
+    fn records_size(database: &ThumbnailDatabase) -> u64 {
+        let txn = database.database.begin_read().unwrap();
+        let thumbnails = txn.open_table(THUMBNAILS).unwrap();
+        let size = thumbnails
+            .iter()
+            .unwrap()
+            .map(|entry| entry.unwrap().1.value().len() as u64)
+            .sum();
+        size
+    }
+
+    fn records(database: &ThumbnailDatabase) -> u64 {
+        let txn = database.database.begin_read().unwrap();
+        let thumbnails = txn.open_table(THUMBNAILS).unwrap();
+        thumbnails.len().unwrap()
+    }
+
+    #[test]
+    fn eviction_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut database = ThumbnailDatabase::open(&dir.path().join("thumbnails.redb")).unwrap();
+        let a = create(dir.path(), "a", "a");
+        let b = create(dir.path(), "b", "b");
+        let c = create(dir.path(), "c", "c");
+        database.save(&a, ThumbnailSize::Normal, &image(0)).unwrap();
+        // Room for two records of the same size
+        database.size_limit = bytes(&database) * 5 / 2;
+        database.save(&b, ThumbnailSize::Normal, &image(0)).unwrap();
+        assert!(database.load(&a, ThumbnailSize::Normal).is_some());
+        // The pending access to `a` makes `b` the least recently used
+        database.save(&c, ThumbnailSize::Normal, &image(0)).unwrap();
+        assert!(database.load(&a, ThumbnailSize::Normal).is_some());
+        assert!(database.load(&b, ThumbnailSize::Normal).is_none());
+        assert!(database.load(&c, ThumbnailSize::Normal).is_some());
+        assert!(bytes(&database) <= database.size_limit);
+
+        // Every size is evicted on its own
+        database.save(&c, ThumbnailSize::Large, &image(0)).unwrap();
+        assert!(database.load(&a, ThumbnailSize::Normal).is_none());
+        assert!(database.load(&c, ThumbnailSize::Normal).is_some());
+        assert!(database.load(&c, ThumbnailSize::Large).is_some());
+    }
+
+    #[test]
+    fn bytes_accounting() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = ThumbnailDatabase::open(&dir.path().join("thumbnails.redb")).unwrap();
+        let a = create(dir.path(), "a", "a");
+        let b = create(dir.path(), "b", "b");
+        assert_eq!(bytes(&database), 0);
+        database.save(&a, ThumbnailSize::Normal, &image(0)).unwrap();
+        database.save(&b, ThumbnailSize::Normal, &image(0)).unwrap();
+        assert_eq!(bytes(&database), records_size(&database));
+
+        // Replacing a thumbnail counts only the new record
+        let large = RgbaImage::from_fn(64, 64, |x, y| Rgba([x as u8 * 4, y as u8 * 4, 0, 255]));
+        database.save(&a, ThumbnailSize::Normal, &large).unwrap();
+        assert_eq!(records(&database), 2);
+        assert_eq!(bytes(&database), records_size(&database));
+
+        fs::remove_file(&a).unwrap();
+        assert_eq!(database.vacuum().unwrap(), 1);
+        assert_eq!(bytes(&database), records_size(&database));
+        fs::remove_file(&b).unwrap();
+        assert_eq!(database.vacuum().unwrap(), 1);
+        assert_eq!(bytes(&database), 0);
+    }
+
+    #[test]
+    fn vacuum() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = ThumbnailDatabase::open(&dir.path().join("thumbnails.redb")).unwrap();
+        let kept = create(dir.path(), "kept", "kept");
+        let changed = create(dir.path(), "changed", "changed");
+        let gone = create(dir.path(), "gone", "gone");
+        fs::create_dir(dir.path().join("volume")).unwrap();
+        let unmounted = create(&dir.path().join("volume"), "unmounted", "unmounted");
+        for path in &[&kept, &changed, &gone, &unmounted] {
+            database
+                .save(path, ThumbnailSize::Normal, &image(0))
+                .unwrap();
+        }
+        let failed = create(dir.path(), "failed", "failed");
+        database.save_failure(&failed).unwrap();
+        assert_eq!(database.vacuum().unwrap(), 0);
+
+        fs::write(&changed, "changed again").unwrap();
+        // The outdated thumbnail isn't returned, but only the vacuum removes it
+        assert!(database.load(&changed, ThumbnailSize::Normal).is_none());
+        assert_eq!(records(&database), 4);
+        fs::remove_file(&gone).unwrap();
+        // The whole directory could be on an unmounted volume
+        fs::remove_dir_all(dir.path().join("volume")).unwrap();
+        fs::remove_file(&failed).unwrap();
+        assert_eq!(database.vacuum().unwrap(), 3);
+        assert_eq!(records(&database), 2);
+        assert!(database.load(&kept, ThumbnailSize::Normal).is_some());
+        let txn = database.database.begin_read().unwrap();
+        assert!(txn.open_table(FAILURES).unwrap().is_empty().unwrap());
+        assert!(txn
+            .open_table(THUMBNAILS)
+            .unwrap()
+            .get((&path_key(&dir.path().join("volume/unmounted"))[..], 0))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = ThumbnailDatabase::open(&dir.path().join("thumbnails.redb")).unwrap();
+        let path = create(dir.path(), "broken", "broken");
+        assert!(!database.has_failed(&path));
+        database.save_failure(&path).unwrap();
+        assert!(database.has_failed(&path));
+
+        // A new version of the file is tried again
+        fs::write(&path, "fixed now").unwrap();
+        assert!(!database.has_failed(&path));
+        database.save_failure(&path).unwrap();
+        assert!(database.has_failed(&path));
+
+        // A thumbnail replaces the failure
+        database
+            .save(&path, ThumbnailSize::Normal, &image(0))
+            .unwrap();
+        assert!(!database.has_failed(&path));
+
+        let missing = dir.path().join("missing");
+        database.save_failure(&missing).unwrap();
+        assert!(!database.has_failed(&missing));
+    }
+}
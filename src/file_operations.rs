@@ -0,0 +1,670 @@
+use filetime::FileTime;
+use log::warn;
+use std::{
+    ffi::OsStr,
+    fmt,
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    Copy,
+    Move,
+}
+
+/// The state of a running operation, it's reported after every processed chunk or item.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub total_items: usize,
+    pub done_items: usize,
+    pub total_bytes: u64,
+    pub done_bytes: u64,
+    pub current: PathBuf,
+}
+
+/// Size of a tree of files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TreeSize {
+    pub items: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum OperationError {
+    /// The operation stopped at this error.
+    Io(io::Error),
+    /// The operation processed everything it could, these entries failed.
+    Partial(Vec<(PathBuf, io::Error)>),
+}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationError::Io(e) => write!(f, "{}", e),
+            OperationError::Partial(failures) => {
+                write!(f, "{} entries failed", failures.len())
+            }
+        }
+    }
+}
+
+impl From<io::Error> for OperationError {
+    fn from(e: io::Error) -> OperationError {
+        OperationError::Io(e)
+    }
+}
+
+/// Copies or moves every path in `sources` into the `destination` directory.
+///
+/// Directories are copied recursively, symbolic links are copied as links and the modification
+/// time and permissions of every entry are preserved. Entries that already exist in the
+/// destination are not overwritten, the copy receives a new name instead. A move that crosses
+/// filesystems falls back to copying and then deleting the sources.
+pub fn transfer(
+    mode: TransferMode,
+    sources: &[PathBuf],
+    destination: &Path,
+    mut report: impl FnMut(&Progress),
+) -> Result<(), io::Error> {
+    let mut progress = Progress::default();
+    let mut sizes = Vec::with_capacity(sources.len());
+    for source in sources {
+        // What can't be read now fails when it's copied, after everything before it
+        let size = measure_all(std::slice::from_ref(source));
+        progress.total_items += size.items;
+        progress.total_bytes += size.bytes;
+        sizes.push(size);
+    }
+    report(&progress);
+    for (source, size) in sources.iter().zip(sizes) {
+        let file_name = source.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no file name", source.display()),
+            )
+        })?;
+        if mode == TransferMode::Move && source.parent() == Some(destination) {
+            // Moving into the same directory is a no-op
+            skip(&mut progress, size, &mut report);
+            continue;
+        }
+        if destination.starts_with(source) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} can't be copied into itself", source.display()),
+            ));
+        }
+        let target = unique_destination(destination, file_name);
+        match mode {
+            TransferMode::Copy => copy_new_entry(source, &target, &mut progress, &mut report)?,
+            TransferMode::Move => match fs::rename(source, &target) {
+                Ok(()) => skip(&mut progress, size, &mut report),
+                Err(e) if is_cross_device(&e) => {
+                    copy_new_entry(source, &target, &mut progress, &mut report)?;
+                    remove_entry(source)?;
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Moves `source` to exactly `target`, copying and deleting it when they are in different
+/// filesystems.
+pub fn move_entry(source: &Path, target: &Path) -> Result<(), io::Error> {
+    match fs::rename(source, target) {
+        Err(e) if is_cross_device(&e) => {
+            copy_new_entry(source, target, &mut Progress::default(), &mut |_| {})?;
+            remove_entry(source)
+        }
+        res => res,
+    }
+}
+
+/// Returns a path inside `dir` named after `file_name` that doesn't exist yet, appending a
+/// counter to the file stem when needed, like in `photo (2).png`.
+pub fn unique_destination(dir: &Path, file_name: &OsStr) -> PathBuf {
+    let path = dir.join(file_name);
+    if path.symlink_metadata().is_err() {
+        return path;
+    }
+    let name = Path::new(file_name);
+    let stem = name
+        .file_stem()
+        .unwrap_or(file_name)
+        .to_string_lossy()
+        .into_owned();
+    let extension = name.extension().map(|e| e.to_string_lossy().into_owned());
+    let mut count = 2;
+    loop {
+        let candidate = match &extension {
+            Some(extension) => format!("{} ({}).{}", stem, count, extension),
+            None => format!("{} ({})", stem, count),
+        };
+        let path = dir.join(candidate);
+        if path.symlink_metadata().is_err() {
+            return path;
+        }
+        count += 1;
+    }
+}
+
+/// Formats a size in bytes using binary units, like `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Permanently deletes every path in `paths`, directories are deleted recursively without
+/// following links.
+///
+/// An entry that fails doesn't stop the operation, everything else is deleted and the failures
+/// are returned together. The directories that contain a failed entry are kept.
+pub fn delete(paths: &[PathBuf], mut report: impl FnMut(&Progress)) -> Result<(), OperationError> {
+    let size = measure_all(paths);
+    let mut progress = Progress {
+        total_items: size.items,
+        total_bytes: size.bytes,
+        ..Progress::default()
+    };
+    report(&progress);
+    let mut failures = Vec::new();
+    for path in paths {
+        delete_entry(path, &mut progress, &mut report, &mut failures);
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(OperationError::Partial(failures))
+    }
+}
+
+/// Measures every path in `paths`, the entries that can't be read are left out.
+pub fn measure_all(paths: &[PathBuf]) -> TreeSize {
+    let mut size = TreeSize::default();
+    for path in paths {
+        let meta = match path.symlink_metadata() {
+            Ok(meta) => meta,
+            Err(_) => {
+                continue;
+            }
+        };
+        size.items += 1;
+        if meta.is_dir() {
+            if let Ok(entries) = path.read_dir() {
+                let children: Vec<PathBuf> =
+                    entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+                let children_size = measure_all(&children);
+                size.items += children_size.items;
+                size.bytes += children_size.bytes;
+            }
+        } else if meta.is_file() {
+            size.bytes += meta.len();
+        }
+    }
+    size
+}
+
+/// Returns whether `path` was deleted, its failures and the ones of its children are recorded.
+fn delete_entry(
+    path: &Path,
+    progress: &mut Progress,
+    report: &mut impl FnMut(&Progress),
+    failures: &mut Vec<(PathBuf, io::Error)>,
+) -> bool {
+    progress.current = path.to_path_buf();
+    let meta = match path.symlink_metadata() {
+        Ok(meta) => meta,
+        Err(e) => {
+            failures.push((path.to_path_buf(), e));
+            return false;
+        }
+    };
+    let res = if meta.is_dir() {
+        let mut complete = true;
+        match path.read_dir() {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => {
+                            complete &= delete_entry(&entry.path(), progress, report, failures);
+                        }
+                        Err(e) => {
+                            failures.push((path.to_path_buf(), e));
+                            complete = false;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                failures.push((path.to_path_buf(), e));
+                return false;
+            }
+        }
+        if !complete {
+            // The failure inside was already recorded, this one would only be noise
+            return false;
+        }
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match res {
+        Ok(()) => {
+            progress.done_items += 1;
+            if meta.is_file() {
+                progress.done_bytes += meta.len();
+            }
+            report(progress);
+            true
+        }
+        Err(e) => {
+            failures.push((path.to_path_buf(), e));
+            false
+        }
+    }
+}
+
+fn skip(progress: &mut Progress, size: TreeSize, report: &mut impl FnMut(&Progress)) {
+    progress.done_items += size.items;
+    progress.done_bytes += size.bytes;
+    report(progress);
+}
+
+/// Copies `source` to `target`, which must not exist yet. When the copy fails halfway what was
+/// already copied is removed.
+fn copy_new_entry(
+    source: &Path,
+    target: &Path,
+    progress: &mut Progress,
+    report: &mut impl FnMut(&Progress),
+) -> Result<(), io::Error> {
+    let existed = target.symlink_metadata().is_ok();
+    let res = copy_entry(source, target, progress, report);
+    if res.is_err() && !existed && target.symlink_metadata().is_ok() {
+        if let Err(e) = remove_entry(target) {
+            warn!("Can't remove the partial copy {}: {}", target.display(), e);
+        }
+    }
+    res
+}
+
+fn copy_entry(
+    source: &Path,
+    target: &Path,
+    progress: &mut Progress,
+    report: &mut impl FnMut(&Progress),
+) -> Result<(), io::Error> {
+    progress.current = source.to_path_buf();
+    let meta = source.symlink_metadata()?;
+    let file_type = meta.file_type();
+    if file_type.is_dir() {
+        fs::create_dir(target)?;
+        for entry in source.read_dir()? {
+            let entry = entry?;
+            copy_entry(
+                &entry.path(),
+                &target.join(entry.file_name()),
+                progress,
+                report,
+            )?;
+        }
+        // Applied after the children, creating them updates the modification time
+        preserve_metadata(target, &meta)?;
+    } else if file_type.is_symlink() {
+        copy_symlink(source, target)?;
+        let mtime = FileTime::from_last_modification_time(&meta);
+        filetime::set_symlink_file_times(target, mtime, mtime)?;
+    } else if file_type.is_file() {
+        copy_file(source, target, progress, report)?;
+        preserve_metadata(target, &meta)?;
+    } else {
+        // Reading a FIFO or a device could block forever or never end
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is a special file, it can't be copied", source.display()),
+        ));
+    }
+    progress.done_items += 1;
+    report(progress);
+    Ok(())
+}
+
+fn copy_file(
+    source: &Path,
+    target: &Path,
+    progress: &mut Progress,
+    report: &mut impl FnMut(&Progress),
+) -> Result<(), io::Error> {
+    let mut reader = File::open(source)?;
+    let mut writer = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..read])?;
+        progress.done_bytes += read as u64;
+        report(progress);
+    }
+    writer.sync_all()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, target: &Path) -> Result<(), io::Error> {
+    std::os::unix::fs::symlink(fs::read_link(source)?, target)
+}
+
+#[cfg(windows)]
+fn copy_symlink(source: &Path, target: &Path) -> Result<(), io::Error> {
+    let link = fs::read_link(source)?;
+    if source.is_dir() {
+        std::os::windows::fs::symlink_dir(link, target)
+    } else {
+        std::os::windows::fs::symlink_file(link, target)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn copy_symlink(source: &Path, target: &Path) -> Result<(), io::Error> {
+    // Without link support the content is copied
+    fs::copy(source, target).map(|_| ())
+}
+
+fn preserve_metadata(target: &Path, meta: &Metadata) -> Result<(), io::Error> {
+    fs::set_permissions(target, meta.permissions())?;
+    filetime::set_file_mtime(target, FileTime::from_last_modification_time(meta))
+}
+
+/// Removes a file, link or whole directory, links are never followed.
+pub fn remove_entry(path: &Path) -> Result<(), io::Error> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(unix)]
+fn is_cross_device(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(not(unix))]
+fn is_cross_device(e: &io::Error) -> bool {
+    // ERROR_NOT_SAME_DEVICE
+    e.raw_os_error() == Some(17)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, content: &str) {
+        fs::write(path, content).unwrap();
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    /// The permissions can't keep root out.
+    #[cfg(unix)]
+    fn is_root() -> bool {
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    /// A source directory with a tree inside and an empty destination.
+    fn setup() -> (TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let destination = dir.path().join("destination");
+        fs::create_dir_all(source.join("tree/sub")).unwrap();
+        fs::create_dir(&destination).unwrap();
+        write(&source.join("tree/a.txt"), "a");
+        write(&source.join("tree/sub/b.txt"), "bb");
+        (dir, source, destination)
+    }
+
+    #[test]
+    fn copy() {
+        let (_dir, source, destination) = setup();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", source.join("tree/link")).unwrap();
+        let mut last = Progress::default();
+        transfer(
+            TransferMode::Copy,
+            &[source.join("tree")],
+            &destination,
+            |progress| last = progress.clone(),
+        )
+        .unwrap();
+        assert_eq!(read(&destination.join("tree/a.txt")), "a");
+        assert_eq!(read(&destination.join("tree/sub/b.txt")), "bb");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(destination.join("tree/link")).unwrap(),
+            Path::new("a.txt")
+        );
+        assert_eq!(last.done_items, last.total_items);
+        assert_eq!((last.done_bytes, last.total_bytes), (3, 3));
+        assert!(source.join("tree/a.txt").exists());
+
+        // A copy never overwrites, it gets a new name
+        transfer(
+            TransferMode::Copy,
+            &[source.join("tree/a.txt")],
+            &destination.join("tree"),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(read(&destination.join("tree/a (2).txt")), "a");
+    }
+
+    #[test]
+    fn moves() {
+        let (_dir, source, destination) = setup();
+        transfer(
+            TransferMode::Move,
+            &[source.join("tree/a.txt")],
+            &source.join("tree"),
+            |_| {},
+        )
+        .unwrap();
+        // Moving into the same directory leaves the entry as it is
+        assert_eq!(read(&source.join("tree/a.txt")), "a");
+        assert!(!source.join("tree/a (2).txt").exists());
+
+        let error = transfer(
+            TransferMode::Move,
+            &[source.join("tree")],
+            &source.join("tree/sub"),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(source.join("tree/sub/b.txt").exists());
+
+        transfer(
+            TransferMode::Move,
+            &[source.join("tree")],
+            &destination,
+            |_| {},
+        )
+        .unwrap();
+        assert!(!source.join("tree").exists());
+        assert_eq!(read(&destination.join("tree/sub/b.txt")), "bb");
+
+        move_entry(&destination.join("tree/a.txt"), &source.join("renamed.txt")).unwrap();
+        assert!(!destination.join("tree/a.txt").exists());
+        assert_eq!(read(&source.join("renamed.txt")), "a");
+    }
+
+    #[test]
+    fn unique_destinations() {
+        let dir = tempfile::tempdir().unwrap();
+        let unique = |name: &str| {
+            let path = unique_destination(dir.path(), OsStr::new(name));
+            write(&path, "");
+            path.file_name().unwrap().to_string_lossy().into_owned()
+        };
+        assert_eq!(unique("photo.png"), "photo.png");
+        assert_eq!(unique("photo.png"), "photo (2).png");
+        assert_eq!(unique("photo.png"), "photo (3).png");
+        assert_eq!(unique("notes"), "notes");
+        assert_eq!(unique("notes"), "notes (2)");
+        assert_eq!(unique(".bashrc"), ".bashrc");
+        assert_eq!(unique(".bashrc"), ".bashrc (2)");
+        assert_eq!(unique("archive.tar.gz"), "archive.tar.gz");
+        assert_eq!(unique("archive.tar.gz"), "archive.tar (2).gz");
+    }
+
+    #[test]
+    fn metadata() {
+        let (_dir, source, destination) = setup();
+        let mtime = FileTime::from_unix_time(1_000_000_000, 0);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(source.join("tree/a.txt"), fs::Permissions::from_mode(0o640))
+                .unwrap();
+            std::os::unix::fs::symlink("a.txt", source.join("tree/link")).unwrap();
+            filetime::set_symlink_file_times(source.join("tree/link"), mtime, mtime).unwrap();
+        }
+        filetime::set_file_mtime(source.join("tree/a.txt"), mtime).unwrap();
+        filetime::set_file_mtime(source.join("tree"), mtime).unwrap();
+        transfer(
+            TransferMode::Copy,
+            &[source.join("tree")],
+            &destination,
+            |_| {},
+        )
+        .unwrap();
+        let modified =
+            |path: &Path| FileTime::from_last_modification_time(&path.symlink_metadata().unwrap());
+        assert_eq!(modified(&destination.join("tree/a.txt")), mtime);
+        assert_eq!(modified(&destination.join("tree")), mtime);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = destination
+                .join("tree/a.txt")
+                .metadata()
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o640);
+            assert_eq!(modified(&destination.join("tree/link")), mtime);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files() {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+        let (_dir, source, destination) = setup();
+        let fifo = source.join("tree/sub/fifo");
+        let fifo_path = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo_path.as_ptr(), 0o600) }, 0);
+        write(&source.join("first.txt"), "first");
+        let error = transfer(
+            TransferMode::Copy,
+            &[source.join("first.txt"), source.join("tree")],
+            &destination,
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        // The entries before are copied, the partial copy is removed
+        assert_eq!(read(&destination.join("first.txt")), "first");
+        assert!(!destination.join("tree").exists());
+        assert!(fifo.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_entries() {
+        use std::os::unix::fs::PermissionsExt;
+        if is_root() {
+            return;
+        }
+        let (_dir, source, destination) = setup();
+        let locked = source.join("tree/sub");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        write(&source.join("first.txt"), "first");
+        let mut first = None;
+        let res = transfer(
+            TransferMode::Copy,
+            &[source.join("first.txt"), source.join("tree")],
+            &destination,
+            |progress| {
+                first.get_or_insert(progress.clone());
+            },
+        );
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        // The unreadable directory counts as an item without content
+        let first = first.unwrap();
+        assert_eq!((first.total_items, first.total_bytes), (4, 6));
+        assert_eq!(read(&destination.join("first.txt")), "first");
+        assert!(!destination.join("tree").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn partial_delete() {
+        use std::os::unix::fs::PermissionsExt;
+        if is_root() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let locked = root.join("locked");
+        fs::create_dir_all(&locked).unwrap();
+        fs::create_dir(root.join("sibling")).unwrap();
+        write(&root.join("a.txt"), "a");
+        write(&root.join("sibling/b.txt"), "b");
+        write(&locked.join("x"), "x");
+        write(&locked.join("y"), "y");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+        let res = delete(std::slice::from_ref(&root), |_| {});
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut failed: Vec<PathBuf> = match res {
+            Err(OperationError::Partial(failures)) => {
+                failures.into_iter().map(|(path, _)| path).collect()
+            }
+            res => panic!("unexpected result {:?}", res),
+        };
+        failed.sort();
+        // Only the entries that failed themselves, not the directories that contain them
+        assert_eq!(failed, vec![locked.join("x"), locked.join("y")]);
+        assert!(!root.join("a.txt").exists());
+        assert!(!root.join("sibling").exists());
+        assert!(locked.join("x").exists());
+        assert!(root.is_dir());
+    }
+}
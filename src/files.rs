@@ -1,26 +1,132 @@
-use crate::file_content_info::FileContentInfo;
+use crate::{
+    file_content_info::{FileContentInfo, Generation, SCHEDULER},
+    trash::{self, TrashItem},
+};
+use log::error;
 use mime::Mime;
+use parking_lot::Mutex;
 use std::{
     cmp::Ordering,
+    collections::HashSet,
+    fmt,
     fs::FileType,
     io,
     path::{Path, PathBuf},
-    sync::Arc
+    sync::Arc,
 };
-use parking_lot::Mutex;
 
 #[derive(Debug, Clone)]
 pub struct FileInfo {
+    /// Where the entry is read from, inside the trash for trashed entries.
+    pub path: PathBuf,
     pub file_name: String,
     pub file_type: FileType,
     pub content_info: Arc<Mutex<Option<FileContentInfo>>>,
-    pub extension_mime: Option<Mime>
+    pub extension_mime: Option<Mime>,
+    /// Set for the entries of the trash place.
+    pub trashed: Option<TrashItem>,
+}
+
+/// A change observed on disk inside a watched directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirectoryChange {
+    Created(PathBuf),
+    Removed(PathBuf),
+    /// The entry may have changed in any way, it's reloaded from disk.
+    Modified(PathBuf),
+    Renamed(PathBuf, PathBuf),
+}
+
+impl DirectoryChange {
+    /// The directory this change has to be routed to.
+    pub fn parent(&self) -> Option<&Path> {
+        match self {
+            DirectoryChange::Created(path)
+            | DirectoryChange::Removed(path)
+            | DirectoryChange::Modified(path)
+            | DirectoryChange::Renamed(_, path) => path.parent(),
+        }
+    }
+
+    /// Merges a burst of changes, only the last change of every path is kept because
+    /// `Directory::apply_changes` resyncs entries instead of trusting the event kind.
+    pub fn coalesce(changes: impl IntoIterator<Item = DirectoryChange>) -> Vec<DirectoryChange> {
+        let mut coalesced: Vec<DirectoryChange> = Vec::new();
+        for change in changes {
+            match &change {
+                DirectoryChange::Created(path)
+                | DirectoryChange::Removed(path)
+                | DirectoryChange::Modified(path) => {
+                    // Renames are kept as barriers, what happened before them must be replayed
+                    if let Some(index) = coalesced.iter().rposition(|c| c.touches(path)) {
+                        if !matches!(coalesced[index], DirectoryChange::Renamed(..)) {
+                            coalesced.remove(index);
+                        }
+                    }
+                }
+                DirectoryChange::Renamed(from, to) => {
+                    // The backend reports the halves of a rename before the rename itself
+                    coalesced.retain(|c| match c {
+                        DirectoryChange::Removed(path) => path != from,
+                        DirectoryChange::Created(path) | DirectoryChange::Modified(path) => {
+                            path != to
+                        }
+                        _ => true,
+                    });
+                }
+            }
+            coalesced.push(change);
+        }
+        coalesced
+    }
+
+    fn touches(&self, path: &Path) -> bool {
+        match self {
+            DirectoryChange::Created(p)
+            | DirectoryChange::Removed(p)
+            | DirectoryChange::Modified(p) => p == path,
+            DirectoryChange::Renamed(from, to) => from == path || to == path,
+        }
+    }
+}
+
+/// The effect of a `DirectoryChange` over the indices of the directory entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryChange {
+    Inserted(usize),
+    Removed(usize),
+    Updated(usize),
+}
+
+/// Called from the content info threads with the path of an entry whose content info is ready.
+#[derive(Clone)]
+pub struct ContentInfoListener(Arc<Mutex<Box<dyn Fn(&Path) + Send>>>);
+
+impl ContentInfoListener {
+    pub fn new(listener: impl Fn(&Path) + Send + 'static) -> ContentInfoListener {
+        ContentInfoListener(Arc::new(Mutex::new(Box::new(listener))))
+    }
+}
+
+impl fmt::Debug for ContentInfoListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ContentInfoListener")
+    }
+}
+
+/// How the content info jobs of the entries of a listing are submitted.
+#[derive(Debug, Clone)]
+struct Jobs {
+    generation: Generation,
+    listener: Option<ContentInfoListener>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Directory {
     files: Vec<FileInfo>,
     path: PathBuf,
+    /// The jobs of the current listing.
+    jobs: Jobs,
 }
 
 impl PartialEq for Directory {
@@ -34,48 +140,212 @@ impl Directory {
         let mut dir = Self {
             files: Vec::new(),
             path: path.as_ref().to_path_buf(),
+            jobs: Jobs {
+                generation: SCHEDULER.generation(),
+                listener: None,
+            },
         };
         dir.change_path(path)?;
         Ok(dir)
     }
 
     fn change_path(&mut self, path: impl AsRef<Path>) -> Result<(), io::Error> {
+        if trash::is_trash_place(path.as_ref()) {
+            return self.show_trash();
+        }
         let path = path.as_ref().canonicalize()?;
-        self.files.clear();
-        for item in path.read_dir()? {
-            let item = item?;
-            let meta = item.metadata()?;
-            let file_type = meta.file_type();
-            let file_name = item.file_name().to_string_lossy().into_owned();
-            if file_name.starts_with(".") {
-                continue;
+        // Nothing is replaced until the new directory is fully read
+        let jobs = self.next_jobs();
+        let files = read_entries(&path, &jobs);
+        if files.is_err() {
+            SCHEDULER.cancel(jobs.generation);
+        }
+        let mut files = files?;
+        files.sort_unstable_by(compare_entries);
+        self.replace_jobs(jobs);
+        self.files = files;
+        self.path = path;
+        Ok(())
+    }
+
+    /// Cancels the pending content info jobs of the listing.
+    pub fn cancel_jobs(&self) {
+        SCHEDULER.cancel(self.jobs.generation);
+    }
+
+    /// Sets who is told about the content info of the entries read from now on.
+    pub fn set_content_info_listener(&mut self, listener: ContentInfoListener) {
+        self.jobs.listener = Some(listener);
+    }
+
+    /// Makes the content info of the entries at `indices` be computed before the other ones.
+    pub fn set_visible(&self, indices: impl IntoIterator<Item = usize>) {
+        let paths = indices
+            .into_iter()
+            .filter_map(|index| self.files.get(index))
+            .map(|info| info.path.clone());
+        SCHEDULER.set_visible(self.jobs.generation, paths);
+    }
+
+    /// The jobs of a new listing.
+    fn next_jobs(&self) -> Jobs {
+        Jobs {
+            generation: SCHEDULER.generation(),
+            listener: self.jobs.listener.clone(),
+        }
+    }
+
+    fn replace_jobs(&mut self, jobs: Jobs) {
+        SCHEDULER.cancel(self.jobs.generation);
+        self.jobs = jobs;
+    }
+
+    /// Shows the items of every trash under their original names.
+    fn show_trash(&mut self) -> Result<(), io::Error> {
+        let jobs = self.next_jobs();
+        let files = read_trash(&jobs);
+        if files.is_err() {
+            SCHEDULER.cancel(jobs.generation);
+        }
+        let mut files = files?;
+        files.sort_unstable_by(compare_entries);
+        self.replace_jobs(jobs);
+        self.files = files;
+        self.path = PathBuf::from(trash::TRASH_PLACE);
+        Ok(())
+    }
+
+    pub fn is_trash(&self) -> bool {
+        trash::is_trash_place(&self.path)
+    }
+
+    /// The directories that have to be watched to notice the changes of this one, the `files`
+    /// directory of every trash for the trash place.
+    pub fn watch_paths(&self) -> Vec<PathBuf> {
+        if self.is_trash() {
+            trash::trash_dirs()
+                .into_iter()
+                .map(|trash| trash.join("files"))
+                .collect()
+        } else {
+            vec![self.path.clone()]
+        }
+    }
+
+    /// Reads again the whole content of the directory.
+    pub fn reload(&mut self) -> Result<(), io::Error> {
+        self.change_path(self.path.clone())
+    }
+
+    /// Applies changes observed on disk, returning the resulting index-level changes in the
+    /// order they have to be replayed.
+    pub fn apply_changes(&mut self, changes: &[DirectoryChange]) -> Vec<EntryChange> {
+        let mut entry_changes = Vec::new();
+        for change in changes {
+            match change {
+                DirectoryChange::Created(path) | DirectoryChange::Modified(path) => {
+                    self.resync(path, &mut entry_changes);
+                }
+                DirectoryChange::Removed(path) => {
+                    if let Some(index) = self.index_of(path) {
+                        self.files.remove(index);
+                        entry_changes.push(EntryChange::Removed(index));
+                    }
+                }
+                DirectoryChange::Renamed(from, to) => {
+                    let index = match self.index_of(from) {
+                        Some(index) => index,
+                        None => {
+                            self.resync(to, &mut entry_changes);
+                            continue;
+                        }
+                    };
+                    let mut info = self.files.remove(index);
+                    entry_changes.push(EntryChange::Removed(index));
+                    if let Some(index) = self.index_of(to) {
+                        self.files.remove(index);
+                        entry_changes.push(EntryChange::Removed(index));
+                    }
+                    match to.file_name().map(|n| n.to_string_lossy().into_owned()) {
+                        Some(file_name) if !file_name.starts_with('.') => {
+                            // Keep the already computed content info, only the name changed
+                            info.file_name = file_name;
+                            info.path = to.clone();
+                            info.extension_mime = mime_guess::from_path(to).first();
+                            entry_changes.push(EntryChange::Inserted(self.insert(info)));
+                        }
+                        _ => {}
+                    }
+                }
             }
-            let content_info = Arc::new(Mutex::new(None));
-            //let content_info = crate::file_content_info::for_path(item.path(), 48).unwrap();
-            if file_type.is_file() {
-                let path = item.path();
-                let content_info = content_info.clone();
-                rayon::spawn(move || {
-                    let done_content_info = crate::file_content_info::for_path(path, 48).unwrap();
-                    content_info.lock().replace(done_content_info);
-                });
+        }
+        entry_changes
+    }
+
+    /// Reloads the entry at `path`, inserting, replacing or removing it to match the disk.
+    fn resync(&mut self, path: &Path, entry_changes: &mut Vec<EntryChange>) {
+        if path.parent() != Some(self.path.as_path()) {
+            return;
+        }
+        let info = match load_entry(path, &self.jobs) {
+            Ok(info) => info,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    error!("Failed to read {}: {}", path.display(), e);
+                }
+                None
             }
-            let info = FileInfo {
-                file_name,
-                file_type,
-                content_info: content_info,
-                extension_mime: mime_guess::from_path(item.path()).first()
-            };
-            self.files.push(info);
+        };
+        let old_index = self.index_of(path);
+        match (old_index, info) {
+            (Some(index), Some(info)) => {
+                self.files.remove(index);
+                let new_index = self.insert(info);
+                if new_index == index {
+                    entry_changes.push(EntryChange::Updated(index));
+                } else {
+                    entry_changes.push(EntryChange::Removed(index));
+                    entry_changes.push(EntryChange::Inserted(new_index));
+                }
+            }
+            (Some(index), None) => {
+                self.files.remove(index);
+                entry_changes.push(EntryChange::Removed(index));
+            }
+            (None, Some(info)) => {
+                entry_changes.push(EntryChange::Inserted(self.insert(info)));
+            }
+            (None, None) => {}
         }
+    }
+
+    fn insert(&mut self, info: FileInfo) -> usize {
+        let index = match self
+            .files
+            .binary_search_by(|probe| compare_entries(probe, &info))
+        {
+            Ok(index) | Err(index) => index,
+        };
+        self.files.insert(index, info);
+        index
+    }
+
+    /// The indices of the entries read from `paths`.
+    pub fn indices_of(&self, paths: &HashSet<PathBuf>) -> Vec<usize> {
         self.files
-            .sort_unstable_by(|a, b| match (a.file_type.is_dir(), b.file_type.is_dir()) {
-                (true, true) | (false, false) => a.file_name.cmp(&b.file_name),
-                (false, true) => Ordering::Greater,
-                (true, false) => Ordering::Less,
-            });
-        self.path = path;
-        Ok(())
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| paths.contains(&info.path))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn index_of(&self, path: &Path) -> Option<usize> {
+        if path.parent() != Some(self.path.as_path()) {
+            return None;
+        }
+        let file_name = path.file_name()?.to_string_lossy();
+        self.files.iter().position(|f| f.file_name == file_name)
     }
 
     pub fn get(&self, index: usize) -> Option<&FileInfo> {
@@ -86,7 +356,7 @@ impl Directory {
         self.files.len()
     }
 
-    pub fn files(&self) -> impl Iterator<Item=&FileInfo> {
+    pub fn files(&self) -> impl Iterator<Item = &FileInfo> {
         self.files.iter()
     }
 
@@ -95,8 +365,9 @@ impl Directory {
     }
 
     pub fn go_up(&mut self) -> Result<(), io::Error> {
-        self.path.pop();
-        self.change_path(self.path.clone())?;
+        let mut path = self.path.clone();
+        path.pop();
+        self.change_path(path)?;
         Ok(())
     }
 
@@ -111,6 +382,99 @@ impl Directory {
     }
 }
 
+fn read_entries(path: &Path, jobs: &Jobs) -> Result<Vec<FileInfo>, io::Error> {
+    let mut files = Vec::new();
+    for item in path.read_dir()? {
+        match load_entry(&item?.path(), jobs) {
+            Ok(Some(info)) => files.push(info),
+            Ok(None) => {}
+            // Removed while the directory was read
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                continue;
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Reads the items of every trash, they are shown under their original names.
+fn read_trash(jobs: &Jobs) -> Result<Vec<FileInfo>, io::Error> {
+    let mut files = Vec::new();
+    for item in trash::list() {
+        let path = item.path();
+        let file_type = match path.symlink_metadata() {
+            Ok(meta) => meta.file_type(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                continue;
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        let file_name = match item.original_path.file_name() {
+            Some(file_name) => file_name.to_string_lossy().into_owned(),
+            None => item.id.to_string_lossy().into_owned(),
+        };
+        let mut info = new_entry(&path, file_name, file_type, jobs);
+        info.extension_mime = mime_guess::from_path(&item.original_path).first();
+        info.trashed = Some(item);
+        files.push(info);
+    }
+    Ok(files)
+}
+
+/// Reads the information of a directory entry, `None` is returned for hidden entries.
+fn load_entry(path: &Path, jobs: &Jobs) -> Result<Option<FileInfo>, io::Error> {
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().into_owned(),
+        None => {
+            return Ok(None);
+        }
+    };
+    if file_name.starts_with('.') {
+        return Ok(None);
+    }
+    let file_type = path.symlink_metadata()?.file_type();
+    Ok(Some(new_entry(path, file_name, file_type, jobs)))
+}
+
+/// Creates the information of the entry at `path`, its content info is computed in background
+/// by one of `jobs`.
+fn new_entry(path: &Path, file_name: String, file_type: FileType, jobs: &Jobs) -> FileInfo {
+    let content_info = Arc::new(Mutex::new(None));
+    if file_type.is_file() {
+        let path = path.to_path_buf();
+        let content_info = content_info.clone();
+        let listener = jobs.listener.clone();
+        SCHEDULER.submit(jobs.generation, path.clone(), move || {
+            let done_content_info = crate::file_content_info::for_path(&path, 48);
+            content_info.lock().replace(done_content_info);
+            if let Some(listener) = listener {
+                (listener.0.lock())(&path);
+            }
+        });
+    }
+    FileInfo {
+        path: path.to_path_buf(),
+        file_name,
+        file_type,
+        content_info,
+        extension_mime: mime_guess::from_path(path).first(),
+        trashed: None,
+    }
+}
+
+fn compare_entries(a: &FileInfo, b: &FileInfo) -> Ordering {
+    match (a.file_type.is_dir(), b.file_type.is_dir()) {
+        (true, true) | (false, false) => a.file_name.cmp(&b.file_name),
+        (false, true) => Ordering::Greater,
+        (true, false) => Ordering::Less,
+    }
+}
+
 impl Default for Directory {
     fn default() -> Self {
         Self::new(".").unwrap()
@@ -1,19 +1,121 @@
-use crate::{files::Directory, items_view::ItemsView, WATCHING_DIRS, file_content_info::FileContentInfo};
-use log::{debug, error, trace};
+use crate::{
+    file_content_info::{AudioMetadata, FileContentInfo},
+    files::{ContentInfoListener, Directory, DirectoryChange, EntryChange},
+    items_view::{
+        ItemActivatedEvent, ItemActivatedHandler, ItemsChange, ItemsChanges, ItemsView,
+        VisibleItemsChangedHandler,
+    },
+    trash::TrashItem,
+    WATCHING_DIRS,
+};
+use log::{debug, error, trace, warn};
 use orbtk::prelude::*;
-use std::{path::PathBuf, ops::{Deref, DerefMut}, fs::FileType};
+use std::{path::PathBuf, ops::{Deref, DerefMut, Range}, fs::FileType, rc::Rc, collections::HashSet};
 use mime::Mime;
 
 #[derive(Debug)]
 pub enum Event {
     SelectionChanged(Vec<usize>),
+    ItemActivated(usize),
+}
+
+/// Pushed by the directories watcher when the content of the shown directory changes on disk.
+#[derive(Debug, Clone)]
+pub struct DirectoryChangedEvent(pub Vec<DirectoryChange>);
+
+impl orbtk::prelude::Event for DirectoryChangedEvent {}
+
+pub type DirectoryChangedHandlerFn =
+    dyn Fn(&mut StatesContext, Vec<DirectoryChange>) -> bool + 'static;
+
+#[derive(Clone)]
+pub struct DirectoryChangedEventHandler {
+    handler: Rc<DirectoryChangedHandlerFn>,
+}
+
+impl Into<Rc<dyn EventHandler>> for DirectoryChangedEventHandler {
+    fn into(self) -> Rc<dyn EventHandler> {
+        Rc::new(self)
+    }
+}
+
+impl EventHandler for DirectoryChangedEventHandler {
+    fn handle_event(&self, states: &mut StatesContext, event: &EventBox) -> bool {
+        if let Ok(event) = event.downcast_ref::<DirectoryChangedEvent>() {
+            return (self.handler)(states, event.0.clone());
+        }
+        false
+    }
+
+    fn handles_event(&self, event: &EventBox) -> bool {
+        event.is_type::<DirectoryChangedEvent>()
+    }
+}
+
+pub trait DirectoryChangedHandler: Sized + Widget {
+    /// Inserts a handler for the changes on disk of the shown directory.
+    fn on_directory_changed<H: Fn(&mut StatesContext, Vec<DirectoryChange>) -> bool + 'static>(
+        self,
+        handler: H,
+    ) -> Self {
+        self.insert_handler(DirectoryChangedEventHandler {
+            handler: Rc::new(handler),
+        })
+    }
+}
+
+/// Pushed from the content info threads when the content info of an entry is ready.
+#[derive(Debug, Clone)]
+pub struct ContentInfoReadyEvent(pub PathBuf);
+
+impl orbtk::prelude::Event for ContentInfoReadyEvent {}
+
+pub type ContentInfoReadyHandlerFn = dyn Fn(&mut StatesContext, PathBuf) -> bool + 'static;
+
+#[derive(Clone)]
+pub struct ContentInfoReadyEventHandler {
+    handler: Rc<ContentInfoReadyHandlerFn>,
+}
+
+impl Into<Rc<dyn EventHandler>> for ContentInfoReadyEventHandler {
+    fn into(self) -> Rc<dyn EventHandler> {
+        Rc::new(self)
+    }
+}
+
+impl EventHandler for ContentInfoReadyEventHandler {
+    fn handle_event(&self, states: &mut StatesContext, event: &EventBox) -> bool {
+        if let Ok(event) = event.downcast_ref::<ContentInfoReadyEvent>() {
+            return (self.handler)(states, event.0.clone());
+        }
+        false
+    }
+
+    fn handles_event(&self, event: &EventBox) -> bool {
+        event.is_type::<ContentInfoReadyEvent>()
+    }
+}
+
+pub trait ContentInfoReadyHandler: Sized + Widget {
+    /// Inserts a handler for the content info computed in background.
+    fn on_content_info_ready<H: Fn(&mut StatesContext, PathBuf) -> bool + 'static>(
+        self,
+        handler: H,
+    ) -> Self {
+        self.insert_handler(ContentInfoReadyEventHandler {
+            handler: Rc::new(handler),
+        })
+    }
 }
 
 #[derive(Default, AsAny)]
 struct FilesViewState {
     directory: Directory,
     event: Option<Event>,
-    last_path: PathBuf,
+    watched_paths: Vec<PathBuf>,
+    pending_changes: Vec<DirectoryChange>,
+    /// The entries whose content info is ready but not shown yet.
+    ready_content_info: HashSet<PathBuf>,
 }
 
 impl State for FilesViewState {
@@ -24,14 +126,14 @@ impl State for FilesViewState {
             .get::<PathBufWrapper>("path")
             .as_path()
             .to_owned();
-        self.directory = Directory::new(path.clone()).unwrap();
-        ctx.widget().get_mut::<PathBufWrapper>("path").0 = self.directory.path().to_path_buf();
-        for fi in self.directory.files() {
-            ctx.widget().get_mut::<FilesInfo>("files_info").push(FileInfo { file_name: fi.file_name.clone(), file_type: fi.file_type, content_info: fi.content_info.lock().clone(), extension_mime: fi.extension_mime.clone() });
-        }
-        ItemsView::count_set(&mut ctx.child("directory_view"), self.directory.len());
-        ItemsView::request_update_set(&mut ctx.child("directory_view"), true);
-        WATCHING_DIRS.lock().unwrap().insert(path, ctx.event_adapter());
+        let (entity, event_adapter) = (ctx.entity, ctx.event_adapter());
+        self.directory
+            .set_content_info_listener(ContentInfoListener::new(move |path| {
+                event_adapter
+                    .push_event_direct(entity, ContentInfoReadyEvent(path.to_path_buf()));
+            }));
+        self.directory.set_path(path).unwrap();
+        self.refresh(ctx);
     }
 
     fn update(&mut self, _: &mut Registry, ctx: &mut Context) {
@@ -41,11 +143,39 @@ impl State for FilesViewState {
             .get::<PathBufWrapper>("path")
             .as_path()
             .to_owned();
-        if current_path != self.last_path {
+        if current_path != self.directory.path() {
             trace!("Updating FileView");
-            ItemsView::count_set(&mut ctx.child("directory_view"), self.directory.len());
-            ItemsView::request_update_set(&mut ctx.child("directory_view"), true);
-            self.last_path = current_path;
+            if let Err(e) = self.directory.set_path(&current_path) {
+                error!("Failed to go to {}: {}", current_path.display(), e);
+            }
+            self.refresh(ctx);
+        }
+        if !self.pending_changes.is_empty() && self.directory.is_trash() {
+            // The trash place is made of several directories, it's read again as a whole
+            if let Err(e) = self.directory.reload() {
+                error!("Failed to read the trash: {}", e);
+            }
+            self.refresh(ctx);
+        } else if !self.pending_changes.is_empty() {
+            let changes = std::mem::take(&mut self.pending_changes);
+            let entry_changes = self.directory.apply_changes(&changes);
+            if !entry_changes.is_empty() {
+                trace!("Applying {} changes to FileView", entry_changes.len());
+                self.sync_files_info(ctx);
+                let mut directory_view = ctx.child("directory_view");
+                directory_view.get_mut::<ItemsChanges>("changes").0.extend(
+                    entry_changes.into_iter().map(|change| match change {
+                        EntryChange::Inserted(index) => ItemsChange::Inserted(index),
+                        EntryChange::Removed(index) => ItemsChange::Removed(index),
+                        EntryChange::Updated(index) => ItemsChange::Updated(index),
+                    }),
+                );
+                ItemsView::count_set(&mut directory_view, self.directory.len());
+            }
+        }
+        if !self.ready_content_info.is_empty() {
+            let ready = std::mem::take(&mut self.ready_content_info);
+            self.show_content_info(ctx, &ready);
         }
         if let Some(event) = self.event.take() {
             match event {
@@ -55,6 +185,20 @@ impl State for FilesViewState {
                         SelectionChangedEvent(ctx.entity, changes),
                     );
                 }
+                Event::ItemActivated(index) => {
+                    ctx.event_adapter()
+                        .push_event_direct(ctx.entity, ItemActivatedEvent(index));
+                }
+            }
+        }
+    }
+
+    fn cleanup(&mut self, _: &mut Registry, ctx: &mut Context) {
+        self.directory.cancel_jobs();
+        let mut watching_dirs = WATCHING_DIRS.lock().unwrap();
+        for path in self.watched_paths.drain(..) {
+            if let Err(e) = watching_dirs.remove(&path, ctx.entity) {
+                warn!("Failed to stop watching {}: {}", path.display(), e);
             }
         }
     }
@@ -64,6 +208,101 @@ impl FilesViewState {
     fn event(&mut self, event: impl Into<Option<Event>>) {
         self.event = event.into();
     }
+
+    fn directory_changed(&mut self, changes: Vec<DirectoryChange>) {
+        self.pending_changes.extend(changes);
+    }
+
+    fn content_info_ready(&mut self, path: PathBuf) {
+        self.ready_content_info.insert(path);
+    }
+
+    /// Rebuilds the items of the entries read from `paths` to show their content info.
+    fn show_content_info(&self, ctx: &mut Context, paths: &HashSet<PathBuf>) {
+        let indices = self.directory.indices_of(paths);
+        if indices.is_empty() {
+            return;
+        }
+        {
+            let mut widget = ctx.widget();
+            let files_info = widget.get_mut::<FilesInfo>("files_info");
+            for index in &indices {
+                if let (Some(info), Some(entry)) =
+                    (files_info.get_mut(*index), self.directory.get(*index))
+                {
+                    info.content_info = entry.content_info.lock().clone();
+                }
+            }
+        }
+        let mut directory_view = ctx.child("directory_view");
+        directory_view
+            .get_mut::<ItemsChanges>("changes")
+            .0
+            .extend(indices.into_iter().map(ItemsChange::Updated));
+        ItemsView::count_set(&mut directory_view, self.directory.len());
+    }
+
+    /// Makes the content info of the items shown on screen be computed first.
+    fn visible_items_changed(&mut self, range: Range<usize>) {
+        self.directory.set_visible(range);
+    }
+
+    /// Shows the whole content of the current directory and starts watching it.
+    fn refresh(&mut self, ctx: &mut Context) {
+        ctx.widget().get_mut::<PathBufWrapper>("path").0 = self.directory.path().to_path_buf();
+        self.pending_changes.clear();
+        self.sync_files_info(ctx);
+        ItemsView::count_set(&mut ctx.child("directory_view"), self.directory.len());
+        ItemsView::request_update_set(&mut ctx.child("directory_view"), true);
+        self.watch(ctx);
+    }
+
+    /// Moves the watches of this view to the current directory.
+    fn watch(&mut self, ctx: &mut Context) {
+        let paths = self.directory.watch_paths();
+        if self.watched_paths == paths {
+            return;
+        }
+        let mut watching_dirs = WATCHING_DIRS.lock().unwrap();
+        for old_path in self.watched_paths.drain(..) {
+            if let Err(e) = watching_dirs.remove(&old_path, ctx.entity) {
+                warn!("Failed to stop watching {}: {}", old_path.display(), e);
+            }
+        }
+        for path in paths {
+            match watching_dirs.insert(&path, ctx.entity, ctx.event_adapter()) {
+                Ok(()) => {
+                    self.watched_paths.push(path);
+                }
+                Err(e) => {
+                    if let notify::ErrorKind::MaxFilesWatch = e.kind {
+                        warn!(
+                            "Changes on {} will not be shown, the limit of watches was reached",
+                            path.display()
+                        );
+                    } else {
+                        warn!("Changes on {} will not be shown: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Mirrors the directory entries into the `files_info` property read by the items builder.
+    fn sync_files_info(&self, ctx: &mut Context) {
+        let files_info = self
+            .directory
+            .files()
+            .map(|fi| FileInfo {
+                file_name: fi.file_name.clone(),
+                file_type: fi.file_type,
+                content_info: fi.content_info.lock().clone(),
+                extension_mime: fi.extension_mime.clone(),
+                trashed: fi.trashed.clone(),
+            })
+            .collect();
+        ctx.widget().set::<FilesInfo>("files_info", files_info);
+    }
 }
 
 #[derive(Debug, Default, AsAny, PartialEq, Eq, Clone)]
@@ -96,10 +335,29 @@ pub struct FileInfo {
     file_name: String,
     file_type: FileType,
     content_info: Option<FileContentInfo>,
-    extension_mime: Option<Mime>
+    extension_mime: Option<Mime>,
+    trashed: Option<TrashItem>
 }
 
 impl FileInfo {
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type.is_dir()
+    }
+
+    /// The item of the trash this entry shows, only set in the trash place.
+    pub fn trashed(&self) -> Option<&TrashItem> {
+        self.trashed.as_ref()
+    }
+
+    /// The tags of audio files, once their content was read.
+    pub fn audio(&self) -> Option<&AudioMetadata> {
+        self.content_info.as_ref().and_then(|ci| ci.audio.as_ref())
+    }
+
     pub fn media_type(&self) -> Option<Mime> {
         self.extension_mime.clone().or_else(|| self.content_info.as_ref().and_then(|ci| ci.mime.clone()))
     }
@@ -109,14 +367,27 @@ type FilesInfo = Vec<FileInfo>;
 
 into_property_source!(FileInfo);
 
-widget!(FilesView<FilesViewState>: SelectionChangedHandler {
+widget!(FilesView<FilesViewState>: SelectionChangedHandler, ItemActivatedHandler, DirectoryChangedHandler, ContentInfoReadyHandler {
     path: PathBufWrapper,
     files_info: FilesInfo
 });
 
 impl Template for FilesView {
     fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
-        self.name("FilesView").child(
+        let view = self
+            .on_directory_changed(move |states, changes| {
+                states
+                    .get_mut::<FilesViewState>(id)
+                    .directory_changed(changes);
+                true
+            })
+            .on_content_info_ready(move |states, path| {
+                states
+                    .get_mut::<FilesViewState>(id)
+                    .content_info_ready(path);
+                true
+            });
+        view.name("FilesView").child(
             ItemsView::new()
                 .id("directory_view")
                 .on_selection_changed(move |states, _, change| {
@@ -124,6 +395,18 @@ impl Template for FilesView {
                         .get_mut::<FilesViewState>(id)
                         .event(Event::SelectionChanged(change));
                 })
+                .on_item_activated(move |states, index| {
+                    states
+                        .get_mut::<FilesViewState>(id)
+                        .event(Event::ItemActivated(index));
+                    true
+                })
+                .on_visible_items_changed(move |states, range| {
+                    states
+                        .get_mut::<FilesViewState>(id)
+                        .visible_items_changed(range);
+                    true
+                })
                 .items_builder(move |bc, index| {
                     let entry = bc
                         .get_widget(id)
@@ -144,24 +427,27 @@ impl Template for FilesView {
                                 icon = Ok(Image::from_rgba_image(image).unwrap());
                             }
                             _ => {
-                                if let Some(s) = entry.file_name.rfind('.') {
-                                    let ext = &entry.file_name[s + 1..];
-                                    icon = Err(match &ext.to_lowercase()[..] {
-                                        "c" | "hpp" | "cpp" | "cxx" | "rb" | "py" | "rs" | "js" | "css"
-                                        | "html" | "php" | "xml" => material_icons_font::MD_CODE,
-                                        _ => match entry.media_type() {
-                                            Some(mime) => match mime.type_() {
-                                                mime::AUDIO => material_icons_font::MD_AUDIOTRACK,
-                                                mime::IMAGE => material_icons_font::MD_IMAGE,
-                                                mime::TEXT => material_icons_font::MD_TEXT_SNIPPET,
-                                                _ => material_icons_font::MD_ARCHIVE,
-                                            },
-                                            None => material_icons_font::MD_ARCHIVE,
+                                let ext = entry.file_name.rfind('.').map(|s| entry.file_name[s + 1..].to_lowercase());
+                                icon = Err(match ext.as_deref() {
+                                    Some("c") | Some("hpp") | Some("cpp") | Some("cxx") | Some("rb") | Some("py")
+                                    | Some("rs") | Some("js") | Some("css") | Some("html") | Some("php")
+                                    | Some("xml") => material_icons_font::MD_CODE,
+                                    // The type is also known from the content of files without extension
+                                    _ => match entry.media_type() {
+                                        Some(mime) => match (mime.type_(), mime.subtype().as_str()) {
+                                            (mime::AUDIO, _) => material_icons_font::MD_AUDIOTRACK,
+                                            (mime::IMAGE, _) => material_icons_font::MD_IMAGE,
+                                            (mime::TEXT, _) => material_icons_font::MD_TEXT_SNIPPET,
+                                            (mime::APPLICATION, "pdf") => material_icons_font::MD_PICTURE_AS_PDF,
+                                            // The subtype of `vnd.comicbook+zip` is without the suffix
+                                            (mime::APPLICATION, "epub") | (mime::APPLICATION, "x-cbz")
+                                            | (mime::APPLICATION, "x-cbr") | (mime::APPLICATION, "vnd.comicbook")
+                                            | (mime::APPLICATION, "vnd.comicbook-rar") => material_icons_font::MD_BOOK,
+                                            _ => material_icons_font::MD_ARCHIVE,
                                         },
-                                    });
-                                } else {
-                                    icon = Err(material_icons_font::MD_ARCHIVE);
-                                }
+                                        None => material_icons_font::MD_ARCHIVE,
+                                    },
+                                });
                             }
                         }
                     }
@@ -190,6 +476,22 @@ impl Template for FilesView {
                             .v_align("center")
                             .build(bc),
                     };
+                    // Files whose content can't be read get a broken image badge
+                    let icon_widget = match entry.content_info.as_ref().and_then(|ci| ci.error.as_ref()) {
+                        Some(_) => Grid::new()
+                            .h_align("center")
+                            .child(icon_widget)
+                            .child(
+                                FontIconBlock::new()
+                                    .icon(material_icons_font::MD_BROKEN_IMAGE)
+                                    .icon_size(16)
+                                    .h_align("end")
+                                    .v_align("end")
+                                    .build(bc),
+                            )
+                            .build(bc),
+                        None => icon_widget,
+                    };
                     Stack::new()
                         .child(icon_widget)
                         .child(
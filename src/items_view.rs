@@ -1,9 +1,122 @@
-use crate::distribute::Distribute;
+use crate::distribute::{ChildrenOrder, Distribute};
+use crate::distribute_layout::SLOT_SIZE;
 use behaviors::MouseBehavior;
 use orbtk::prelude::*;
-use std::{cell::Cell, collections::HashSet};
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    ops::Range,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// The longest time between the two clicks of a double click.
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
 
 static ITEMS_PANEL: &str = "items_panel";
+static SCROLL_VIEWER: &str = "items_scroll_viewer";
+
+/// Describes an incremental change of the items of an `ItemsView`, indices are relative to the
+/// state left by the previous change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemsChange {
+    Inserted(usize),
+    Removed(usize),
+    Updated(usize),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ItemsChanges(pub Vec<ItemsChange>);
+
+into_property_source!(ItemsChanges);
+
+/// Pushed when the range of items that intersect the viewport changes.
+#[derive(Debug, Clone)]
+pub struct VisibleItemsChangedEvent(pub Range<usize>);
+
+impl orbtk::prelude::Event for VisibleItemsChangedEvent {}
+
+pub type VisibleItemsChangedHandlerFn = dyn Fn(&mut StatesContext, Range<usize>) -> bool + 'static;
+
+#[derive(Clone)]
+pub struct VisibleItemsChangedEventHandler {
+    handler: Rc<VisibleItemsChangedHandlerFn>,
+}
+
+impl Into<Rc<dyn EventHandler>> for VisibleItemsChangedEventHandler {
+    fn into(self) -> Rc<dyn EventHandler> {
+        Rc::new(self)
+    }
+}
+
+impl EventHandler for VisibleItemsChangedEventHandler {
+    fn handle_event(&self, states: &mut StatesContext, event: &EventBox) -> bool {
+        if let Ok(event) = event.downcast_ref::<VisibleItemsChangedEvent>() {
+            return (self.handler)(states, event.0.clone());
+        }
+        false
+    }
+
+    fn handles_event(&self, event: &EventBox) -> bool {
+        event.is_type::<VisibleItemsChangedEvent>()
+    }
+}
+
+pub trait VisibleItemsChangedHandler: Sized + Widget {
+    /// Inserts a handler for the changes of the items shown in the viewport.
+    fn on_visible_items_changed<H: Fn(&mut StatesContext, Range<usize>) -> bool + 'static>(
+        self,
+        handler: H,
+    ) -> Self {
+        self.insert_handler(VisibleItemsChangedEventHandler {
+            handler: Rc::new(handler),
+        })
+    }
+}
+
+/// Pushed when an item is activated by a double click.
+#[derive(Debug, Clone)]
+pub struct ItemActivatedEvent(pub usize);
+
+impl orbtk::prelude::Event for ItemActivatedEvent {}
+
+pub type ItemActivatedHandlerFn = dyn Fn(&mut StatesContext, usize) -> bool + 'static;
+
+#[derive(Clone)]
+pub struct ItemActivatedEventHandler {
+    handler: Rc<ItemActivatedHandlerFn>,
+}
+
+impl Into<Rc<dyn EventHandler>> for ItemActivatedEventHandler {
+    fn into(self) -> Rc<dyn EventHandler> {
+        Rc::new(self)
+    }
+}
+
+impl EventHandler for ItemActivatedEventHandler {
+    fn handle_event(&self, states: &mut StatesContext, event: &EventBox) -> bool {
+        if let Ok(event) = event.downcast_ref::<ItemActivatedEvent>() {
+            return (self.handler)(states, event.0);
+        }
+        false
+    }
+
+    fn handles_event(&self, event: &EventBox) -> bool {
+        event.is_type::<ItemActivatedEvent>()
+    }
+}
+
+pub trait ItemActivatedHandler: Sized + Widget {
+    /// Inserts a handler for the activation of the items, by their index.
+    fn on_item_activated<H: Fn(&mut StatesContext, usize) -> bool + 'static>(
+        self,
+        handler: H,
+    ) -> Self {
+        self.insert_handler(ItemActivatedEventHandler {
+            handler: Rc::new(handler),
+        })
+    }
+}
 
 #[derive(Default, AsAny)]
 struct ItemsViewState {
@@ -11,58 +124,191 @@ struct ItemsViewState {
     count: usize,
     selected_entities: RefCell<HashSet<Entity>>,
     items_panel: Entity,
+    scroll_viewer: Entity,
+    /// The item widget and the entity that holds its content, for every index.
+    items: Vec<(Entity, Entity)>,
+    /// The items that intersected the viewport after the last layout.
+    visible: Range<usize>,
 }
 
 impl ItemsViewState {
     fn generate_items(&mut self, ctx: &mut Context) {
         let count = ctx.widget().clone_or_default::<usize>("count");
-        let entity = ctx.entity;
+        let request_update = *ctx.widget().get::<bool>("request_update");
+        let changes = std::mem::take(&mut ctx.widget().get_mut::<ItemsChanges>("changes").0);
 
-        if count != self.count || *ctx.widget().get::<bool>("request_update") {
+        if !request_update && !changes.is_empty() && self.builder.is_some() {
+            self.apply_changes(ctx, changes);
+            if self.count == count {
+                return;
+            }
+        }
+
+        if count != self.count || request_update {
             ctx.widget().set("request_update", false);
-            if let Some(builder) = &self.builder {
+            if self.builder.is_some() {
                 ctx.clear_children_of(self.items_panel);
+                self.items.clear();
+                // The new items are reported as visible even if their indices didn't change
+                self.visible = 0..0;
+                // The selection was made on the previous items, it must not apply to the new ones
+                self.selected_entities.borrow_mut().clear();
+                ctx.widget()
+                    .get_mut::<SelectedEntities>("selected_entities")
+                    .0
+                    .clear();
+                ctx.widget()
+                    .get_mut::<SelectedIndices>("selected_indices")
+                    .0
+                    .clear();
 
                 for i in 0..count {
-                    let item = {
-                        let build_context = &mut ctx.build_context();
-                        let child = builder(build_context, i);
-                        let item = ItemsViewItem::new()
-                            .parent(entity.0)
-                            //.height(55) // Hack to do the mouse click bound check work
-                            .build(build_context);
-
-                        let mouse_behavior =
-                            MouseBehavior::new().target(item.0).build(build_context);
-                        build_context.register_shared_property::<Selector>(
-                            "selector",
-                            mouse_behavior,
-                            item,
-                        );
-                        build_context.register_shared_property::<bool>(
-                            "pressed",
-                            mouse_behavior,
-                            item,
-                        );
-                        build_context.append_child(item, mouse_behavior);
-
-                        build_context.register_shared_property::<Brush>("foreground", child, item);
-                        build_context.register_shared_property::<f32>("opacity", item, entity);
-                        build_context.register_shared_property::<f32>("opacity", child, entity);
-                        build_context.register_shared_property::<f64>("font_size", child, item);
-                        build_context.register_shared_property::<f64>("font", child, item);
-                        build_context.append_child(self.items_panel, item);
-                        build_context.append_child(mouse_behavior, child);
-
-                        item
-                    };
-                    ctx.get_widget(item).update_widget(entity, false, false);
+                    let item = self.build_item(ctx);
+                    self.build_item_content(ctx, item, i);
+                    self.items.push(item);
                 }
+                self.update_order(ctx);
             }
 
             self.count = count;
         }
     }
+
+    /// Replays `changes` over the current items, the items of the removed entries are dropped
+    /// and the others moved, only the inserted and updated items are rebuilt.
+    fn apply_changes(&mut self, ctx: &mut Context, changes: Vec<ItemsChange>) {
+        let mut stale = HashSet::new();
+        for change in changes {
+            match change {
+                ItemsChange::Inserted(index) => {
+                    let item = self.build_item(ctx);
+                    self.items.insert(index.min(self.items.len()), item);
+                    stale.insert(item.0);
+                }
+                ItemsChange::Removed(index) => {
+                    if index < self.items.len() {
+                        let (item, _) = self.items.remove(index);
+                        ctx.remove_child_from(item, self.items_panel);
+                        self.selected_entities.borrow_mut().remove(&item);
+                        ctx.widget()
+                            .get_mut::<SelectedEntities>("selected_entities")
+                            .0
+                            .remove(&item);
+                        stale.remove(&item);
+                    }
+                }
+                ItemsChange::Updated(index) => {
+                    if let Some((item, _)) = self.items.get(index) {
+                        stale.insert(*item);
+                    }
+                }
+            }
+        }
+        self.count = self.items.len();
+        self.update_order(ctx);
+
+        // The selected items keep their selection at their new index
+        let selected_indices = {
+            let widget = ctx.widget();
+            let selected_entities = &widget.get::<SelectedEntities>("selected_entities").0;
+            self.items
+                .iter()
+                .enumerate()
+                .filter(|(_, (item, _))| selected_entities.contains(item))
+                .map(|(index, _)| index)
+                .collect()
+        };
+        ctx.widget()
+            .get_mut::<SelectedIndices>("selected_indices")
+            .0 = selected_indices;
+
+        let stale: Vec<_> = self
+            .items
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, (item, _))| stale.contains(item))
+            .collect();
+        for (index, item) in stale {
+            self.build_item_content(ctx, item, index);
+        }
+    }
+
+    /// Makes the items panel lay out the items in the order of their indices.
+    fn update_order(&self, ctx: &mut Context) {
+        let order = self.items.iter().map(|(item, _)| *item).collect();
+        ctx.widget().set("order", ChildrenOrder(order));
+    }
+
+    /// Builds an empty item and appends it to the items panel.
+    fn build_item(&self, ctx: &mut Context) -> (Entity, Entity) {
+        let entity = ctx.entity;
+        let build_context = &mut ctx.build_context();
+        let item = ItemsViewItem::new()
+            .parent(entity.0)
+            //.height(55) // Hack to do the mouse click bound check work
+            .build(build_context);
+
+        let mouse_behavior = MouseBehavior::new().target(item.0).build(build_context);
+        build_context.register_shared_property::<Selector>("selector", mouse_behavior, item);
+        build_context.register_shared_property::<bool>("pressed", mouse_behavior, item);
+        build_context.append_child(item, mouse_behavior);
+
+        build_context.register_shared_property::<f32>("opacity", item, entity);
+        build_context.append_child(self.items_panel, item);
+
+        (item, mouse_behavior)
+    }
+
+    /// Replaces the content of `item` with the one built for `index`.
+    fn build_item_content(&self, ctx: &mut Context, item: (Entity, Entity), index: usize) {
+        let entity = ctx.entity;
+        let (item, mouse_behavior) = item;
+        if let Some(builder) = &self.builder {
+            ctx.clear_children_of(mouse_behavior);
+            let build_context = &mut ctx.build_context();
+            let child = builder(build_context, index);
+            build_context.register_shared_property::<Brush>("foreground", child, item);
+            build_context.register_shared_property::<f32>("opacity", child, entity);
+            build_context.register_shared_property::<f64>("font_size", child, item);
+            build_context.register_shared_property::<f64>("font", child, item);
+            build_context.append_child(mouse_behavior, child);
+        }
+        ctx.get_widget(item).update_widget(entity, false, false);
+    }
+
+    /// Notifies the items that intersect the viewport when they change. The items are laid out
+    /// in rows of slots of the same size, so the rows in the viewport give the visible items.
+    fn update_visible_items(&mut self, ctx: &mut Context) {
+        let viewport_height = ctx
+            .get_widget(self.scroll_viewer)
+            .get::<Rectangle>("bounds")
+            .height();
+        // The padding of the scroll viewer is the scroll offset
+        let top = -ctx
+            .get_widget(self.scroll_viewer)
+            .get::<Thickness>("padding")
+            .top();
+        let bottom = top + viewport_height;
+        // The panel is as wide as its columns
+        let columns = (ctx
+            .get_widget(self.items_panel)
+            .get::<Rectangle>("bounds")
+            .width()
+            / SLOT_SIZE.0) as usize;
+        let first_row = (top.max(0.0) / SLOT_SIZE.1) as usize;
+        let last_row = (bottom.max(0.0) / SLOT_SIZE.1).ceil() as usize;
+        let len = self.items.len();
+        let mut visible = (first_row * columns).min(len)..(last_row * columns).min(len);
+        if visible.start >= visible.end {
+            visible = 0..0;
+        }
+        if visible != self.visible {
+            self.visible = visible.clone();
+            ctx.event_adapter()
+                .push_event_direct(ctx.entity, VisibleItemsChangedEvent(visible));
+        }
+    }
 }
 
 impl State for ItemsViewState {
@@ -70,6 +316,9 @@ impl State for ItemsViewState {
         self.items_panel = ctx
             .entity_of_child(ITEMS_PANEL)
             .expect("ItemsViewState.init: ItemsPanel child could not be found.");
+        self.scroll_viewer = ctx
+            .entity_of_child(SCROLL_VIEWER)
+            .expect("ItemsViewState.init: ScrollViewer child could not be found.");
 
         self.generate_items(ctx);
     }
@@ -79,6 +328,8 @@ impl State for ItemsViewState {
     }
 
     fn update_post_layout(&mut self, _: &mut Registry, ctx: &mut Context) {
+        self.update_visible_items(ctx);
+
         for index in ctx
             .widget()
             .get::<SelectedEntities>("selected_entities")
@@ -116,28 +367,62 @@ impl State for ItemsViewState {
 #[derive(Default, AsAny)]
 pub struct ItemsViewItemState {
     request_selection_toggle: Cell<bool>,
+    request_activation: Cell<bool>,
+    last_click: Cell<Option<Instant>>,
 }
 
 impl ItemsViewItemState {
-    fn toggle_selection(&self) {
-        self.request_selection_toggle.set(true);
+    /// A click toggles the selection of the item, a second one soon after activates it.
+    fn click(&self) {
+        let now = Instant::now();
+        match self.last_click.replace(Some(now)) {
+            Some(last) if now.duration_since(last) <= DOUBLE_CLICK_INTERVAL => {
+                self.last_click.set(None);
+                self.request_activation.set(true);
+            }
+            _ => {
+                self.request_selection_toggle.set(true);
+            }
+        }
     }
 }
 
 impl State for ItemsViewItemState {
     fn update(&mut self, _: &mut Registry, ctx: &mut Context) {
-        if !ctx.widget().get::<bool>("enabled") || !self.request_selection_toggle.get() {
+        if !ctx.widget().get::<bool>("enabled")
+            || !(self.request_selection_toggle.get() || self.request_activation.get())
+        {
             return;
         }
-        self.request_selection_toggle.set(false);
+        let toggle_selection = self.request_selection_toggle.replace(false);
+        let activate = self.request_activation.replace(false);
 
         let selected = *ctx.widget().get::<bool>("selected");
 
         let entity = ctx.entity;
-        let index = ctx.index_as_child(entity).unwrap();
-
         let parent_entity: Entity = (*ctx.widget().get::<u32>("parent")).into();
 
+        let parent = ctx.get_widget(parent_entity);
+        // The items are laid out in this order, their order in the panel is the creation one
+        let index = match parent
+            .get::<ChildrenOrder>("order")
+            .0
+            .iter()
+            .position(|item| *item == entity)
+        {
+            Some(index) => index,
+            None => {
+                return;
+            }
+        };
+
+        if activate {
+            ctx.event_adapter()
+                .push_event_direct(parent_entity, ItemActivatedEvent(index));
+        }
+        if !toggle_selection {
+            return;
+        }
         let mut parent = ctx.get_widget(parent_entity);
 
         let selection_mode = *parent.get::<SelectionMode>("selection_mode");
@@ -254,7 +539,7 @@ impl Template for ItemsViewItem {
             .font_size(32.0)
             .font("Roboto-Regular")
             .on_click(move |states, _| {
-                states.get::<ItemsViewItemState>(id).toggle_selection();
+                states.get::<ItemsViewItemState>(id).click();
                 false
             })
         /*.child(
@@ -276,7 +561,7 @@ impl Template for ItemsViewItem {
 }
 
 widget!(
-    ItemsView<ItemsViewState>: SelectionChangedHandler {
+    ItemsView<ItemsViewState>: SelectionChangedHandler, VisibleItemsChangedHandler, ItemActivatedHandler {
         /// Sets or shares the background property.
         background: Brush,
 
@@ -305,7 +590,13 @@ widget!(
         selected_entities: SelectedEntities,
 
         /// Use this flag to force the redrawing of the items.
-        request_update: bool
+        request_update: bool,
+
+        /// Queues incremental changes of the items, they are applied and cleared on update.
+        changes: ItemsChanges,
+
+        /// The item widgets in the order of their indices.
+        order: ChildrenOrder
     }
 );
 
@@ -322,9 +613,10 @@ impl ItemsView {
 
 impl Template for ItemsView {
     fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
-        let items_panel = Distribute::new().id(ITEMS_PANEL).build(ctx);
+        let items_panel = Distribute::new().id(ITEMS_PANEL).order(id).build(ctx);
 
         let scroll_viewer = ScrollViewer::new()
+            .id(SCROLL_VIEWER)
             .mode(("disabled", "auto"))
             .child(items_panel)
             .build(ctx);
@@ -339,6 +631,8 @@ impl Template for ItemsView {
             .selection_mode("single")
             .selected_indices(HashSet::new())
             .selected_entities(HashSet::new())
+            .changes(ItemsChanges::default())
+            .order(ChildrenOrder::default())
             .child(
                 Container::new()
                     .background(id)
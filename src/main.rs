@@ -1,19 +1,39 @@
 use lazy_static::lazy_static;
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use log::warn;
+use notify::{
+    event::{EventKind, ModifyKind, RenameMode},
+    RecommendedWatcher, RecursiveMode, Watcher,
+};
 use orbtk::{prelude::*, theme, theming::config::*};
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
-    sync::Mutex,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+#[cfg(target_os = "linux")]
+mod applications;
+mod clipboard;
+#[cfg(target_os = "linux")]
+mod desktop_entry;
 mod distribute;
 mod distribute_layout;
 mod file_content_info;
+mod file_operations;
 mod files;
 mod files_view;
 mod items_view;
 mod main_view;
+mod trash;
+mod uri;
+#[cfg(target_os = "linux")]
+mod xdg;
+use files::DirectoryChange;
 use files_view::*;
 use main_view::*;
 
@@ -21,42 +41,161 @@ lazy_static! {
     static ref WATCHING_DIRS: Mutex<WatchingDirs> = Mutex::new(WatchingDirs::new());
 }
 
+/// Time without new events after which a burst of events is delivered.
+const COALESCE_DELAY: Duration = Duration::from_millis(100);
+/// Maximum time a burst of events is held before being delivered anyway.
+const COALESCE_MAX_DELAY: Duration = Duration::from_millis(500);
+
+/// The widget that is notified about the changes of a watched directory.
+struct DirListener {
+    entity: Entity,
+    event_adapter: EventAdapter,
+}
+
+/// The listeners of every watched directory, a directory is watched while it has listeners.
+type DirListeners = Arc<Mutex<HashMap<PathBuf, Vec<DirListener>>>>;
+
 struct WatchingDirs {
-    watcher: RecommendedWatcher,
-    paths: HashMap<PathBuf, EventAdapter>,
+    watcher: Option<RecommendedWatcher>,
+    paths: DirListeners,
 }
 
 impl WatchingDirs {
     pub fn new() -> WatchingDirs {
-        let mut watcher: RecommendedWatcher = Watcher::new_immediate(|_res| {
-            /*use notify::event::{Event, EventKind};
-            let event:Event = match res {
-                Ok(event) => event,
-                Err(_) => {
-                    return;
-                },
-            };
-            /*for (instance_path, _) in {
-            }*/
-            match event.kind {
-                _ => {}
-            }*/
+        let (sender, receiver) = mpsc::channel();
+        let watcher = RecommendedWatcher::new_immediate(move |res| {
+            let _ = sender.send(res);
+        })
+        .and_then(|mut watcher| {
+            watcher.configure(notify::Config::PreciseEvents(true))?;
+            Ok(watcher)
         })
-        .unwrap();
-        watcher
-            .configure(notify::Config::PreciseEvents(true))
+        .map_err(|e| warn!("Directories will not be refreshed on changes: {}", e))
+        .ok();
+        // The listeners live outside of `WATCHING_DIRS`, the watcher waits for its event thread
+        // while adding watches and that thread must never wait for `WATCHING_DIRS`
+        let paths = DirListeners::default();
+        let listeners = paths.clone();
+        thread::Builder::new()
+            .name("watching_dirs".to_owned())
+            .spawn(move || dispatch_events(receiver, listeners))
             .unwrap();
-        WatchingDirs {
-            watcher,
-            paths: HashMap::new(),
+        WatchingDirs { watcher, paths }
+    }
+
+    /// Registers `entity` as listener of `path`, the directory is only watched once no matter
+    /// how many listeners it has.
+    pub fn insert(
+        &mut self,
+        path: impl AsRef<Path>,
+        entity: Entity,
+        event_adapter: EventAdapter,
+    ) -> Result<(), notify::Error> {
+        let path = path.as_ref();
+        let mut paths = self.paths.lock().unwrap();
+        if !paths.contains_key(path) {
+            match &mut self.watcher {
+                Some(watcher) => watcher.watch(path, RecursiveMode::NonRecursive)?,
+                None => {
+                    return Err(notify::Error::generic("the watcher is not available"));
+                }
+            }
         }
+        paths.entry(path.to_owned()).or_default().push(DirListener {
+            entity,
+            event_adapter,
+        });
+        Ok(())
     }
 
-    pub fn insert(&mut self, path: impl AsRef<Path>, event_adapter: EventAdapter) {
-        self.watcher
-            .watch(path.as_ref(), RecursiveMode::NonRecursive)
-            .unwrap();
-        self.paths.insert(path.as_ref().into(), event_adapter);
+    /// Unregisters `entity` as listener of `path`, the directory stops being watched when its
+    /// last listener is removed.
+    pub fn remove(&mut self, path: impl AsRef<Path>, entity: Entity) -> Result<(), notify::Error> {
+        let path = path.as_ref();
+        let mut paths = self.paths.lock().unwrap();
+        let listeners = match paths.get_mut(path) {
+            Some(listeners) => listeners,
+            None => {
+                return Ok(());
+            }
+        };
+        listeners.retain(|l| l.entity != entity);
+        if listeners.is_empty() {
+            paths.remove(path);
+            if let Some(watcher) = &mut self.watcher {
+                watcher.unwatch(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects bursts of events, groups them by directory and delivers them to the listeners.
+fn dispatch_events(receiver: Receiver<notify::Result<notify::Event>>, listeners: DirListeners) {
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + COALESCE_MAX_DELAY;
+        let mut changes: HashMap<PathBuf, Vec<DirectoryChange>> = HashMap::new();
+        let mut next = Some(first);
+        while let Some(res) = next.take() {
+            match res {
+                Ok(event) => {
+                    for change in directory_changes(event) {
+                        if let Some(parent) = change.parent() {
+                            changes.entry(parent.to_owned()).or_default().push(change);
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Error watching directories: {}", e);
+                }
+            }
+            let now = Instant::now();
+            if now < deadline {
+                next = receiver
+                    .recv_timeout(COALESCE_DELAY.min(deadline - now))
+                    .ok();
+            }
+        }
+        let listeners = listeners.lock().unwrap();
+        for (path, changes) in changes {
+            let changes = DirectoryChange::coalesce(changes);
+            for listener in listeners.get(&path).into_iter().flatten() {
+                listener
+                    .event_adapter
+                    .push_event_direct(listener.entity, DirectoryChangedEvent(changes.clone()));
+            }
+        }
+    }
+}
+
+fn directory_changes(event: notify::Event) -> Vec<DirectoryChange> {
+    let mut paths = event.paths.into_iter();
+    match event.kind {
+        EventKind::Access(_) => Vec::new(),
+        EventKind::Create(_) => paths.map(DirectoryChange::Created).collect(),
+        EventKind::Remove(_) => paths.map(DirectoryChange::Removed).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            paths.map(DirectoryChange::Removed).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            paths.map(DirectoryChange::Created).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            match (paths.next(), paths.next()) {
+                (Some(from), Some(to)) if from.parent() == to.parent() => {
+                    vec![DirectoryChange::Renamed(from, to)]
+                }
+                (Some(from), Some(to)) => {
+                    vec![DirectoryChange::Removed(from), DirectoryChange::Created(to)]
+                }
+                (from, to) => from
+                    .into_iter()
+                    .chain(to)
+                    .map(DirectoryChange::Modified)
+                    .collect(),
+            }
+        }
+        _ => paths.map(DirectoryChange::Modified).collect(),
     }
 }
 
@@ -77,8 +216,18 @@ fn setup_logger() -> Result<(), fern::InitError> {
     Ok(())
 }
 
+/// The path of the test fixture `name` in the directory `dir` of `tests/fixtures`.
+#[cfg(test)]
+fn test_fixture(dir: &str, name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(dir)
+        .join(name)
+}
+
 fn main() {
     setup_logger().unwrap();
+    thread::spawn(file_content_info::vacuum_thumbnails);
     Application::new()
         .theme(Theme::from_config(
             ThemeConfig::from(theme::LIGHT_THEME_RON)
@@ -95,4 +244,5 @@ fn main() {
                 .build(ctx)
         })
         .run();
+    file_content_info::cleanup();
 }
@@ -1,10 +1,30 @@
-use log::{debug, error};
+use log::{debug, error, warn};
+use mime::Mime;
 use orbtk::prelude::*;
-use std::{ops::{Deref, DerefMut}, path::PathBuf};
+use std::{
+    collections::HashSet,
+    io, mem,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
 
+#[cfg(target_os = "linux")]
+use crate::applications;
+use crate::clipboard::CLIPBOARD;
+use crate::file_content_info::AudioMetadata;
+use crate::file_operations::{
+    self, format_size, measure_all, OperationError, Progress, TransferMode, TreeSize,
+};
 use crate::files::*;
 use crate::files_view::*;
 use crate::items_view::*;
+use crate::trash::{self, TrashItem};
+
+/// Minimum time between two progress reports of a running operation.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct PlacesWrapper(Vec<(String, PathBuf)>);
@@ -25,12 +45,102 @@ impl DerefMut for PlacesWrapper {
 
 #[derive(Debug)]
 enum Event {
-    ClickOnDirContent(usize),
+    /// Opens the entry, a directory is shown and a file opened with its default application.
+    ActivateItem(usize),
+    SelectionChanged,
     Undo,
     Redo,
     MoveUp,
     GoToHome,
     RequestManualPathChange,
+    Copy,
+    Cut,
+    Paste,
+    GoToPlace(usize),
+    MoveToTrash,
+    RestoreFromTrash,
+    EmptyTrash,
+    Delete,
+    ConfirmAction,
+    CancelAction,
+    DismissReport,
+    OpenWith,
+    SelectApplication(usize),
+    /// Opens the file with the chosen application, making it the default one if set.
+    OpenWithApplication(bool),
+    CancelOpenWith,
+}
+
+/// An action that waits for the user to confirm it.
+#[derive(Debug)]
+enum PendingAction {
+    Delete {
+        paths: Vec<PathBuf>,
+        trashed: Vec<TrashItem>,
+    },
+    EmptyTrash,
+}
+
+/// A file waiting for the user to choose the application that opens it.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct OpenWith {
+    path: PathBuf,
+    mime: Mime,
+    applications: Vec<applications::Application>,
+    selected: Option<usize>,
+}
+
+/// Pushed from the threads that run file operations.
+#[derive(Debug, Clone)]
+pub enum OperationEvent {
+    Started(String),
+    Progress(Progress),
+    /// The entries that failed while the operation continued, as `(path, error)`.
+    Failures(Vec<(PathBuf, String)>),
+    /// The size of the entries of a confirmation request, measured in the background.
+    Measured(u64, TreeSize),
+    Finished(Result<(), String>),
+}
+
+impl orbtk::prelude::Event for OperationEvent {}
+
+pub type OperationHandlerFn = dyn Fn(&mut StatesContext, OperationEvent) -> bool + 'static;
+
+#[derive(Clone)]
+pub struct OperationEventHandler {
+    handler: Rc<OperationHandlerFn>,
+}
+
+impl Into<Rc<dyn EventHandler>> for OperationEventHandler {
+    fn into(self) -> Rc<dyn EventHandler> {
+        Rc::new(self)
+    }
+}
+
+impl EventHandler for OperationEventHandler {
+    fn handle_event(&self, states: &mut StatesContext, event: &EventBox) -> bool {
+        if let Ok(event) = event.downcast_ref::<OperationEvent>() {
+            return (self.handler)(states, event.clone());
+        }
+        false
+    }
+
+    fn handles_event(&self, event: &EventBox) -> bool {
+        event.is_type::<OperationEvent>()
+    }
+}
+
+pub trait OperationHandler: Sized + Widget {
+    /// Inserts a handler for the state changes of the file operations.
+    fn on_operation<H: Fn(&mut StatesContext, OperationEvent) -> bool + 'static>(
+        self,
+        handler: H,
+    ) -> Self {
+        self.insert_handler(OperationEventHandler {
+            handler: Rc::new(handler),
+        })
+    }
 }
 
 #[derive(Default, AsAny)]
@@ -38,12 +148,28 @@ struct MainViewState {
     event: Option<Event>,
     path_history: Vec<PathBuf>,
     path_history_cursor: usize,
+    operation_title: String,
+    operation_events: Vec<OperationEvent>,
+    pending_action: Option<PendingAction>,
+    confirmation_question: String,
+    /// Identifies the last confirmation request, older measures are ignored.
+    confirmation_id: u64,
+    /// The entries whose content info was read since the last layout, the details of the
+    /// selection can change with it.
+    ready_content_info: HashSet<PathBuf>,
+    #[cfg(target_os = "linux")]
+    open_with: Option<OpenWith>,
 }
 
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ApplicationNames(pub Vec<String>);
+
 into_property_source!(PlacesWrapper);
+into_property_source!(ApplicationNames);
 
-widget!(MainView<MainViewState> {
-    places: PlacesWrapper
+widget!(MainView<MainViewState>: OperationHandler {
+    places: PlacesWrapper,
+    application_names: ApplicationNames
 });
 
 impl State for MainViewState {
@@ -57,7 +183,8 @@ impl State for MainViewState {
         picture_dir().map(|d| places.push(("Pictures".to_owned(), d)));
         public_dir().map(|d| places.push(("Public".to_owned(), d)));
         video_dir().map(|d| places.push(("Videos".to_owned(), d)));
-        //ListView::count_set(&mut ctx.child("places"), places.len());
+        places.push(("Trash".to_owned(), PathBuf::from(trash::TRASH_PLACE)));
+        ListView::count_set(&mut ctx.child("places"), places.len());
         MainView::places_set(&mut ctx.widget(), PlacesWrapper(places));
         self.path_history.push(
             FilesView::path_ref(&ctx.child("files_view"))
@@ -68,25 +195,32 @@ impl State for MainViewState {
     }
 
     fn update(&mut self, _: &mut Registry, ctx: &mut Context) {
+        if !self.operation_events.is_empty() {
+            self.update_status(ctx);
+        }
         if let Some(event) = self.event.take() {
-            /*match event {
-                Event::ClickOnDirContent(index) => {
-                    let file = FilesView::directory_ref(&mut ctx.child("files_view"))
+            match event {
+                Event::ActivateItem(index) => {
+                    let file = FilesView::files_info_ref(&ctx.child("files_view"))
                         .get(index)
-                        .cloned()
-                        .unwrap();
-                    if file.file_type.is_dir() {
-                        FilesView::directory_mut(&mut ctx.child("files_view"))
-                            .go_deeper(index)
-                            .unwrap();
-                        self.update_path_history(ctx);
+                        .cloned();
+                    if let Some(file) = file.filter(|f| f.trashed().is_none()) {
+                        let path =
+                            FilesView::path_ref(&ctx.child("files_view")).join(file.file_name());
+                        if file.is_dir() {
+                            self.go_to(ctx, path);
+                            self.update_path_history(ctx);
+                        } else {
+                            self.open_file(ctx, path, file.media_type());
+                        }
                     }
                 }
+                Event::SelectionChanged => {
+                    self.show_selection_details(ctx);
+                }
                 Event::Undo => {
                     if self.path_history_cursor != 0 {
-                        FilesView::directory_mut(&mut ctx.child("files_view"))
-                            .set_path(self.path_history[self.path_history_cursor - 1].clone())
-                            .unwrap();
+                        self.go_to(ctx, self.path_history[self.path_history_cursor - 1].clone());
                         self.path_history_cursor -= 1;
                         Button::enabled_set(&mut ctx.child("redo"), true);
                         self.update_path_editor(ctx);
@@ -98,9 +232,7 @@ impl State for MainViewState {
                 Event::Redo => {
                     if self.path_history_cursor + 1 < self.path_history.len() {
                         self.path_history_cursor += 1;
-                        FilesView::directory_mut(&mut ctx.child("files_view"))
-                            .set_path(self.path_history[self.path_history_cursor].clone())
-                            .unwrap();
+                        self.go_to(ctx, self.path_history[self.path_history_cursor].clone());
                         Button::enabled_set(&mut ctx.child("undo"), true);
                         self.update_path_editor(ctx);
                     }
@@ -109,19 +241,16 @@ impl State for MainViewState {
                     }
                 }
                 Event::MoveUp => {
-                    FilesView::directory_mut(&mut ctx.child("files_view"))
-                        .go_up()
-                        .unwrap();
-                    self.update_path_history(ctx);
+                    let mut path = FilesView::path_ref(&ctx.child("files_view")).to_path_buf();
+                    if !trash::is_trash_place(&path) && path.pop() {
+                        self.go_to(ctx, path);
+                        self.update_path_history(ctx);
+                    }
                 }
                 Event::GoToHome => {
                     if let Some(path) = dirs::home_dir() {
                         debug!("Going to home");
-                        if let Err(e) =
-                            FilesView::directory_mut(&mut ctx.child("files_view")).set_path(path)
-                        {
-                            error!("Failed to go to home directory: {}", e);
-                        }
+                        self.go_to(ctx, path);
                         self.update_path_history(ctx);
                     }
                 }
@@ -138,18 +267,246 @@ impl State for MainViewState {
                             }
                         }
                     }
-                    if path.is_dir() {
-                        if let Err(e) =
-                            FilesView::directory_mut(&mut ctx.child("files_view")).set_path(&path)
-                        {
-                            error!("Failed to go to {}: {}", path.display(), e);
-                        }
+                    if path.is_dir() || trash::is_trash_place(&path) {
+                        self.go_to(ctx, path);
                         self.update_path_history(ctx);
                     } else {
                         self.update_path_editor(ctx);
                     }
                 }
-            }*/
+                Event::Copy => {
+                    CLIPBOARD.lock().copy(self.selected_paths(ctx));
+                    self.update_paste_button(ctx);
+                }
+                Event::Cut => {
+                    CLIPBOARD.lock().cut(self.selected_paths(ctx));
+                    self.update_paste_button(ctx);
+                }
+                Event::Paste => {
+                    let destination = FilesView::path_ref(&ctx.child("files_view")).to_path_buf();
+                    if trash::is_trash_place(&destination) {
+                        return;
+                    }
+                    let paste = CLIPBOARD.lock().take_paste();
+                    self.update_paste_button(ctx);
+                    if let Some(paste) = paste {
+                        let title = match paste.mode {
+                            TransferMode::Copy => "Copying",
+                            TransferMode::Move => "Moving",
+                        };
+                        self.run_operation(ctx, title, move |report| {
+                            Ok(paste.run(destination, report)?)
+                        });
+                    }
+                }
+                Event::GoToPlace(index) => {
+                    let place = MainView::places_ref(&ctx.widget())
+                        .get(index)
+                        .map(|(_, path)| path.clone());
+                    if let Some(path) = place {
+                        self.go_to(ctx, path);
+                        self.update_path_history(ctx);
+                    }
+                }
+                Event::MoveToTrash => {
+                    let current_path = FilesView::path_ref(&ctx.child("files_view")).to_path_buf();
+                    if trash::is_trash_place(&current_path) {
+                        return;
+                    }
+                    let paths = self.selected_paths(ctx);
+                    self.run_operation(ctx, "Moving to trash", move |report| {
+                        let mut progress = Progress {
+                            total_items: paths.len(),
+                            ..Progress::default()
+                        };
+                        // A failure doesn't stop the rest, they're reported together
+                        let mut failures = Vec::new();
+                        for path in paths {
+                            if let Err(e) = trash::trash(&path) {
+                                failures.push((path.clone(), e));
+                            }
+                            progress.current = path;
+                            progress.done_items += 1;
+                            report(&progress);
+                        }
+                        if failures.is_empty() {
+                            Ok(())
+                        } else {
+                            Err(OperationError::Partial(failures))
+                        }
+                    });
+                }
+                Event::RestoreFromTrash => {
+                    let items: Vec<_> = self
+                        .selected_files(ctx)
+                        .into_iter()
+                        .filter_map(|file| file.trashed().cloned())
+                        .collect();
+                    self.run_operation(ctx, "Restoring", move |report| {
+                        let mut progress = Progress {
+                            total_items: items.len(),
+                            ..Progress::default()
+                        };
+                        for item in items {
+                            progress.current = trash::restore(&item)?;
+                            progress.done_items += 1;
+                            report(&progress);
+                        }
+                        Ok(())
+                    });
+                }
+                Event::EmptyTrash => {
+                    if trash::is_empty() {
+                        return;
+                    }
+                    self.request_confirmation(
+                        ctx,
+                        PendingAction::EmptyTrash,
+                        "Permanently delete everything in the trash?".to_owned(),
+                        "Empty trash",
+                        || {
+                            let paths: Vec<_> =
+                                trash::list().iter().map(|item| item.path()).collect();
+                            measure_all(&paths)
+                        },
+                    );
+                }
+                Event::Delete => {
+                    let paths = self.selected_paths(ctx);
+                    if paths.is_empty() {
+                        return;
+                    }
+                    let trashed = self
+                        .selected_files(ctx)
+                        .into_iter()
+                        .filter_map(|file| file.trashed().cloned())
+                        .collect();
+                    let question = if paths.len() == 1 {
+                        format!(
+                            "Permanently delete \"{}\"?",
+                            paths[0]
+                                .file_name()
+                                .map(|name| name.to_string_lossy())
+                                .unwrap_or_default()
+                        )
+                    } else {
+                        format!("Permanently delete {} selected items?", paths.len())
+                    };
+                    let measured_paths = paths.clone();
+                    self.request_confirmation(
+                        ctx,
+                        PendingAction::Delete { paths, trashed },
+                        question,
+                        "Delete",
+                        move || measure_all(&measured_paths),
+                    );
+                }
+                Event::ConfirmAction => {
+                    self.hide_confirmation(ctx);
+                    match self.pending_action.take() {
+                        Some(PendingAction::Delete { paths, trashed }) => {
+                            self.run_operation(ctx, "Deleting", move |report| {
+                                let result = file_operations::delete(&paths, report);
+                                // The information of the trashed items that are gone is useless
+                                for item in trashed {
+                                    if item.path().symlink_metadata().is_err() {
+                                        let _ = trash::forget(&item);
+                                    }
+                                }
+                                result
+                            });
+                        }
+                        Some(PendingAction::EmptyTrash) => {
+                            self.run_operation(ctx, "Emptying the trash", |_| Ok(trash::empty()?));
+                        }
+                        None => {}
+                    }
+                }
+                Event::CancelAction => {
+                    self.pending_action = None;
+                    self.hide_confirmation(ctx);
+                }
+                Event::DismissReport => {
+                    ctx.child("report").set("visibility", Visibility::Collapsed);
+                }
+                Event::OpenWith => {
+                    let path = FilesView::path_ref(&ctx.child("files_view")).to_path_buf();
+                    let file = self
+                        .selected_files(ctx)
+                        .into_iter()
+                        .find(|file| !file.is_dir() && file.trashed().is_none());
+                    if let Some(file) = file {
+                        self.show_open_with(ctx, path.join(file.file_name()), file.media_type());
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Event::SelectApplication(index) => {
+                    if let Some(ref mut open_with) = self.open_with {
+                        open_with.selected = Some(index);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Event::OpenWithApplication(set_default) => {
+                    let open_with = match self.open_with.take() {
+                        Some(open_with) => open_with,
+                        None => {
+                            return;
+                        }
+                    };
+                    let app = match open_with
+                        .selected
+                        .and_then(|index| open_with.applications.get(index))
+                    {
+                        Some(app) => app,
+                        None => {
+                            // Nothing chosen yet
+                            self.open_with = Some(open_with);
+                            return;
+                        }
+                    };
+                    ctx.child("open_with")
+                        .set("visibility", Visibility::Collapsed);
+                    if set_default {
+                        if let Err(e) = applications::set_default(&open_with.mime, app) {
+                            warn!("Can't set the default application: {}", e);
+                        }
+                    }
+                    if let Err(e) = app.launch(&[open_with.path.clone()]) {
+                        warn!("Can't launch {}: {}", app.id, e);
+                        TextBlock::text_set(
+                            &mut ctx.child("status"),
+                            format!("Can't launch {}: {}", app.name, e),
+                        );
+                    }
+                }
+                #[cfg(not(target_os = "linux"))]
+                Event::SelectApplication(_) | Event::OpenWithApplication(_) => {}
+                Event::CancelOpenWith => {
+                    #[cfg(target_os = "linux")]
+                    {
+                        self.open_with = None;
+                    }
+                    ctx.child("open_with")
+                        .set("visibility", Visibility::Collapsed);
+                }
+            }
+        }
+    }
+
+    fn update_post_layout(&mut self, _: &mut Registry, ctx: &mut Context) {
+        // The files view shows the content info during its update, which follows this one
+        if self.ready_content_info.is_empty() {
+            return;
+        }
+        let ready = mem::take(&mut self.ready_content_info);
+        let files = self.selected_files(ctx);
+        if let [file] = files.as_slice() {
+            let shown = ready
+                .iter()
+                .any(|path| path.file_name().map_or(false, |name| name == file.file_name()));
+            if shown {
+                self.show_selection_details(ctx);
+            }
         }
     }
 }
@@ -159,6 +516,278 @@ impl MainViewState {
         self.event = event.into();
     }
 
+    fn content_info_ready(&mut self, path: PathBuf) {
+        self.ready_content_info.insert(path);
+    }
+
+    /// Runs `operation` in its own thread, reporting its progress in the status bar. The views
+    /// pick the resulting changes on disk by themselves.
+    fn run_operation<F>(&mut self, ctx: &mut Context, title: &str, operation: F)
+    where
+        F: FnOnce(&mut dyn FnMut(&Progress)) -> Result<(), OperationError> + Send + 'static,
+    {
+        let entity = ctx.entity;
+        let event_adapter = ctx.event_adapter();
+        event_adapter.push_event_direct(entity, OperationEvent::Started(title.to_owned()));
+        let title = title.to_owned();
+        thread::spawn(move || {
+            let mut last_report = Instant::now();
+            let mut report = |progress: &Progress| {
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    last_report = Instant::now();
+                    event_adapter
+                        .push_event_direct(entity, OperationEvent::Progress(progress.clone()));
+                }
+            };
+            let result = operation(&mut report).map_err(|e| {
+                error!("{} failed: {}", title, e);
+                if let OperationError::Partial(failures) = &e {
+                    let failures = failures
+                        .iter()
+                        .map(|(path, e)| (path.clone(), e.to_string()))
+                        .collect();
+                    event_adapter.push_event_direct(entity, OperationEvent::Failures(failures));
+                }
+                e.to_string()
+            });
+            event_adapter.push_event_direct(entity, OperationEvent::Finished(result));
+        });
+    }
+
+    fn operation_event(&mut self, event: OperationEvent) {
+        self.operation_events.push(event);
+    }
+
+    fn update_status(&mut self, ctx: &mut Context) {
+        let mut status = None;
+        for event in self.operation_events.drain(..) {
+            status = Some(match event {
+                OperationEvent::Started(title) => {
+                    let status = format!("{}...", title);
+                    self.operation_title = title;
+                    status
+                }
+                OperationEvent::Progress(progress) => format!(
+                    "{} {} of {} items ({} of {})",
+                    self.operation_title,
+                    progress.done_items,
+                    progress.total_items,
+                    format_size(progress.done_bytes),
+                    format_size(progress.total_bytes)
+                ),
+                OperationEvent::Failures(failures) => {
+                    self.show_report(ctx, &failures);
+                    continue;
+                }
+                OperationEvent::Measured(id, size) => {
+                    if id == self.confirmation_id && self.pending_action.is_some() {
+                        TextBlock::text_set(
+                            &mut ctx.child("confirmation_text"),
+                            format!("{} {}", self.confirmation_question, describe_size(size)),
+                        );
+                    }
+                    continue;
+                }
+                OperationEvent::Finished(Ok(())) => format!("{} finished", self.operation_title),
+                OperationEvent::Finished(Err(e)) => {
+                    format!("{} failed: {}", self.operation_title, e)
+                }
+            });
+        }
+        if let Some(status) = status {
+            TextBlock::text_set(&mut ctx.child("status"), status);
+        }
+    }
+
+    /// The items selected in the files view.
+    fn selected_files(&self, ctx: &mut Context) -> Vec<crate::files_view::FileInfo> {
+        let selected_indices = ItemsView::selected_indices_ref(&ctx.child("directory_view"))
+            .0
+            .clone();
+        let files_view = ctx.child("files_view");
+        let files_info = FilesView::files_info_ref(&files_view);
+        selected_indices
+            .into_iter()
+            .filter_map(|index| files_info.get(index).cloned())
+            .collect()
+    }
+
+    /// The paths of the items selected in the files view, the items of the trash place are
+    /// located inside the trash.
+    fn selected_paths(&self, ctx: &mut Context) -> Vec<PathBuf> {
+        let path = FilesView::path_ref(&ctx.child("files_view")).to_path_buf();
+        self.selected_files(ctx)
+            .into_iter()
+            .map(|file| match file.trashed() {
+                Some(item) => item.path(),
+                None => path.join(file.file_name()),
+            })
+            .collect()
+    }
+
+    /// Describes the selected item in the status bar, like where a trashed item comes from or
+    /// the tags of an audio file.
+    fn show_selection_details(&mut self, ctx: &mut Context) {
+        let files = self.selected_files(ctx);
+        let details = match files.as_slice() {
+            [file] => file
+                .trashed()
+                .map(|item| match item.deletion_date {
+                    Some(date) => format!(
+                        "Deleted from {} on {}",
+                        item.original_path.display(),
+                        date.format("%Y-%m-%d %H:%M")
+                    ),
+                    None => format!("Deleted from {}", item.original_path.display()),
+                })
+                .or_else(|| file.audio().and_then(describe_audio)),
+            _ => None,
+        };
+        if let Some(details) = details {
+            TextBlock::text_set(&mut ctx.child("status"), details);
+        }
+    }
+
+    /// Shows `question` in the confirmation bar, `action` runs only if the user confirms it with
+    /// the `confirm` button. The size of what's affected is found by `measure` in its own thread
+    /// and added to the question once known.
+    fn request_confirmation<F>(
+        &mut self,
+        ctx: &mut Context,
+        action: PendingAction,
+        question: String,
+        confirm: &str,
+        measure: F,
+    ) where
+        F: FnOnce() -> TreeSize + Send + 'static,
+    {
+        self.pending_action = Some(action);
+        self.confirmation_id += 1;
+        TextBlock::text_set(
+            &mut ctx.child("confirmation_text"),
+            format!("{} Measuring...", question),
+        );
+        self.confirmation_question = question;
+        Button::text_set(&mut ctx.child("confirm"), confirm.to_owned());
+        ctx.child("confirmation")
+            .set("visibility", Visibility::Visible);
+        let (id, entity, event_adapter) = (self.confirmation_id, ctx.entity, ctx.event_adapter());
+        thread::spawn(move || {
+            event_adapter.push_event_direct(entity, OperationEvent::Measured(id, measure()));
+        });
+    }
+
+    fn hide_confirmation(&mut self, ctx: &mut Context) {
+        ctx.child("confirmation")
+            .set("visibility", Visibility::Collapsed);
+    }
+
+    /// Lists the entries an operation couldn't process, until the user dismisses it.
+    fn show_report(&mut self, ctx: &mut Context, failures: &[(PathBuf, String)]) {
+        let mut text = format!("{} couldn't process:", self.operation_title);
+        for (path, e) in failures {
+            text.push_str(&format!("\n{}: {}", path.display(), e));
+        }
+        TextBlock::text_set(&mut ctx.child("report_text"), text);
+        ctx.child("report").set("visibility", Visibility::Visible);
+    }
+
+    /// Opens `path` with the default application of its type.
+    fn open_file(&mut self, ctx: &mut Context, path: PathBuf, mime: Option<Mime>) {
+        #[cfg(target_os = "linux")]
+        let result = match mime {
+            Some(mime) => applications::open(&path, &mime),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the type of the file is unknown",
+            )),
+        };
+        #[cfg(not(target_os = "linux"))]
+        let result: Result<(), io::Error> = {
+            let _ = mime;
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "opening files isn't supported on this platform",
+            ))
+        };
+        if let Err(e) = result {
+            warn!("Can't open {}: {}", path.display(), e);
+            TextBlock::text_set(
+                &mut ctx.child("status"),
+                format!(
+                    "Can't open {}: {}",
+                    path.file_name()
+                        .map(|name| name.to_string_lossy())
+                        .unwrap_or_default(),
+                    e
+                ),
+            );
+        }
+    }
+
+    /// Lists the applications that can open `path` so the user chooses one of them.
+    #[cfg(target_os = "linux")]
+    fn show_open_with(&mut self, ctx: &mut Context, path: PathBuf, mime: Option<Mime>) {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mime = match mime {
+            Some(mime) => mime,
+            None => {
+                TextBlock::text_set(
+                    &mut ctx.child("status"),
+                    format!("The type of {} is unknown", file_name),
+                );
+                return;
+            }
+        };
+        let applications = applications::for_mime(&mime);
+        if applications.is_empty() {
+            TextBlock::text_set(
+                &mut ctx.child("status"),
+                format!("No application can open {}", file_name),
+            );
+            return;
+        }
+        MainView::application_names_set(
+            &mut ctx.widget(),
+            ApplicationNames(applications.iter().map(|app| app.name.clone()).collect()),
+        );
+        let mut list = ctx.child("open_with_apps");
+        ListView::count_set(&mut list, applications.len());
+        ListView::request_update_set(&mut list, true);
+        TextBlock::text_set(
+            &mut ctx.child("open_with_text"),
+            format!("Open {} ({}) with:", file_name, mime.essence_str()),
+        );
+        ctx.child("open_with")
+            .set("visibility", Visibility::Visible);
+        self.open_with = Some(OpenWith {
+            path,
+            mime,
+            applications,
+            selected: None,
+        });
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn show_open_with(&mut self, ctx: &mut Context, _path: PathBuf, _mime: Option<Mime>) {
+        TextBlock::text_set(
+            &mut ctx.child("status"),
+            "Choosing applications isn't supported on this platform",
+        );
+    }
+
+    fn update_paste_button(&mut self, ctx: &mut Context) {
+        Button::enabled_set(&mut ctx.child("paste"), !CLIPBOARD.lock().is_empty());
+    }
+
+    /// Makes the files view show `path`, it takes care of reading and watching the directory.
+    fn go_to(&mut self, ctx: &mut Context, path: PathBuf) {
+        FilesView::path_set(&mut ctx.child("files_view"), PathBufWrapper::from(path));
+    }
+
     fn update_path_history(&mut self, ctx: &mut Context) {
         let current_path = FilesView::path_ref(&ctx.child("files_view"))
             .as_path()
@@ -188,12 +817,59 @@ impl MainViewState {
     }
 }
 
+/// Describes the size of the affected trees for the confirmation messages.
+fn describe_size(size: TreeSize) -> String {
+    format!(
+        "{} files and folders, {}",
+        size.items,
+        format_size(size.bytes)
+    )
+}
+
+/// Describes the tags of an audio file like `Artist - Title - Album (3:25)`.
+fn describe_audio(audio: &AudioMetadata) -> Option<String> {
+    let tags: Vec<&str> = [
+        audio.artist.as_deref(),
+        audio.title.as_deref(),
+        audio.album.as_deref(),
+    ]
+    .iter()
+    .flatten()
+    .copied()
+    .collect();
+    let duration = audio.duration.map(|duration| {
+        let seconds = duration.as_secs();
+        match seconds / 3600 {
+            0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+            hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+        }
+    });
+    match (tags.is_empty(), duration) {
+        (true, None) => None,
+        (true, Some(duration)) => Some(duration),
+        (false, None) => Some(tags.join(" - ")),
+        (false, Some(duration)) => Some(format!("{} ({})", tags.join(" - "), duration)),
+    }
+}
+
 impl Template for MainView {
     fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
-        self.name("MainView").child(
+        let view = self.on_operation(move |states, event| {
+            states.get_mut::<MainViewState>(id).operation_event(event);
+            true
+        });
+        view.name("MainView").child(
             Grid::new()
-                .columns(Columns::create().push(Column::default()).push("auto"))
-                .rows(Rows::create().push("auto").push(Row::default()))
+                .columns(Columns::create().push("auto").push(Column::default()))
+                .rows(
+                    Rows::create()
+                        .push("auto")
+                        .push(Row::default())
+                        .push("auto")
+                        .push("auto")
+                        .push("auto")
+                        .push("auto"),
+                )
                 .child(
                     Grid::new()
                         .columns(Columns::create().push("auto").push(Column::default()))
@@ -255,6 +931,106 @@ impl Template for MainView {
                                         .enabled(dirs::home_dir().is_some())
                                         .build(ctx),
                                 )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .icon(material_icons_font::MD_OPEN_WITH)
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::OpenWith);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .icon(material_icons_font::MD_CONTENT_COPY)
+                                        .on_click(move |states, _| {
+                                            states.get_mut::<MainViewState>(id).event(Event::Copy);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .icon(material_icons_font::MD_CONTENT_CUT)
+                                        .on_click(move |states, _| {
+                                            states.get_mut::<MainViewState>(id).event(Event::Cut);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .id("paste")
+                                        .icon(material_icons_font::MD_CONTENT_PASTE)
+                                        .on_click(move |states, _| {
+                                            states.get_mut::<MainViewState>(id).event(Event::Paste);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .enabled(false)
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .icon(material_icons_font::MD_DELETE)
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::MoveToTrash);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .icon(material_icons_font::MD_DELETE_FOREVER)
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::Delete);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .icon(material_icons_font::MD_RESTORE_FROM_TRASH)
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::RestoreFromTrash);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .style("button_single_content")
+                                        .icon(material_icons_font::MD_DELETE_SWEEP)
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::EmptyTrash);
+                                            true
+                                        })
+                                        .min_width(0)
+                                        .build(ctx),
+                                )
                                 .build(ctx),
                         )
                         .child(
@@ -269,22 +1045,212 @@ impl Template for MainView {
                                 .build(ctx),
                         )
                         .attach(Grid::column(0))
+                        .attach(Grid::column_span(2))
                         .attach(Grid::row(0))
                         .build(ctx),
                 )
+                .child(
+                    ListView::new()
+                        .id("places")
+                        .attach(Grid::column(0))
+                        .attach(Grid::row(1))
+                        .items_builder(move |bc, index| {
+                            let name = bc
+                                .get_widget(id)
+                                .get::<PlacesWrapper>("places")
+                                .get(index)
+                                .map(|(name, _)| name.clone())
+                                .unwrap_or_default();
+                            TextBlock::new()
+                                .margin((0, 2, 0, 2))
+                                .v_align("center")
+                                .text(name)
+                                .build(bc)
+                        })
+                        .on_selection_changed(move |states, _, selected| {
+                            if let Some(index) = selected.first() {
+                                states
+                                    .get_mut::<MainViewState>(id)
+                                    .event(Event::GoToPlace(*index));
+                            }
+                        })
+                        .build(ctx),
+                )
                 .child(
                     FilesView::new()
                         .id("files_view")
                         .attach(Grid::column(1))
                         .attach(Grid::row(1))
                         .path(PathBufWrapper::from(PathBuf::from(".")))
-                        .on_selection_changed(move |states, _, change| {
+                        .on_selection_changed(move |states, _, _| {
+                            states
+                                .get_mut::<MainViewState>(id)
+                                .event(Event::SelectionChanged);
+                        })
+                        // Not handled, the files view handles it too
+                        .on_content_info_ready(move |states, path| {
+                            states
+                                .get_mut::<MainViewState>(id)
+                                .content_info_ready(path);
+                            false
+                        })
+                        .on_item_activated(move |states, index| {
                             states
                                 .get_mut::<MainViewState>(id)
-                                .event(Event::ClickOnDirContent(change[0]));
+                                .event(Event::ActivateItem(index));
+                            true
                         })
                         .build(ctx),
                 )
+                .child(
+                    Stack::new()
+                        .id("open_with")
+                        .visibility(Visibility::Collapsed)
+                        .attach(Grid::column(0))
+                        .attach(Grid::column_span(2))
+                        .attach(Grid::row(2))
+                        .margin((4, 2, 4, 2))
+                        .child(TextBlock::new().id("open_with_text").build(ctx))
+                        .child(
+                            ListView::new()
+                                .id("open_with_apps")
+                                .height(160)
+                                .items_builder(move |bc, index| {
+                                    let name = bc
+                                        .get_widget(id)
+                                        .get::<ApplicationNames>("application_names")
+                                        .0
+                                        .get(index)
+                                        .cloned()
+                                        .unwrap_or_default();
+                                    TextBlock::new()
+                                        .margin((0, 2, 0, 2))
+                                        .v_align("center")
+                                        .text(name)
+                                        .build(bc)
+                                })
+                                .on_selection_changed(move |states, _, selected| {
+                                    if let Some(index) = selected.first() {
+                                        states
+                                            .get_mut::<MainViewState>(id)
+                                            .event(Event::SelectApplication(*index));
+                                    }
+                                })
+                                .build(ctx),
+                        )
+                        .child(
+                            Stack::new()
+                                .orientation(Orientation::Horizontal)
+                                .h_align("end")
+                                .child(
+                                    Button::new()
+                                        .text("Open")
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::OpenWithApplication(false));
+                                            true
+                                        })
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .text("Always open with it")
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::OpenWithApplication(true));
+                                            true
+                                        })
+                                        .build(ctx),
+                                )
+                                .child(
+                                    Button::new()
+                                        .text("Cancel")
+                                        .on_click(move |states, _| {
+                                            states
+                                                .get_mut::<MainViewState>(id)
+                                                .event(Event::CancelOpenWith);
+                                            true
+                                        })
+                                        .build(ctx),
+                                )
+                                .build(ctx),
+                        )
+                        .build(ctx),
+                )
+                .child(
+                    Stack::new()
+                        .id("confirmation")
+                        .orientation(Orientation::Horizontal)
+                        .visibility(Visibility::Collapsed)
+                        .attach(Grid::column(0))
+                        .attach(Grid::column_span(2))
+                        .attach(Grid::row(3))
+                        .margin((4, 2, 4, 2))
+                        .child(
+                            TextBlock::new()
+                                .id("confirmation_text")
+                                .v_align("center")
+                                .margin((0, 0, 8, 0))
+                                .build(ctx),
+                        )
+                        .child(
+                            Button::new()
+                                .id("confirm")
+                                .text("Delete")
+                                .on_click(move |states, _| {
+                                    states
+                                        .get_mut::<MainViewState>(id)
+                                        .event(Event::ConfirmAction);
+                                    true
+                                })
+                                .build(ctx),
+                        )
+                        .child(
+                            Button::new()
+                                .text("Cancel")
+                                .on_click(move |states, _| {
+                                    states
+                                        .get_mut::<MainViewState>(id)
+                                        .event(Event::CancelAction);
+                                    true
+                                })
+                                .build(ctx),
+                        )
+                        .build(ctx),
+                )
+                .child(
+                    Stack::new()
+                        .id("report")
+                        .visibility(Visibility::Collapsed)
+                        .attach(Grid::column(0))
+                        .attach(Grid::column_span(2))
+                        .attach(Grid::row(4))
+                        .margin((4, 2, 4, 2))
+                        .child(TextBlock::new().id("report_text").build(ctx))
+                        .child(
+                            Button::new()
+                                .text("Dismiss")
+                                .h_align("end")
+                                .on_click(move |states, _| {
+                                    states
+                                        .get_mut::<MainViewState>(id)
+                                        .event(Event::DismissReport);
+                                    true
+                                })
+                                .build(ctx),
+                        )
+                        .build(ctx),
+                )
+                .child(
+                    TextBlock::new()
+                        .id("status")
+                        .attach(Grid::column(1))
+                        .attach(Grid::row(5))
+                        .margin((4, 2, 4, 2))
+                        .build(ctx),
+                )
                 .build(ctx),
         )
     }
@@ -0,0 +1,433 @@
+use crate::{
+    file_operations::{move_entry, remove_entry, unique_destination},
+    uri::{decode_path, encode_path},
+};
+use chrono::{Local, NaiveDateTime};
+use log::warn;
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// The path of the virtual place that shows the content of every trash.
+pub const TRASH_PLACE: &str = "trash:///";
+
+const INFO_EXTENSION: &str = "trashinfo";
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// An item inside one of the trash directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashItem {
+    /// The trash directory that contains the item, the one with `files` and `info` inside.
+    pub trash_dir: PathBuf,
+    /// The name of the item inside the `files` directory.
+    pub id: OsString,
+    pub original_path: PathBuf,
+    pub deletion_date: Option<NaiveDateTime>,
+}
+
+impl TrashItem {
+    /// Where the trashed item is stored now.
+    pub fn path(&self) -> PathBuf {
+        self.trash_dir.join("files").join(&self.id)
+    }
+
+    fn info_path(&self) -> PathBuf {
+        info_path(&self.trash_dir, &self.id)
+    }
+}
+
+pub fn is_trash_place(path: impl AsRef<Path>) -> bool {
+    path.as_ref() == Path::new(TRASH_PLACE)
+}
+
+/// The trash of the user, `$XDG_DATA_HOME/Trash`.
+pub fn home_trash() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("Trash"))
+}
+
+/// Every existing trash directory, the home trash first.
+pub fn trash_dirs() -> Vec<PathBuf> {
+    let mut trash_dirs: Vec<PathBuf> = home_trash().filter(|t| t.is_dir()).into_iter().collect();
+    #[cfg(unix)]
+    for mount_point in unix::mount_points() {
+        for trash_dir in unix::topdir_trashes(&mount_point) {
+            if trash_dir.is_dir() && !trash_dirs.contains(&trash_dir) {
+                trash_dirs.push(trash_dir);
+            }
+        }
+    }
+    trash_dirs
+}
+
+/// Whether no trash directory has an item, without reading their information.
+pub fn is_empty() -> bool {
+    trash_dirs().iter().all(|trash_dir| {
+        trash_dir
+            .join("files")
+            .read_dir()
+            .map_or(true, |mut entries| entries.next().is_none())
+    })
+}
+
+/// Moves `path` to the trash, following the freedesktop.org Trash specification.
+///
+/// The home trash is used when `path` lives in its filesystem, otherwise the trash of the mount
+/// point of `path` is used, `$topdir/.Trash/$uid` or `$topdir/.Trash-$uid`.
+pub fn trash(path: impl AsRef<Path>) -> Result<TrashItem, io::Error> {
+    let path = absolute(path.as_ref())?;
+    let home_trash = home_trash().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "the home trash directory is unknown",
+        )
+    })?;
+    create_trash_dir(&home_trash)?;
+    #[cfg(unix)]
+    if !unix::same_device(&path, &home_trash)? {
+        match unix::topdir_trash_for(&path) {
+            Ok((topdir, trash_dir)) => {
+                // Items of a topdir trash are relative to the topdir
+                let relative_path = path.strip_prefix(&topdir).unwrap_or(&path).to_path_buf();
+                return move_to_trash(&path, &trash_dir, &relative_path);
+            }
+            Err(e) => {
+                warn!(
+                    "Can't use the trash of the mount point of {}, the home trash is used instead: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+    move_to_trash(&path, &home_trash, &path)
+}
+
+/// Lists the items of every trash directory.
+pub fn list() -> Vec<TrashItem> {
+    let mut items = Vec::new();
+    for trash_dir in trash_dirs() {
+        let info_dir = match trash_dir.join("info").read_dir() {
+            Ok(info_dir) => info_dir,
+            Err(e) => {
+                warn!("Failed to read the trash {}: {}", trash_dir.display(), e);
+                continue;
+            }
+        };
+        for entry in info_dir.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension() != Some(OsStr::new(INFO_EXTENSION)) {
+                continue;
+            }
+            let id = match path.file_stem() {
+                Some(id) => id.to_owned(),
+                None => {
+                    continue;
+                }
+            };
+            match read_info(&path, &trash_dir) {
+                Ok((original_path, deletion_date)) => {
+                    let item = TrashItem {
+                        trash_dir: trash_dir.clone(),
+                        id,
+                        original_path,
+                        deletion_date,
+                    };
+                    // An info file without its item is left by an interrupted trashing
+                    if item.path().symlink_metadata().is_ok() {
+                        items.push(item);
+                    }
+                }
+                Err(e) => {
+                    warn!("Invalid trash info file {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+    items
+}
+
+/// Moves `item` back to its original location, returning where it was restored. When the
+/// original path is taken the item is restored next to it with a new name.
+pub fn restore(item: &TrashItem) -> Result<PathBuf, io::Error> {
+    let mut target = item.original_path.clone();
+    if target.symlink_metadata().is_ok() {
+        let file_name = target.file_name().unwrap_or(&item.id).to_owned();
+        target = unique_destination(target.parent().unwrap_or(Path::new("/")), &file_name);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_entry(&item.path(), &target)?;
+    fs::remove_file(item.info_path())?;
+    Ok(target)
+}
+
+/// Removes the information of `item` once its content was deleted.
+pub fn forget(item: &TrashItem) -> Result<(), io::Error> {
+    fs::remove_file(item.info_path())
+}
+
+/// Permanently deletes the content of every trash directory. Every item is tried, the first
+/// error found is returned.
+pub fn empty() -> Result<(), io::Error> {
+    let mut result = Ok(());
+    for trash_dir in trash_dirs() {
+        for dir in &["files", "info"] {
+            let entries = match trash_dir.join(dir).read_dir() {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    continue;
+                }
+                Err(e) => {
+                    result = result.and(Err(e));
+                    continue;
+                }
+            };
+            for entry in entries {
+                if let Err(e) = entry.and_then(|entry| remove_entry(&entry.path())) {
+                    result = result.and(Err(e));
+                }
+            }
+        }
+        // The cache of sizes is meaningless once the trash is empty
+        let _ = fs::remove_file(trash_dir.join("directorysizes"));
+    }
+    result
+}
+
+fn move_to_trash(
+    path: &Path,
+    trash_dir: &Path,
+    recorded_path: &Path,
+) -> Result<TrashItem, io::Error> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} can't be trashed", path.display()),
+        )
+    })?;
+    let deletion_date = Local::now().naive_local();
+    let (id, mut info_file) = create_info_file(trash_dir, file_name)?;
+    let info_path = info_path(trash_dir, &id);
+    let res = write!(
+        info_file,
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        encode_path(recorded_path),
+        deletion_date.format(DATE_FORMAT)
+    )
+    .and_then(|_| info_file.sync_all())
+    .and_then(|_| move_entry(path, &trash_dir.join("files").join(&id)));
+    if let Err(e) = res {
+        let _ = fs::remove_file(info_path);
+        return Err(e);
+    }
+    Ok(TrashItem {
+        trash_dir: trash_dir.to_path_buf(),
+        id,
+        original_path: path.to_path_buf(),
+        deletion_date: Some(deletion_date),
+    })
+}
+
+/// Reserves a name in the trash by atomically creating its info file.
+fn create_info_file(trash_dir: &Path, file_name: &OsStr) -> Result<(OsString, File), io::Error> {
+    let mut count = 1;
+    loop {
+        let id = if count == 1 {
+            file_name.to_owned()
+        } else {
+            let mut id = file_name.to_owned();
+            id.push(format!(".{}", count));
+            id
+        };
+        count += 1;
+        if trash_dir.join("files").join(&id).symlink_metadata().is_ok() {
+            continue;
+        }
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(info_path(trash_dir, &id))
+        {
+            Ok(file) => {
+                return Ok((id, file));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn read_info(path: &Path, trash_dir: &Path) -> Result<(PathBuf, Option<NaiveDateTime>), io::Error> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+    let mut in_group = false;
+    let mut original_path = None;
+    let mut deletion_date = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_group = line == "[Trash Info]";
+            continue;
+        }
+        if !in_group {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim_end();
+        let value = parts.next().unwrap_or("").trim_start();
+        match key {
+            "Path" => {
+                original_path = Some(decode_path(value).ok_or_else(|| invalid("bad Path key"))?)
+            }
+            "DeletionDate" => {
+                deletion_date = NaiveDateTime::parse_from_str(value, DATE_FORMAT).ok()
+            }
+            _ => {}
+        }
+    }
+    let original_path = original_path.ok_or_else(|| invalid("missing Path key"))?;
+    // Relative paths are relative to the directory that contains the trash
+    let original_path = if original_path.is_absolute() {
+        original_path
+    } else {
+        trash_dir
+            .parent()
+            .map(|topdir| topdir.join(&original_path))
+            .unwrap_or(original_path)
+    };
+    Ok((original_path, deletion_date))
+}
+
+fn info_path(trash_dir: &Path, id: &OsStr) -> PathBuf {
+    let mut file_name = id.to_owned();
+    file_name.push(".");
+    file_name.push(INFO_EXTENSION);
+    trash_dir.join("info").join(file_name)
+}
+
+fn create_trash_dir(trash_dir: &Path) -> Result<(), io::Error> {
+    for dir in &["files", "info"] {
+        let dir = trash_dir.join(dir);
+        if !dir.is_dir() {
+            fs::create_dir_all(&dir)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(trash_dir, fs::Permissions::from_mode(0o700))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Makes `path` absolute without resolving its last component, a link is trashed itself and
+/// not its target.
+fn absolute(path: &Path) -> Result<PathBuf, io::Error> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) if parent != Path::new("") => {
+            Ok(parent.canonicalize()?.join(file_name))
+        }
+        (_, Some(file_name)) => Ok(std::env::current_dir()?.join(file_name)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} can't be trashed", path.display()),
+        )),
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs, io,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        path::{Path, PathBuf},
+    };
+
+    const STICKY_BIT: u32 = 0o1000;
+
+    fn uid() -> u32 {
+        unsafe { libc::getuid() }
+    }
+
+    pub fn same_device(path: &Path, other: &Path) -> Result<bool, io::Error> {
+        Ok(path.symlink_metadata()?.dev() == other.metadata()?.dev())
+    }
+
+    /// The candidate trash directories of a mount point, by order of preference.
+    pub fn topdir_trashes(topdir: &Path) -> Vec<PathBuf> {
+        let mut trashes = Vec::with_capacity(2);
+        let shared = topdir.join(".Trash");
+        // The shared trash is only trusted when it can't be hijacked by other users
+        if let Ok(meta) = shared.symlink_metadata() {
+            if meta.is_dir() && meta.permissions().mode() & STICKY_BIT != 0 {
+                trashes.push(shared.join(uid().to_string()));
+            }
+        }
+        trashes.push(topdir.join(format!(".Trash-{}", uid())));
+        trashes
+    }
+
+    /// Finds the mount point of `path` and the trash directory to use in it.
+    pub fn topdir_trash_for(path: &Path) -> Result<(PathBuf, PathBuf), io::Error> {
+        let dev = path.symlink_metadata()?.dev();
+        let mut topdir = path.parent().unwrap_or(path).to_path_buf();
+        while let Some(parent) = topdir.parent() {
+            if parent.metadata()?.dev() != dev {
+                break;
+            }
+            topdir = parent.to_path_buf();
+        }
+        let mut last_error = None;
+        for trash_dir in topdir_trashes(&topdir) {
+            match super::create_trash_dir(&trash_dir) {
+                Ok(()) => {
+                    return Ok((topdir, trash_dir));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no trash")))
+    }
+
+    /// The mount points of the system, they are only known in Linux.
+    pub fn mount_points() -> Vec<PathBuf> {
+        let mounts = match fs::read_to_string("/proc/self/mounts") {
+            Ok(mounts) => mounts,
+            Err(_) => {
+                return Vec::new();
+            }
+        };
+        mounts
+            .lines()
+            .filter_map(|line| line.split(' ').nth(1))
+            .map(|mount_point| PathBuf::from(unescape_mount_point(mount_point)))
+            .collect()
+    }
+
+    /// Mount points have the spaces and other separators escaped as octal sequences.
+    fn unescape_mount_point(mount_point: &str) -> String {
+        let mut unescaped = String::with_capacity(mount_point.len());
+        let mut chars = mount_point.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\\' {
+                let code: String = chars.clone().take(3).collect();
+                if let Ok(byte) = u8::from_str_radix(&code, 8) {
+                    unescaped.push(byte as char);
+                    chars.nth(2);
+                    continue;
+                }
+            }
+            unescaped.push(ch);
+        }
+        unescaped
+    }
+}
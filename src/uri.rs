@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+/// The `file://` URI of `path`, as used by the freedesktop specifications.
+pub fn file_uri(path: impl AsRef<Path>) -> String {
+    format!("file://{}", encode_path(path.as_ref()))
+}
+
+/// Escapes a path like in URLs. The characters left as they are are the ones GLib leaves in
+/// paths, the URIs of thumbnails have to be the same as theirs.
+pub fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for byte in path_bytes(path) {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'!'
+            | b'~'
+            | b'*'
+            | b'\''
+            | b'('
+            | b')'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b','
+            | b':'
+            | b'='
+            | b'@'
+            | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Unescapes a path escaped like in URLs, `None` if an escape is incomplete.
+pub fn decode_path(value: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    Some(path_from_bytes(bytes))
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_encoding() {
+        // Escaped like g_filename_to_uri does
+        let path = Path::new("/home/user/a$b&c+d,e:f=g@h;i j#k%l?m[n]é.png");
+        let encoded = encode_path(path);
+        assert_eq!(
+            encoded,
+            "/home/user/a$b&c+d,e:f=g@h%3Bi%20j%23k%25l%3Fm%5Bn%5D%C3%A9.png"
+        );
+        assert_eq!(decode_path(&encoded).unwrap(), path);
+        assert_eq!(
+            file_uri("/home/user/Música/año 2020"),
+            "file:///home/user/M%C3%BAsica/a%C3%B1o%202020"
+        );
+    }
+
+    #[test]
+    fn path_decoding() {
+        assert_eq!(
+            decode_path("/home/user/100%25%20done%c3%a9").unwrap(),
+            Path::new("/home/user/100% doneé")
+        );
+        // The characters left as they are may be escaped anyway
+        assert_eq!(decode_path("%2Fa%2Bb").unwrap(), Path::new("/a+b"));
+        assert!(decode_path("/home/user/100%").is_none());
+        assert!(decode_path("/home/user/100%2").is_none());
+        assert!(decode_path("/home/user/100%zz").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let path = Path::new(OsStr::from_bytes(b"/home/user/caf\xe9"));
+        assert_eq!(encode_path(path), "/home/user/caf%E9");
+        assert_eq!(decode_path("/home/user/caf%E9").unwrap(), path);
+    }
+}
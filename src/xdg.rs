@@ -0,0 +1,57 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// `$XDG_DATA_HOME` followed by `$XDG_DATA_DIRS`, in decreasing order of importance.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = dirs::data_dir().into_iter().collect();
+    dirs.extend(env_dirs("XDG_DATA_DIRS", "/usr/local/share:/usr/share"));
+    dirs
+}
+
+/// `$XDG_CONFIG_HOME` followed by `$XDG_CONFIG_DIRS`, in decreasing order of importance.
+pub fn config_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = dirs::config_dir().into_iter().collect();
+    dirs.extend(env_dirs("XDG_CONFIG_DIRS", "/etc/xdg"));
+    dirs
+}
+
+/// Finds the executable `program` like a `TryExec` key, an absolute path is used as is while a
+/// name is searched in `$PATH`.
+pub fn find_executable(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.is_absolute() {
+        return Some(path.to_path_buf()).filter(|p| is_executable(p));
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|p| is_executable(p))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Reads a list of directories from the environment variable `var`, only absolute paths are
+/// valid.
+fn env_dirs(var: &str, default: &str) -> Vec<PathBuf> {
+    let value = env::var(var)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| default.to_owned());
+    value
+        .split(':')
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .collect()
+}